@@ -0,0 +1,350 @@
+//! Who may use the HTTP API, and from which web pages.
+//!
+//! Clients send `Authorization: Bearer <token>`; WebSocket and EventSource
+//! clients, which cannot set headers, may pass `?access_token=<token>` instead.
+//! Other requests may not, so tokens stay out of ordinary URLs and logs.
+//! Each token has scopes: `events` to read tabs, pages and events, `navigate`
+//! to open, close and switch tabs, navigate, replay and run WebDriver
+//! sessions, `script` to run scripts in the page (WebDriver elements, execute
+//! and screenshots, DevTools `Runtime`), and `admin` for everything, including
+//! the WebSocket transport and closing the browser. Without tokens the API is
+//! open to anyone who can reach it, so it may only listen on loopback.
+//!
+//! Requests carrying an `Origin` header are refused unless the origin is
+//! listed in `cors_origins`. That keeps web pages away from the API, WebSocket
+//! handshakes included, since CORS does not cover those. Without tokens, the
+//! `Host` header must also name loopback or the bind address, so a page whose
+//! domain was rebound to 127.0.0.1 cannot read the API as its own origin.
+//! Every successful or failed change made with a token is published as an
+//! `api_audit` event.
+//!
+//! ```json
+//! {
+//!   "tokens": [{ "name": "ci", "token": "change-me", "scopes": ["events", "navigate"] }],
+//!   "cors_origins": ["http://localhost:8080"]
+//! }
+//! ```
+
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use crate::browser::BrowserHandle;
+use crate::event::{BrowserEvent, CommandError};
+
+/// Environment variable holding a token with every scope
+pub const TOKEN_ENV: &str = "TINKER_API_TOKEN";
+
+/// How long browsers may cache a preflight answer, in seconds
+const PREFLIGHT_MAX_AGE: &str = "600";
+
+/// What a token allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Read tabs, pages and events
+    Events,
+    /// Open, close and switch tabs, navigate and replay
+    Navigate,
+    /// Run scripts in the page
+    Script,
+    /// Everything
+    Admin,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Shown in logs and audit events instead of the token itself
+    pub name: String,
+    pub token: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl ApiToken {
+    pub fn allows(&self, scope: Scope) -> bool {
+        covers(&self.scopes, scope)
+    }
+}
+
+fn covers(scopes: &[Scope], scope: Scope) -> bool {
+    scopes.contains(&Scope::Admin) || scopes.contains(&scope)
+}
+
+/// Tokens and origins the API accepts
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiAccess {
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+    /// Web origins allowed to call the API, e.g. `http://localhost:8080`, or `*` for any
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+    /// Address the API listens on, also accepted as `Host`
+    #[serde(skip)]
+    pub bind_address: Option<IpAddr>,
+}
+
+impl ApiAccess {
+    /// Read tokens and origins from a JSON file
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read API access file {}: {}", path.display(), e))?;
+        let access: Self = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid API access file {}: {}", path.display(), e))?;
+        if let Some(blank) = access.tokens.iter().find(|token| token.token.trim().is_empty()) {
+            return Err(format!("Invalid API access file {}: token {} is empty", path.display(), blank.name).into());
+        }
+        Ok(access)
+    }
+
+    /// Add an admin token taken from the environment
+    pub fn with_admin_token(mut self, token: String) -> Result<Self, Box<dyn std::error::Error>> {
+        if token.trim().is_empty() {
+            return Err("An empty API token would let anyone in".into());
+        }
+        self.tokens.push(ApiToken {
+            name: "env".to_string(),
+            token,
+            scopes: vec![Scope::Admin],
+        });
+        Ok(self)
+    }
+
+    pub fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+
+    /// No tokens are configured, so every request is allowed
+    pub fn is_open(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// The token matching what a client presented
+    pub fn authenticate(&self, presented: &str) -> Option<&ApiToken> {
+        if presented.trim().is_empty() {
+            return None;
+        }
+        // Compare MACs so the time taken says nothing about the tokens
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"tinker-api-token");
+        let tag = hmac::sign(&key, presented.as_bytes());
+        self.tokens.iter().find(|token| hmac::verify(&key, token.token.as_bytes(), tag.as_ref()).is_ok())
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.cors_origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    /// Whether a `Host` header, port and all, names this machine's loopback or the bind address
+    pub fn allows_host(&self, host: &str) -> bool {
+        let name = match host.strip_prefix('[') {
+            Some(rest) => rest.split(']').next().unwrap_or_default(),
+            None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+        };
+        if name.eq_ignore_ascii_case("localhost") {
+            return true;
+        }
+        match name.parse::<IpAddr>() {
+            Ok(address) => address.is_loopback() || Some(address) == self.bind_address,
+            Err(_) => false,
+        }
+    }
+}
+
+/// The token a request was made with, for handlers that check scopes themselves
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Grant {
+    pub fn allows(&self, scope: Scope) -> bool {
+        covers(&self.scopes, scope)
+    }
+}
+
+/// Scope a request needs, from its method and path
+pub fn required_scope(method: &Method, path: &str) -> Scope {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let reading = method == Method::GET || method == Method::HEAD;
+    match segments.as_slice() {
+        // The WebSocket transport takes any command, shutdown included
+        ["events", "ws"] => Scope::Admin,
+        // DevTools methods are checked one by one once connected
+        ["devtools", ..] => Scope::Events,
+        ["session", _, "element", ..] | ["session", _, "execute", ..] | ["session", _, "screenshot"] => Scope::Script,
+        _ if reading => Scope::Events,
+        _ => Scope::Navigate,
+    }
+}
+
+/// Apply `access` to every route of `app`, auditing changes through `browser`
+pub fn protect(app: Router, access: ApiAccess, browser: BrowserHandle) -> Router {
+    app.layer(middleware::from_fn_with_state((access, browser), guard))
+}
+
+fn refuse(status: StatusCode, kind: &str, message: String) -> Response {
+    let mut response = (status, Json(CommandError::new(kind, message))).into_response();
+    if status == StatusCode::UNAUTHORIZED {
+        response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    }
+    response
+}
+
+/// A WebSocket handshake or an EventSource request, which cannot send headers
+fn is_stream(request: &Request<Body>) -> bool {
+    let header_contains = |name, value: &str| request.headers().get_all(name).iter()
+        .filter_map(|header| header.to_str().ok())
+        .any(|header| header.to_ascii_lowercase().contains(value));
+    header_contains(header::UPGRADE, "websocket") || header_contains(header::ACCEPT, "text/event-stream")
+}
+
+fn presented_token(request: &Request<Body>) -> Option<String> {
+    let header = request.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    header.or_else(|| {
+        if !is_stream(request) {
+            return None;
+        }
+        request.uri().query()?
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("access_token="))
+            .and_then(|token| urlencoding::decode(token).ok())
+            .map(|token| token.into_owned())
+    })
+}
+
+async fn guard(
+    State((access, browser)): State<(ApiAccess, BrowserHandle)>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    // Browsers always send Host; only a rebound name would differ from these
+    let host = request.headers().get(header::HOST).map(|host| host.to_str().unwrap_or_default());
+    if let Some(host) = host.filter(|host| access.is_open() && !access.allows_host(host)) {
+        return refuse(StatusCode::FORBIDDEN, "forbidden", format!("Host {} is not allowed", host));
+    }
+
+    let origin = request.headers().get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .map(str::to_string);
+    if let Some(origin) = &origin {
+        if !access.allows_origin(origin) {
+            return refuse(StatusCode::FORBIDDEN, "forbidden", format!("Origin {} is not allowed", origin));
+        }
+    }
+
+    // Preflights carry no credentials; the request they announce is checked when it comes
+    if request.method() == Method::OPTIONS && origin.is_some() {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, POST, DELETE"));
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("authorization, content-type"));
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static(PREFLIGHT_MAX_AGE));
+        return allow_origin(response, origin);
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let changes = method != Method::GET && method != Method::HEAD;
+    let audit = |token: &str, success: bool| {
+        if changes {
+            browser.report(BrowserEvent::ApiAudit {
+                token: token.to_string(),
+                action: format!("{} {}", method, path),
+                success,
+            });
+        }
+    };
+    let mut grant = None;
+    if !access.is_open() && path != "/health" {
+        let Some(token) = presented_token(&request).and_then(|token| access.authenticate(&token)) else {
+            return refuse(StatusCode::UNAUTHORIZED, "unauthorized", "A valid bearer token is required".to_string());
+        };
+        let scope = required_scope(&method, &path);
+        if !token.allows(scope) {
+            audit(&token.name, false);
+            let message = format!("Token {} lacks the {:?} scope for {} {}", token.name, scope, method, path);
+            return allow_origin(refuse(StatusCode::FORBIDDEN, "forbidden", message), origin);
+        }
+        let token = Grant { name: token.name.clone(), scopes: token.scopes.clone() };
+        request.extensions_mut().insert(token.clone());
+        grant = Some(token);
+    }
+
+    let response = next.run(request).await;
+    if let Some(grant) = grant {
+        audit(&grant.name, response.status().is_success());
+    }
+    allow_origin(response, origin)
+}
+
+fn allow_origin(mut response: Response, origin: Option<String>) -> Response {
+    if let Some(value) = origin.and_then(|origin| HeaderValue::from_str(&origin).ok()) {
+        response.headers_mut().insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        response.headers_mut().insert(header::VARY, HeaderValue::from_static("origin"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_scopes() {
+        assert_eq!(required_scope(&Method::GET, "/tabs"), Scope::Events);
+        assert_eq!(required_scope(&Method::POST, "/navigate"), Scope::Navigate);
+        assert_eq!(required_scope(&Method::DELETE, "/tabs/3"), Scope::Navigate);
+        assert_eq!(required_scope(&Method::POST, "/session/abc/url"), Scope::Navigate);
+        assert_eq!(required_scope(&Method::GET, "/session/abc/element/PAGE-1/text"), Scope::Script);
+        assert_eq!(required_scope(&Method::POST, "/session/abc/execute/sync"), Scope::Script);
+        assert_eq!(required_scope(&Method::GET, "/devtools/browser"), Scope::Events);
+        assert_eq!(required_scope(&Method::GET, "/events/ws"), Scope::Admin);
+    }
+
+    #[test]
+    fn test_tokens_and_origins() {
+        let access: ApiAccess = serde_json::from_str(r#"{
+            "tokens": [{ "name": "ci", "token": "s3cret", "scopes": ["events", "navigate"] }],
+            "cors_origins": ["http://localhost:8080"]
+        }"#).unwrap();
+        let access = access.with_admin_token("root".to_string()).unwrap();
+
+        let ci = access.authenticate("s3cret").unwrap();
+        assert_eq!(ci.name, "ci");
+        assert!(ci.allows(Scope::Navigate) && !ci.allows(Scope::Script));
+        assert!(access.authenticate("root").unwrap().allows(Scope::Script));
+        assert!(access.authenticate("s3cre").is_none());
+        assert!(access.authenticate("").is_none());
+        assert!(access.clone().with_admin_token(" ".to_string()).is_err());
+
+        assert!(access.allows_origin("http://localhost:8080"));
+        assert!(!access.allows_origin("https://evil.example"));
+        assert!(!ApiAccess::default().allows_origin("http://localhost:8080"));
+    }
+
+    #[test]
+    fn test_hosts() {
+        let access = ApiAccess::default();
+        assert!(access.allows_host("localhost:3005"));
+        assert!(access.allows_host("LOCALHOST"));
+        assert!(access.allows_host("127.0.0.1:3005"));
+        assert!(access.allows_host("[::1]:3005"));
+        assert!(!access.allows_host("rebound.example:3005"));
+        assert!(!access.allows_host("localhost.rebound.example"));
+        assert!(!access.allows_host("192.168.1.20:3005"));
+        assert!(access.with_bind_address("192.168.1.20".parse().unwrap()).allows_host("192.168.1.20:3005"));
+    }
+}
@@ -0,0 +1,763 @@
+//! Chrome DevTools Protocol subset over WebSocket, for Puppeteer-style clients.
+//!
+//! `GET /json/version` and `GET /json/list` describe the browser and its
+//! targets the way Chrome does; clients then connect to `/devtools/browser`
+//! and attach to targets, or straight to `/devtools/page/:id`. Targets are
+//! tabs and the tab id is the target id. Scripts and navigation only run in
+//! the active tab, so commands for a target bring its tab to the front first.
+//!
+//! Supported: `Browser.getVersion`/`close`, `Target` discovery, attaching,
+//! creating, activating and closing, `Page.navigate`/`reload`/
+//! `captureScreenshot`/`getFrameTree`, and `Runtime.evaluate`/`callFunctionOn`
+//! with results always returned by value. Network events are mirrored from
+//! Tinker's own navigation and page-load events, so they cover documents only
+//! and report every response as a 200. With API tokens configured, each method
+//! needs the scope its effect calls for, and changes are audited.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Extension, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, warn};
+use crate::browser::BrowserHandle;
+use crate::event::{BrowserCommand, BrowserEvent, CommandResult, EventEnvelope, TabInfo};
+use super::auth::{Grant, Scope};
+use super::webdriver::{self, WebDriverError};
+
+/// Protocol version reported to clients
+pub const PROTOCOL_VERSION: &str = "1.3";
+
+const BROWSER_PATH: &str = "/devtools/browser";
+const PAGE_PATH: &str = "/devtools/page";
+
+/// How long an awaited promise or a screenshot may take
+const RESULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// DevTools discovery and WebSocket routes, to merge into the API router
+pub fn router(browser: BrowserHandle) -> Router {
+    Router::new()
+        .route("/json/version", get(version))
+        .route("/json", get(list_targets))
+        .route("/json/list", get(list_targets))
+        .route(BROWSER_PATH, get(browser_socket))
+        .route(&format!("{}/:id", PAGE_PATH), get(page_socket))
+        .with_state(browser)
+}
+
+fn host(headers: &HeaderMap) -> &str {
+    headers.get(header::HOST).and_then(|host| host.to_str().ok()).unwrap_or("localhost")
+}
+
+async fn version(headers: HeaderMap) -> Json<Value> {
+    Json(json!({
+        "Browser": format!("Tinker/{}", env!("CARGO_PKG_VERSION")),
+        "Protocol-Version": PROTOCOL_VERSION,
+        "webSocketDebuggerUrl": format!("ws://{}{}", host(&headers), BROWSER_PATH),
+    }))
+}
+
+async fn list_targets(State(browser): State<BrowserHandle>, headers: HeaderMap) -> Json<Vec<Value>> {
+    let targets = browser.tabs().into_iter().map(|tab| json!({
+        "id": tab.id.to_string(),
+        "type": "page",
+        "title": tab.title,
+        "url": tab.url,
+        "description": "",
+        "webSocketDebuggerUrl": format!("ws://{}{}/{}", host(&headers), PAGE_PATH, tab.id),
+    }));
+    Json(targets.collect())
+}
+
+async fn browser_socket(
+    ws: WebSocketUpgrade,
+    State(browser): State<BrowserHandle>,
+    grant: Option<Extension<Grant>>,
+) -> Response {
+    // Subscribe before upgrading so nothing that happens after the handshake is missed
+    let feed = browser.subscribe();
+    let grant = grant.map(|Extension(grant)| grant);
+    ws.on_upgrade(move |socket| serve(socket, browser, grant, None, feed))
+}
+
+async fn page_socket(
+    ws: WebSocketUpgrade,
+    State(browser): State<BrowserHandle>,
+    grant: Option<Extension<Grant>>,
+    Path(id): Path<usize>,
+) -> Response {
+    if !browser.tabs().iter().any(|tab| tab.id == id) {
+        return (StatusCode::NOT_FOUND, format!("No target with id {}", id)).into_response();
+    }
+    let feed = browser.subscribe();
+    let grant = grant.map(|Extension(grant)| grant);
+    ws.on_upgrade(move |socket| serve(socket, browser, grant, Some(id), feed))
+}
+
+/// Scope a method needs; anything beyond `events` changes the browser and is audited
+fn required_scope(method: &str) -> Scope {
+    match method {
+        "Browser.close" => Scope::Admin,
+        "Runtime.enable" | "Runtime.disable" => Scope::Events,
+        "Page.captureScreenshot" => Scope::Script,
+        method if method.starts_with("Runtime.") => Scope::Script,
+        "Page.navigate" | "Page.reload" | "Page.bringToFront"
+        | "Target.createTarget" | "Target.closeTarget" | "Target.activateTarget" => Scope::Navigate,
+        _ => Scope::Events,
+    }
+}
+
+/// A failed command, answered as `{"id", "error": {"code", "message"}}`
+#[derive(Debug, Clone, PartialEq)]
+struct ProtocolError {
+    code: i64,
+    message: String,
+}
+
+impl ProtocolError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    fn method_not_found(method: &str) -> Self {
+        Self::new(METHOD_NOT_FOUND, format!("'{}' wasn't found", method))
+    }
+}
+
+impl From<WebDriverError> for ProtocolError {
+    fn from(error: WebDriverError) -> Self {
+        Self::new(SERVER_ERROR, error.message)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    id: u64,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(rename = "sessionId")]
+    session_id: Option<String>,
+}
+
+/// What one connection is attached to and listening for
+#[derive(Debug, Default)]
+struct Connection {
+    /// Target of a `/devtools/page/:id` connection, which needs no session
+    page: Option<usize>,
+    sessions: HashMap<String, usize>,
+    next_id: u64,
+    discover: bool,
+    /// Enabled domains per session, `None` being the connection itself
+    enabled: HashSet<(Option<String>, String)>,
+    /// Loader of each tab's current document
+    loaders: HashMap<usize, String>,
+    /// Loader promised by `Page.navigate` for the navigation to come
+    expected: HashMap<usize, String>,
+    contexts: HashMap<usize, u64>,
+}
+
+impl Connection {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn new_loader(&mut self) -> String {
+        format!("loader-{}", self.next_id())
+    }
+
+    fn context(&mut self, tab: usize) -> u64 {
+        if let Some(context) = self.contexts.get(&tab) {
+            return *context;
+        }
+        let context = self.next_id();
+        self.contexts.insert(tab, context);
+        context
+    }
+
+    /// Sessions listening to a tab, `None` being the connection itself
+    fn listeners(&self, tab: usize) -> Vec<Option<String>> {
+        let page = (self.page == Some(tab)).then_some(None);
+        let sessions = self.sessions.iter()
+            .filter(|(_, target)| **target == tab)
+            .map(|(session, _)| Some(session.clone()));
+        page.into_iter().chain(sessions).collect()
+    }
+
+    fn is_enabled(&self, session: &Option<String>, domain: &str) -> bool {
+        self.enabled.contains(&(session.clone(), domain.to_string()))
+    }
+}
+
+fn event(session: Option<&str>, method: &str, params: Value) -> Value {
+    let mut message = json!({ "method": method, "params": params });
+    if let Some(session) = session {
+        message["sessionId"] = json!(session);
+    }
+    message
+}
+
+fn target_info(tab: &TabInfo, attached: bool) -> Value {
+    json!({
+        "targetId": tab.id.to_string(),
+        "type": "page",
+        "title": tab.title,
+        "url": tab.url,
+        "attached": attached,
+        "canAccessOpener": false,
+        "browserContextId": "default",
+    })
+}
+
+fn frame(tab: usize, url: &str, loader: &str) -> Value {
+    let origin = url::Url::parse(url).map(|url| url.origin().ascii_serialization()).unwrap_or_default();
+    json!({
+        "id": tab.to_string(),
+        "loaderId": loader,
+        "url": url,
+        "securityOrigin": origin,
+        "mimeType": "text/html",
+    })
+}
+
+fn parse_target(params: &Value) -> Result<usize, ProtocolError> {
+    params["targetId"].as_str()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| ProtocolError::new(INVALID_PARAMS, "No target with given id found"))
+}
+
+fn exception(message: &str) -> Value {
+    let error = json!({ "type": "object", "subtype": "error", "className": "Error", "description": message });
+    json!({
+        "result": error,
+        "exceptionDetails": {
+            "exceptionId": 1,
+            "text": "Uncaught",
+            "lineNumber": 0,
+            "columnNumber": 0,
+            "exception": error,
+        },
+    })
+}
+
+/// One client connection; commands run one at a time, in the order they came
+#[derive(Clone)]
+struct Client {
+    browser: BrowserHandle,
+    /// Token the connection was opened with, when the API requires one
+    grant: Option<Grant>,
+    state: Arc<Mutex<Connection>>,
+    out: mpsc::UnboundedSender<Value>,
+}
+
+impl Client {
+    fn state(&self) -> MutexGuard<'_, Connection> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn send(&self, message: Value) {
+        // The socket is gone when nobody is receiving
+        let _ = self.out.send(message);
+    }
+
+    fn tab(&self, id: usize) -> Result<TabInfo, ProtocolError> {
+        self.browser.tabs().into_iter()
+            .find(|tab| tab.id == id)
+            .ok_or_else(|| ProtocolError::new(SERVER_ERROR, "No target with given id found"))
+    }
+
+    fn is_attached(&self, tab: usize) -> bool {
+        let state = self.state();
+        state.page == Some(tab) || state.sessions.values().any(|target| *target == tab)
+    }
+
+    async fn command(&self, command: BrowserCommand) -> Result<CommandResult, ProtocolError> {
+        Ok(webdriver::command(&self.browser, command).await?)
+    }
+
+    /// Make a tab the active one, which page commands need
+    async fn bring_to_front(&self, tab: usize) -> Result<(), ProtocolError> {
+        if self.browser.active_tab().map(|active| active.id) != Some(tab) {
+            self.command(BrowserCommand::SwitchTab { id: tab }).await?;
+        }
+        Ok(())
+    }
+
+    async fn respond(&self, text: &str) {
+        let request: Request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                self.send(json!({ "error": { "code": PARSE_ERROR, "message": format!("Invalid message: {}", e) } }));
+                return;
+            }
+        };
+        let scope = required_scope(&request.method);
+        let outcome = match &self.grant {
+            Some(grant) if !grant.allows(scope) => Err(ProtocolError::new(
+                SERVER_ERROR,
+                format!("Token {} lacks the {:?} scope for {}", grant.name, scope, request.method),
+            )),
+            _ => self.handle(&request).await,
+        };
+        if let (Some(grant), true) = (&self.grant, scope != Scope::Events) {
+            self.browser.report(BrowserEvent::ApiAudit {
+                token: grant.name.clone(),
+                action: request.method.clone(),
+                success: outcome.is_ok(),
+            });
+        }
+        let mut reply = match outcome {
+            Ok(result) => json!({ "id": request.id, "result": result }),
+            Err(error) => json!({ "id": request.id, "error": { "code": error.code, "message": error.message } }),
+        };
+        if let Some(session) = request.session_id {
+            reply["sessionId"] = json!(session);
+        }
+        self.send(reply);
+    }
+
+    async fn handle(&self, request: &Request) -> Result<Value, ProtocolError> {
+        let session = request.session_id.clone();
+        let target = match &session {
+            Some(id) => Some(*self.state().sessions.get(id)
+                .ok_or_else(|| ProtocolError::new(SERVER_ERROR, format!("Session with given id not found: {}", id)))?),
+            None => self.state().page,
+        };
+        let params = &request.params;
+        let page = || target.ok_or_else(|| ProtocolError::method_not_found(&request.method));
+
+        match request.method.as_str() {
+            "Browser.getVersion" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "product": format!("Tinker/{}", env!("CARGO_PKG_VERSION")),
+                "revision": "",
+                "userAgent": "",
+                "jsVersion": "",
+            })),
+            "Browser.close" => {
+                self.command(BrowserCommand::Shutdown).await?;
+                Ok(json!({}))
+            }
+            "Target.setDiscoverTargets" => {
+                let discover = params["discover"].as_bool().unwrap_or(false);
+                self.state().discover = discover;
+                if discover {
+                    for tab in self.browser.tabs() {
+                        let info = target_info(&tab, self.is_attached(tab.id));
+                        self.send(event(None, "Target.targetCreated", json!({ "targetInfo": info })));
+                    }
+                }
+                Ok(json!({}))
+            }
+            "Target.getTargets" => {
+                let infos: Vec<Value> = self.browser.tabs().iter()
+                    .map(|tab| target_info(tab, self.is_attached(tab.id)))
+                    .collect();
+                Ok(json!({ "targetInfos": infos }))
+            }
+            "Target.getTargetInfo" => {
+                let id = if params["targetId"].is_string() { parse_target(params)? } else { page()? };
+                Ok(json!({ "targetInfo": target_info(&self.tab(id)?, self.is_attached(id)) }))
+            }
+            // Tinker does not open targets on its own, so there is nothing to attach to automatically
+            "Target.setAutoAttach" => Ok(json!({})),
+            "Target.attachToTarget" => {
+                let tab = self.tab(parse_target(params)?)?;
+                let id = {
+                    let mut state = self.state();
+                    let id = format!("session-{}", state.next_id());
+                    state.sessions.insert(id.clone(), tab.id);
+                    id
+                };
+                self.send(event(None, "Target.attachedToTarget", json!({
+                    "sessionId": id,
+                    "targetInfo": target_info(&tab, true),
+                    "waitingForDebugger": false,
+                })));
+                Ok(json!({ "sessionId": id }))
+            }
+            "Target.detachFromTarget" => {
+                let id = params["sessionId"].as_str().unwrap_or_default();
+                let detached = self.state().sessions.remove(id)
+                    .ok_or_else(|| ProtocolError::new(SERVER_ERROR, "No session with given id"))?;
+                self.state().enabled.retain(|(session, _)| session.as_deref() != Some(id));
+                self.send(event(None, "Target.detachedFromTarget", json!({
+                    "sessionId": id,
+                    "targetId": detached.to_string(),
+                })));
+                Ok(json!({}))
+            }
+            "Target.createTarget" => {
+                let url = params["url"].as_str().filter(|url| !url.is_empty()).unwrap_or("about:blank");
+                match self.command(BrowserCommand::CreateTab { url: url.to_string() }).await? {
+                    CommandResult::TabCreated { id, .. } => Ok(json!({ "targetId": id.to_string() })),
+                    other => Err(ProtocolError::new(SERVER_ERROR, format!("Unexpected result {:?}", other))),
+                }
+            }
+            "Target.closeTarget" => {
+                self.command(BrowserCommand::CloseTab { id: parse_target(params)? }).await?;
+                Ok(json!({ "success": true }))
+            }
+            "Target.activateTarget" => {
+                self.bring_to_front(parse_target(params)?).await?;
+                Ok(json!({}))
+            }
+            "Page.enable" | "Page.disable" | "Network.enable" | "Network.disable" | "Runtime.enable" | "Runtime.disable" => {
+                let tab = page()?;
+                let (domain, action) = request.method.split_once('.').unwrap_or_default();
+                let key = (session.clone(), domain.to_string());
+                if action == "disable" {
+                    self.state().enabled.remove(&key);
+                    return Ok(json!({}));
+                }
+                let newly = self.state().enabled.insert(key);
+                if newly && domain == "Runtime" {
+                    let url = self.tab(tab)?.url;
+                    let context = self.state().context(tab);
+                    self.send(event(session.as_deref(), "Runtime.executionContextCreated", json!({
+                        "context": {
+                            "id": context,
+                            "origin": frame(tab, &url, "")["securityOrigin"],
+                            "name": "",
+                            "auxData": { "isDefault": true, "type": "default", "frameId": tab.to_string() },
+                        },
+                    })));
+                }
+                Ok(json!({}))
+            }
+            // Lifecycle events are sent whenever the Page domain is enabled
+            "Page.setLifecycleEventsEnabled" => page().map(|_| json!({})),
+            "Page.getFrameTree" => {
+                let tab = self.tab(page()?)?;
+                let loader = self.state().loaders.get(&tab.id).cloned().unwrap_or_default();
+                Ok(json!({ "frameTree": { "frame": frame(tab.id, &tab.url, &loader) } }))
+            }
+            "Page.bringToFront" => {
+                self.bring_to_front(page()?).await?;
+                Ok(json!({}))
+            }
+            "Page.navigate" => {
+                let tab = page()?;
+                let url = params["url"].as_str()
+                    .ok_or_else(|| ProtocolError::new(INVALID_PARAMS, "Invalid parameters: url: string value expected"))?;
+                self.bring_to_front(tab).await?;
+                let loader = {
+                    let mut state = self.state();
+                    let loader = state.new_loader();
+                    state.expected.insert(tab, loader.clone());
+                    loader
+                };
+                if let Err(error) = self.command(BrowserCommand::Navigate { url: url.to_string() }).await {
+                    self.state().expected.remove(&tab);
+                    return Ok(json!({ "frameId": tab.to_string(), "errorText": error.message }));
+                }
+                Ok(json!({ "frameId": tab.to_string(), "loaderId": loader }))
+            }
+            "Page.reload" => {
+                self.bring_to_front(page()?).await?;
+                self.command(BrowserCommand::Reload).await?;
+                Ok(json!({}))
+            }
+            "Page.captureScreenshot" => {
+                let tab = page()?;
+                if !matches!(params["format"].as_str(), None | Some("png")) {
+                    return Err(ProtocolError::new(INVALID_PARAMS, "Only PNG screenshots are supported"));
+                }
+                self.bring_to_front(tab).await?;
+                let token = webdriver::call(&self.browser, "screenshot", json!([])).await?;
+                let data = webdriver::poll(&self.browser, "result", json!([token]), RESULT_TIMEOUT).await?;
+                Ok(json!({ "data": data }))
+            }
+            "Runtime.evaluate" => {
+                let tab = page()?;
+                let expression = params["expression"].as_str()
+                    .ok_or_else(|| ProtocolError::new(INVALID_PARAMS, "Invalid parameters: expression: string value expected"))?;
+                self.bring_to_front(tab).await?;
+                self.evaluate(expression.to_string(), params["awaitPromise"].as_bool().unwrap_or(false)).await
+            }
+            "Runtime.callFunctionOn" => {
+                let tab = page()?;
+                let declaration = params["functionDeclaration"].as_str()
+                    .ok_or_else(|| ProtocolError::new(INVALID_PARAMS, "Invalid parameters: functionDeclaration: string value expected"))?;
+                let mut args = Vec::new();
+                for arg in params["arguments"].as_array().into_iter().flatten() {
+                    if arg.get("objectId").is_some() {
+                        return Err(ProtocolError::new(SERVER_ERROR, "Object handles are not supported; pass arguments by value"));
+                    }
+                    args.push(arg["value"].clone());
+                }
+                self.bring_to_front(tab).await?;
+                let expression = format!("({}).apply(undefined, {})", declaration, Value::Array(args));
+                self.evaluate(expression, params["awaitPromise"].as_bool().unwrap_or(false)).await
+            }
+            method => Err(ProtocolError::method_not_found(method)),
+        }
+    }
+
+    /// Evaluate in the active tab, with exceptions reported as `exceptionDetails`
+    async fn evaluate(&self, expression: String, await_promise: bool) -> Result<Value, ProtocolError> {
+        let mut outcome = webdriver::call(&self.browser, "evaluate", json!([expression, await_promise])).await;
+        if let Ok(pending) = &outcome {
+            if let Some(token) = pending.get("pending") {
+                outcome = webdriver::poll(&self.browser, "result", json!([token]), RESULT_TIMEOUT).await;
+            }
+        }
+        match outcome {
+            Ok(result) => Ok(json!({ "result": result })),
+            Err(error) if error.error == "javascript error" => Ok(exception(&error.message)),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Protocol events for one of Tinker's events
+    fn mirror(&self, envelope: &EventEnvelope) -> Vec<Value> {
+        let mut messages = Vec::new();
+        let mut state = self.state();
+
+        if let BrowserEvent::TabClosed { id } = envelope.event {
+            let closed: Vec<String> = state.sessions.iter()
+                .filter(|(_, target)| **target == id)
+                .map(|(session, _)| session.clone())
+                .collect();
+            for session in closed {
+                state.sessions.remove(&session);
+                state.enabled.retain(|(enabled, _)| enabled.as_ref() != Some(&session));
+                messages.push(event(None, "Target.detachedFromTarget", json!({
+                    "sessionId": session,
+                    "targetId": id.to_string(),
+                })));
+            }
+            if state.discover {
+                messages.push(event(None, "Target.targetDestroyed", json!({ "targetId": id.to_string() })));
+            }
+        }
+
+        if state.discover {
+            let created = matches!(envelope.event, BrowserEvent::TabCreated { .. });
+            let changed = matches!(
+                envelope.event,
+                BrowserEvent::TabActivated { .. } | BrowserEvent::TabUrlChanged { .. } | BrowserEvent::TabTitleChanged { .. }
+            );
+            let tab = envelope.tab_id.and_then(|id| self.browser.tabs().into_iter().find(|tab| tab.id == id));
+            if let (Some(tab), true) = (tab, created || changed) {
+                let attached = state.page == Some(tab.id) || state.sessions.values().any(|target| *target == tab.id);
+                let method = if created { "Target.targetCreated" } else { "Target.targetInfoChanged" };
+                messages.push(event(None, method, json!({ "targetInfo": target_info(&tab, attached) })));
+            }
+        }
+
+        let Some(tab) = envelope.tab_id else {
+            return messages;
+        };
+        let listeners = state.listeners(tab);
+        if listeners.is_empty() {
+            return messages;
+        }
+        let frame_id = tab.to_string();
+        let timestamp = envelope.monotonic_ms as f64 / 1000.0;
+        let wall_time = DateTime::parse_from_rfc3339(&envelope.timestamp)
+            .map(|time| time.timestamp_millis() as f64 / 1000.0)
+            .unwrap_or_default();
+
+        match &envelope.event {
+            BrowserEvent::Navigation { url } => {
+                let loader = state.expected.remove(&tab).unwrap_or_else(|| state.new_loader());
+                state.loaders.insert(tab, loader.clone());
+                state.contexts.remove(&tab);
+                for session in &listeners {
+                    let session_id = session.as_deref();
+                    if state.is_enabled(session, "Network") {
+                        messages.push(event(session_id, "Network.requestWillBeSent", json!({
+                            "requestId": loader,
+                            "loaderId": loader,
+                            "documentURL": url,
+                            "request": { "url": url, "method": "GET", "headers": {} },
+                            "timestamp": timestamp,
+                            "wallTime": wall_time,
+                            "initiator": { "type": "other" },
+                            "type": "Document",
+                            "frameId": frame_id,
+                        })));
+                    }
+                    if state.is_enabled(session, "Page") {
+                        messages.push(event(session_id, "Page.frameStartedLoading", json!({ "frameId": frame_id })));
+                        messages.push(event(session_id, "Page.lifecycleEvent", json!({
+                            "frameId": frame_id,
+                            "loaderId": loader,
+                            "name": "init",
+                            "timestamp": timestamp,
+                        })));
+                    }
+                    if state.is_enabled(session, "Runtime") {
+                        messages.push(event(session_id, "Runtime.executionContextsCleared", json!({})));
+                    }
+                }
+            }
+            BrowserEvent::PageLoaded { url } => {
+                let loader = match state.loaders.get(&tab) {
+                    Some(loader) => loader.clone(),
+                    None => {
+                        let loader = state.new_loader();
+                        state.loaders.insert(tab, loader.clone());
+                        loader
+                    }
+                };
+                let context = state.context(tab);
+                let frame = frame(tab, url, &loader);
+                for session in &listeners {
+                    let session_id = session.as_deref();
+                    if state.is_enabled(session, "Network") {
+                        messages.push(event(session_id, "Network.responseReceived", json!({
+                            "requestId": loader,
+                            "loaderId": loader,
+                            "timestamp": timestamp,
+                            "type": "Document",
+                            "response": {
+                                "url": url,
+                                "status": 200,
+                                "statusText": "",
+                                "headers": {},
+                                "mimeType": "text/html",
+                                "connectionReused": false,
+                                "connectionId": 0,
+                                "encodedDataLength": 0,
+                                "securityState": "unknown",
+                            },
+                            "frameId": frame_id,
+                        })));
+                        messages.push(event(session_id, "Network.loadingFinished", json!({
+                            "requestId": loader,
+                            "timestamp": timestamp,
+                            "encodedDataLength": 0,
+                        })));
+                    }
+                    if state.is_enabled(session, "Page") {
+                        messages.push(event(session_id, "Page.frameNavigated", json!({ "frame": frame, "type": "Navigation" })));
+                        for name in ["DOMContentLoaded", "load"] {
+                            messages.push(event(session_id, "Page.lifecycleEvent", json!({
+                                "frameId": frame_id,
+                                "loaderId": loader,
+                                "name": name,
+                                "timestamp": timestamp,
+                            })));
+                        }
+                        messages.push(event(session_id, "Page.domContentEventFired", json!({ "timestamp": timestamp })));
+                        messages.push(event(session_id, "Page.loadEventFired", json!({ "timestamp": timestamp })));
+                        messages.push(event(session_id, "Page.frameStoppedLoading", json!({ "frameId": frame_id })));
+                    }
+                    if state.is_enabled(session, "Runtime") {
+                        messages.push(event(session_id, "Runtime.executionContextCreated", json!({
+                            "context": {
+                                "id": context,
+                                "origin": frame["securityOrigin"],
+                                "name": "",
+                                "auxData": { "isDefault": true, "type": "default", "frameId": frame_id },
+                            },
+                        })));
+                    }
+                }
+            }
+            _ => {}
+        }
+        messages
+    }
+}
+
+/// The next event, waiting forever once no more can come
+async fn next_event(feed: &mut Option<broadcast::Receiver<EventEnvelope>>) -> EventEnvelope {
+    loop {
+        let Some(receiver) = feed.as_mut() else {
+            return std::future::pending().await;
+        };
+        match receiver.recv().await {
+            Ok(envelope) => return envelope,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("DevTools client fell behind and missed {} events", missed);
+            }
+            Err(broadcast::error::RecvError::Closed) => *feed = None,
+        }
+    }
+}
+
+async fn serve(
+    mut socket: WebSocket,
+    browser: BrowserHandle,
+    grant: Option<Grant>,
+    page: Option<usize>,
+    mut feed: Option<broadcast::Receiver<EventEnvelope>>,
+) {
+    debug!("DevTools client connected (target: {:?})", page);
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+    let (in_tx, mut in_rx) = mpsc::unbounded_channel::<String>();
+    let client = Client {
+        browser,
+        grant,
+        state: Arc::new(Mutex::new(Connection { page, ..Connection::default() })),
+        out: out_tx,
+    };
+
+    let worker = {
+        let client = client.clone();
+        tokio::spawn(async move {
+            while let Some(text) = in_rx.recv().await {
+                client.respond(&text).await;
+            }
+        })
+    };
+
+    'connection: loop {
+        let outgoing = tokio::select! {
+            Some(message) = out_rx.recv() => vec![message],
+            envelope = next_event(&mut feed) => client.mirror(&envelope),
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let _ = in_tx.send(text);
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    debug!("DevTools socket error: {}", e);
+                    break;
+                }
+            },
+        };
+        for message in outgoing {
+            if socket.send(Message::Text(message.to_string())).await.is_err() {
+                break 'connection;
+            }
+        }
+    }
+    worker.abort();
+    debug!("DevTools client disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listeners_are_the_page_and_its_sessions() {
+        let mut connection = Connection { page: Some(2), ..Connection::default() };
+        connection.sessions.insert("session-1".to_string(), 2);
+        connection.sessions.insert("session-2".to_string(), 3);
+        assert_eq!(connection.listeners(2), vec![None, Some("session-1".to_string())]);
+        assert_eq!(connection.listeners(3), vec![Some("session-2".to_string())]);
+        assert!(connection.listeners(4).is_empty());
+    }
+}
@@ -1,18 +1,112 @@
 //! HTTP API server
 
+pub mod auth;
+pub mod cdp;
+mod stream;
+pub mod webdriver;
+
 use std::net::SocketAddr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 use axum::{
-    routing::get,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{delete, get, post},
     Router,
     Json,
 };
+use serde::Deserialize;
 use tracing::info;
+use crate::browser::BrowserHandle;
+use crate::event::{
+    acl, Breakpoint, BrowserCommand, CommandError, CommandResult, EventEnvelope, PendingCommand, ReplayControl,
+    SeekTarget, TabInfo, DEFAULT_COMMAND_TIMEOUT_MS,
+};
+
+/// Events `GET /events` returns unless `limit` says otherwise
+const DEFAULT_EVENT_LIMIT: usize = 50;
+
+/// Error body and status for a command that failed or never ran
+type ApiError = (StatusCode, Json<CommandError>);
+
+/// Routes every API server exposes
+pub fn router() -> Router {
+    Router::new()
+        .route("/health", get(health_check))
+}
+
+/// Everything the `--api-port` server offers: health, browser control, replay control, WebDriver and DevTools
+pub fn app(browser: BrowserHandle) -> Router {
+    router()
+        .merge(control_router(browser.clone()))
+        .merge(webdriver::router(browser.clone()))
+        .merge(cdp::router(browser.clone()))
+        .merge(replay_router(browser))
+}
 
-pub async fn start_api_server() -> Result<(), Box<dyn std::error::Error>> {
-    let app = Router::new()
-        .route("/health", get(health_check));
+/// Browser control
+///
+/// `GET /tabs`, `POST /tabs` with `{"url": ...}`, `DELETE /tabs/:id`,
+/// `POST /tabs/:id/activate`, `POST /navigate` with `{"url": ...}`, `GET /page`
+/// for the active tab, `GET /events?limit=N` for the latest events, oldest first,
+/// and `GET /events/stream` for live events as SSE or over a WebSocket, narrowed
+/// with `?types=a,b` and `?tab=N` and preceded by `?backlog=N` recent ones.
+pub fn control_router(browser: BrowserHandle) -> Router {
+    Router::new()
+        .route("/tabs", get(list_tabs).post(create_tab))
+        .route("/tabs/:id", delete(close_tab))
+        .route("/tabs/:id/activate", post(switch_tab))
+        .route("/navigate", post(navigate))
+        .route("/page", get(current_page))
+        .route("/events", get(recent_events))
+        .route("/events/stream", get(stream::stream_events))
+        .with_state(browser)
+}
+
+type Browser = State<BrowserHandle>;
+
+#[derive(Debug, Deserialize)]
+struct UrlBody {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    limit: Option<usize>,
+}
+
+async fn list_tabs(State(browser): Browser) -> Json<Vec<TabInfo>> {
+    Json(browser.tabs())
+}
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3003));
+async fn create_tab(State(browser): Browser, Json(body): Json<UrlBody>) -> Result<Json<CommandResult>, ApiError> {
+    execute(&browser, BrowserCommand::CreateTab { url: body.url }).await.map(Json)
+}
+
+async fn close_tab(State(browser): Browser, Path(id): Path<usize>) -> Result<Json<CommandResult>, ApiError> {
+    execute(&browser, BrowserCommand::CloseTab { id }).await.map(Json)
+}
+
+async fn switch_tab(State(browser): Browser, Path(id): Path<usize>) -> Result<Json<CommandResult>, ApiError> {
+    execute(&browser, BrowserCommand::SwitchTab { id }).await.map(Json)
+}
+
+async fn navigate(State(browser): Browser, Json(body): Json<UrlBody>) -> Result<Json<CommandResult>, ApiError> {
+    execute(&browser, BrowserCommand::Navigate { url: body.url }).await.map(Json)
+}
+
+async fn current_page(State(browser): Browser) -> Result<Json<TabInfo>, ApiError> {
+    browser.active_tab()
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, Json(CommandError::new("tab_not_found", "No tab is open"))))
+}
+
+async fn recent_events(State(browser): Browser, Query(query): Query<EventsQuery>) -> Json<Vec<EventEnvelope>> {
+    Json(browser.recent_events(query.limit.unwrap_or(DEFAULT_EVENT_LIMIT)))
+}
+
+/// Serve `app` on `addr` until the process exits
+pub async fn serve(addr: SocketAddr, app: Router) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     info!("API server listening on {}", addr);
 
     axum::Server::bind(&addr)
@@ -22,6 +116,118 @@ pub async fn start_api_server() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Replay controls; each answers with the player's status
+///
+/// `GET /replay`, `POST /replay/{start,stop,pause,resume,step}`,
+/// `POST /replay/seek` with `{"to": {"index": 12}}`, `POST /replay/speed` with
+/// `{"speed": 2.0}`, and `POST`/`DELETE /replay/breakpoints`.
+pub fn replay_router(browser: BrowserHandle) -> Router {
+    Router::new()
+        .route("/replay", get(replay_status))
+        .route("/replay/start", post(replay_start))
+        .route("/replay/stop", post(replay_stop))
+        .route("/replay/pause", post(replay_pause))
+        .route("/replay/resume", post(replay_resume))
+        .route("/replay/step", post(replay_step))
+        .route("/replay/seek", post(replay_seek))
+        .route("/replay/speed", post(replay_speed))
+        .route("/replay/breakpoints", post(add_breakpoint).delete(clear_breakpoints))
+        .with_state(browser)
+}
+
+#[derive(Debug, Deserialize)]
+struct SeekBody {
+    to: SeekTarget,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpeedBody {
+    speed: f32,
+}
+
+async fn replay_status(State(browser): Browser) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::Status).await
+}
+
+async fn replay_start(State(browser): Browser) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::Start).await
+}
+
+async fn replay_stop(State(browser): Browser) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::Stop).await
+}
+
+async fn replay_pause(State(browser): Browser) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::Pause).await
+}
+
+async fn replay_resume(State(browser): Browser) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::Resume).await
+}
+
+async fn replay_step(State(browser): Browser) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::Step).await
+}
+
+async fn replay_seek(State(browser): Browser, Json(body): Json<SeekBody>) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::Seek { to: body.to }).await
+}
+
+async fn replay_speed(State(browser): Browser, Json(body): Json<SpeedBody>) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::SetSpeed { speed: body.speed }).await
+}
+
+async fn add_breakpoint(State(browser): Browser, Json(breakpoint): Json<Breakpoint>) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::AddBreakpoint { breakpoint }).await
+}
+
+async fn clear_breakpoints(State(browser): Browser) -> Result<Json<CommandResult>, ApiError> {
+    replay(browser, ReplayControl::ClearBreakpoints).await
+}
+
+async fn replay(browser: BrowserHandle, control: ReplayControl) -> Result<Json<CommandResult>, ApiError> {
+    execute(&browser, BrowserCommand::Replay(control)).await.map(Json)
+}
+
+/// Check a command against the engine's command policy, queue it and wait for its outcome
+pub async fn execute(browser: &BrowserHandle, command: BrowserCommand) -> Result<CommandResult, ApiError> {
+    if let Err(rejection) = browser.authorize(&command) {
+        let error = CommandError::from(&rejection);
+        let command = serde_json::to_string(&command).unwrap_or_else(|_| command.name().to_string());
+        browser.report(acl::rejected_event(&command, None, rejection));
+        return Err((StatusCode::FORBIDDEN, Json(error)));
+    }
+
+    let (pending, outcome) = PendingCommand::with_responder(command);
+    browser.command_sender().send(pending).map_err(|_| {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(CommandError::new("unavailable", "Browser engine is not running")))
+    })?;
+
+    let timeout = Duration::from_millis(DEFAULT_COMMAND_TIMEOUT_MS);
+    let outcome = tokio::task::spawn_blocking(move || outcome.recv_timeout(timeout))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(CommandError::new("internal", e.to_string()))))?;
+    match outcome {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(error)) if error.kind == "tab_not_found" => Err((StatusCode::NOT_FOUND, Json(error))),
+        Ok(Err(error)) => Err((StatusCode::UNPROCESSABLE_ENTITY, Json(error))),
+        Err(RecvTimeoutError::Timeout) => Err((
+            StatusCode::GATEWAY_TIMEOUT,
+            Json(CommandError::new("timeout", format!("Command did not complete within {} ms", timeout.as_millis()))),
+        )),
+        Err(RecvTimeoutError::Disconnected) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(CommandError::new("dropped", "Browser engine dropped the command without a result")),
+        )),
+    }
+}
+
+/// Serve `app` for `browser` on 127.0.0.1:`port` until the process exits
+pub async fn start_api_server(port: u16, browser: BrowserHandle) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    serve(addr, app(browser)).await
+}
+
 async fn health_check() -> Json<serde_json::Value> {
     Json(serde_json::json!({
         "status": "ok",
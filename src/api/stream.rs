@@ -0,0 +1,177 @@
+//! Live event feed at `/events/stream`, for watching Tinker without an MQTT broker.
+//!
+//! Plain requests get Server-Sent Events, named after the event type and with
+//! the sequence number as their id; WebSocket upgrades get one envelope per
+//! text frame. Both carry the envelopes the event viewer keeps.
+
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Query, State},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
+};
+use futures_util::stream::{self, StreamExt};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+use crate::browser::BrowserHandle;
+use crate::event::EventEnvelope;
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct StreamQuery {
+    /// Comma-separated event types, e.g. `navigation,tab_created`; every type when absent
+    types: Option<String>,
+    /// Only events of this tab
+    tab: Option<usize>,
+    /// Send up to this many of the latest matching events before the live ones
+    backlog: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Filter {
+    types: Vec<String>,
+    tab: Option<usize>,
+}
+
+impl Filter {
+    fn new(query: &StreamQuery) -> Self {
+        let types = query.types.as_deref()
+            .map(|types| types.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+            .unwrap_or_default();
+        Self { types, tab: query.tab }
+    }
+
+    fn matches(&self, envelope: &EventEnvelope) -> bool {
+        let type_matches = self.types.is_empty() || self.types.contains(&envelope.event_type);
+        let tab_matches = self.tab.is_none() || envelope.tab_id == self.tab;
+        type_matches && tab_matches
+    }
+}
+
+/// Matching events as they happen
+struct Feed {
+    live: Option<broadcast::Receiver<EventEnvelope>>,
+    filter: Filter,
+    /// Last event of the backlog; anything up to it has been sent already
+    sent_until: Option<u64>,
+}
+
+impl Feed {
+    /// The next matching event, or `None` once no more can come
+    async fn next(&mut self) -> Option<EventEnvelope> {
+        let live = self.live.as_mut()?;
+        loop {
+            match live.recv().await {
+                Ok(envelope) => {
+                    let sent = matches!(self.sent_until, Some(until) if envelope.sequence <= until);
+                    if !sent && self.filter.matches(&envelope) {
+                        return Some(envelope);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Event stream client fell behind and missed {} events", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// `GET /events/stream?types=...&tab=N&backlog=N`, as SSE or a WebSocket
+pub(super) async fn stream_events(
+    ws: Option<WebSocketUpgrade>,
+    State(browser): State<BrowserHandle>,
+    Query(query): Query<StreamQuery>,
+) -> Response {
+    let filter = Filter::new(&query);
+    // Subscribe before reading the backlog so nothing falls between the two
+    let live = browser.subscribe();
+    let count = query.backlog.unwrap_or(0);
+    let mut backlog: Vec<EventEnvelope> = if count > 0 {
+        browser.recent_events(usize::MAX).into_iter().filter(|envelope| filter.matches(envelope)).collect()
+    } else {
+        Vec::new()
+    };
+    backlog.drain(..backlog.len().saturating_sub(count));
+    let feed = Feed {
+        live,
+        filter,
+        sent_until: backlog.last().map(|envelope| envelope.sequence),
+    };
+
+    match ws {
+        Some(ws) => ws.on_upgrade(move |socket| serve_socket(socket, backlog, feed)),
+        None => {
+            let live = stream::unfold(feed, |mut feed| async move {
+                feed.next().await.map(|envelope| (envelope, feed))
+            });
+            let events = stream::iter(backlog).chain(live).map(|envelope| {
+                Event::default()
+                    .event(&envelope.event_type)
+                    .id(envelope.sequence.to_string())
+                    .json_data(&envelope)
+            });
+            Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, envelope: &EventEnvelope) -> bool {
+    match serde_json::to_string(envelope) {
+        Ok(text) => socket.send(Message::Text(text)).await.is_ok(),
+        Err(e) => {
+            warn!("Failed to serialize event {}: {}", envelope.sequence, e);
+            true
+        }
+    }
+}
+
+async fn serve_socket(mut socket: WebSocket, backlog: Vec<EventEnvelope>, mut feed: Feed) {
+    debug!("Event stream client connected (filter: {:?})", feed.filter);
+    for envelope in &backlog {
+        if !send(&mut socket, envelope).await {
+            return;
+        }
+    }
+    loop {
+        let envelope = tokio::select! {
+            envelope = feed.next() => match envelope {
+                Some(envelope) => envelope,
+                None => break,
+            },
+            // Nothing is read from clients; this only notices them leaving
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if !send(&mut socket, &envelope).await {
+            break;
+        }
+    }
+    debug!("Event stream client disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{BrowserEvent, EventSequencer};
+
+    #[test]
+    fn test_filter_by_type_and_tab() {
+        let query = StreamQuery {
+            types: Some("tab_created, navigation".to_string()),
+            tab: Some(1),
+            ..StreamQuery::default()
+        };
+        let filter = Filter::new(&query);
+        assert_eq!(filter.types, vec!["tab_created", "navigation"]);
+
+        let sequencer = EventSequencer::default();
+        let created = sequencer.stamp(BrowserEvent::TabCreated { id: 1, url: "https://example.com".to_string() });
+        let other_tab = sequencer.stamp(BrowserEvent::TabCreated { id: 2, url: "https://example.org".to_string() });
+        let closed = sequencer.stamp(BrowserEvent::TabClosed { id: 1 });
+        assert!(filter.matches(&created));
+        assert!(!filter.matches(&other_tab));
+        assert!(!filter.matches(&closed));
+        assert!(Filter::default().matches(&closed));
+    }
+}
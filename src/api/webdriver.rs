@@ -0,0 +1,604 @@
+//! W3C WebDriver endpoints, enough for Selenium-style clients to drive Tinker.
+//!
+//! One session runs at a time and window handles are tab ids. Work inside the
+//! page goes through `webdriver.js`, sent ahead of every call; page loads and
+//! screenshots finish later and are polled for. `execute/sync` does not wait
+//! for promises.
+//!
+//! Screenshots are not pixels from the screen: `webdriver.js` re-renders the
+//! page's DOM into an image, so they can differ from what the web view shows.
+//! Cross-origin images and styles, canvases, video and native form controls
+//! may be missing or drawn differently.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+use crate::browser::BrowserHandle;
+use crate::event::{BrowserCommand, CommandError, CommandResult, TabInfo};
+
+const SCRIPT: &str = include_str!("../templates/webdriver.js");
+
+/// How often a page load or screenshot is checked on
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+const LOCATOR_STRATEGIES: [&str; 5] = ["css selector", "link text", "partial link text", "tag name", "xpath"];
+
+/// WebDriver routes, to merge into the API router
+pub fn router(browser: BrowserHandle) -> Router {
+    let state = WebDriver {
+        browser,
+        session: Arc::new(Mutex::new(None)),
+    };
+    Router::new()
+        .route("/status", get(status))
+        .route("/session", post(new_session))
+        .route("/session/:session", axum::routing::delete(delete_session))
+        .route("/session/:session/timeouts", get(get_timeouts).post(set_timeouts))
+        .route("/session/:session/url", get(current_url).post(navigate_to))
+        .route("/session/:session/title", get(title))
+        .route("/session/:session/back", post(back))
+        .route("/session/:session/forward", post(forward))
+        .route("/session/:session/refresh", post(refresh))
+        .route("/session/:session/window", get(window_handle).post(switch_to_window).delete(close_window))
+        .route("/session/:session/window/handles", get(window_handles))
+        .route("/session/:session/window/new", post(new_window))
+        .route("/session/:session/element", post(find_element))
+        .route("/session/:session/elements", post(find_elements))
+        .route("/session/:session/element/:element/element", post(find_element_from_element))
+        .route("/session/:session/element/:element/elements", post(find_elements_from_element))
+        .route("/session/:session/element/:element/click", post(element_click))
+        .route("/session/:session/element/:element/value", post(element_send_keys))
+        .route("/session/:session/element/:element/text", get(element_text))
+        .route("/session/:session/execute/sync", post(execute_script))
+        .route("/session/:session/screenshot", get(take_screenshot))
+        .with_state(state)
+}
+
+/// A failed command, answered as `{"value": {"error", "message", "stacktrace"}}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebDriverError {
+    /// Error code from the specification, e.g. `no such element`
+    pub error: String,
+    pub message: String,
+}
+
+impl WebDriverError {
+    pub fn new(error: &str, message: impl Into<String>) -> Self {
+        Self {
+            error: error.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self.error.as_str() {
+            "element click intercepted" | "element not interactable" | "invalid argument" | "invalid selector" => {
+                StatusCode::BAD_REQUEST
+            }
+            "invalid session id" | "no such element" | "no such window" | "stale element reference" => {
+                StatusCode::NOT_FOUND
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<CommandError> for WebDriverError {
+    fn from(error: CommandError) -> Self {
+        let code = match error.kind.as_str() {
+            "tab_not_found" => "no such window",
+            "timeout" => "timeout",
+            "javascript" => "javascript error",
+            "forbidden" => "unsupported operation",
+            _ => "unknown error",
+        };
+        Self::new(code, error.message)
+    }
+}
+
+impl IntoResponse for WebDriverError {
+    fn into_response(self) -> Response {
+        let body = json!({ "value": { "error": self.error, "message": self.message, "stacktrace": "" } });
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+type Reply = Result<Json<Value>, WebDriverError>;
+
+fn reply(value: Value) -> Reply {
+    Ok(Json(json!({ "value": value })))
+}
+
+/// Session timeouts, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Timeouts {
+    pub script: u64,
+    #[serde(rename = "pageLoad")]
+    pub page_load: u64,
+    pub implicit: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            script: 30_000,
+            page_load: 300_000,
+            implicit: 0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TimeoutsBody {
+    script: Option<u64>,
+    #[serde(rename = "pageLoad")]
+    page_load: Option<u64>,
+    implicit: Option<u64>,
+}
+
+impl Timeouts {
+    fn update(&mut self, body: TimeoutsBody) {
+        self.script = body.script.unwrap_or(self.script);
+        self.page_load = body.page_load.unwrap_or(self.page_load);
+        self.implicit = body.implicit.unwrap_or(self.implicit);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    id: String,
+    timeouts: Timeouts,
+}
+
+#[derive(Clone)]
+struct WebDriver {
+    browser: BrowserHandle,
+    session: Arc<Mutex<Option<Session>>>,
+}
+
+impl WebDriver {
+    fn session(&self, id: &str) -> Result<Session, WebDriverError> {
+        self.session.lock().ok()
+            .and_then(|session| session.clone())
+            .filter(|session| session.id == id)
+            .ok_or_else(|| WebDriverError::new("invalid session id", format!("No active session {}", id)))
+    }
+
+    fn active_tab(&self) -> Result<TabInfo, WebDriverError> {
+        self.browser.active_tab().ok_or_else(|| WebDriverError::new("no such window", "No tab is open"))
+    }
+
+    async fn command(&self, request: BrowserCommand) -> Result<CommandResult, WebDriverError> {
+        command(&self.browser, request).await
+    }
+
+    /// Run one of the `webdriver.js` commands in the active tab
+    async fn call(&self, name: &str, args: Value) -> Result<Value, WebDriverError> {
+        call(&self.browser, name, args).await
+    }
+
+    async fn poll(&self, name: &str, args: Value, timeout: Duration) -> Result<Value, WebDriverError> {
+        poll(&self.browser, name, args, timeout).await
+    }
+
+    /// Run a navigation command and wait for the page it leads to
+    async fn navigate(&self, session: &Session, command: BrowserCommand) -> Result<(), WebDriverError> {
+        // Without a page to run scripts in there is nothing to wait for
+        let marked = self.call("markNavigating", json!([])).await.is_ok();
+        self.command(command).await?;
+        if marked {
+            self.poll("ready", json!([]), Duration::from_millis(session.timeouts.page_load)).await?;
+        }
+        Ok(())
+    }
+
+    /// Find elements, retrying for the session's implicit wait while there are none
+    async fn find(&self, session: &Session, locator: Locator, root: Option<String>, multiple: bool) -> Reply {
+        locator.validate()?;
+        let args = json!([locator.using, locator.value, root, multiple]);
+        let deadline = Instant::now() + Duration::from_millis(session.timeouts.implicit);
+        loop {
+            let found = self.call("find", args.clone()).await;
+            if !nothing_found(&found) || Instant::now() >= deadline {
+                return reply(found?);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// A page property, or the tab's record of it when there is no page to ask
+    async fn page_property(&self, expression: &str, fallback: impl FnOnce(TabInfo) -> String) -> Reply {
+        let tab = self.active_tab()?;
+        let script = format!("return {};", expression);
+        match self.call("execute", json!([script, []])).await {
+            Ok(Value::String(value)) => reply(json!(value)),
+            _ => reply(json!(fallback(tab))),
+        }
+    }
+}
+
+pub(super) async fn command(browser: &BrowserHandle, command: BrowserCommand) -> Result<CommandResult, WebDriverError> {
+    super::execute(browser, command)
+        .await
+        .map_err(|(_, Json(error))| error.into())
+}
+
+/// Run one of the `webdriver.js` commands in the active tab
+pub(super) async fn call(browser: &BrowserHandle, name: &str, args: Value) -> Result<Value, WebDriverError> {
+    let script = call_script(name, &args);
+    match command(browser, BrowserCommand::EvaluateScript { tab_id: None, script }).await? {
+        CommandResult::ScriptResult { value, .. } => call_result(value),
+        other => Err(WebDriverError::new("unknown error", format!("Unexpected script result {:?}", other))),
+    }
+}
+
+fn call_script(name: &str, args: &Value) -> String {
+    format!("{}\nwindow.__tinkerWebDriver.call({}, {});", SCRIPT, json!(name), args)
+}
+
+/// What a `webdriver.js` call answered: `{"ok": value}` or `{"error": code, "message": text}`
+fn call_result(value: Value) -> Result<Value, WebDriverError> {
+    match value["error"].as_str() {
+        Some(error) => Err(WebDriverError::new(error, value["message"].as_str().unwrap_or_default())),
+        None => Ok(value["ok"].clone()),
+    }
+}
+
+/// Whether a find came up empty, and is worth retrying while the implicit wait lasts
+fn nothing_found(found: &Result<Value, WebDriverError>) -> bool {
+    match found {
+        Ok(Value::Array(elements)) => elements.is_empty(),
+        Err(error) => error.error == "no such element",
+        Ok(_) => false,
+    }
+}
+
+/// Repeat a call until it returns something other than null or false
+pub(super) async fn poll(browser: &BrowserHandle, name: &str, args: Value, timeout: Duration) -> Result<Value, WebDriverError> {
+    let started = Instant::now();
+    loop {
+        let value = call(browser, name, args.clone()).await?;
+        if !value.is_null() && value != Value::Bool(false) {
+            return Ok(value);
+        }
+        if started.elapsed() >= timeout {
+            return Err(WebDriverError::new("timeout", format!("Timed out after {} ms", timeout.as_millis())));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn new_session_id() -> Result<String, WebDriverError> {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| WebDriverError::new("session not created", "No randomness for a session id"))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn platform_name() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "mac",
+        os => os,
+    }
+}
+
+fn handle_id(handle: &str) -> Result<usize, WebDriverError> {
+    handle.parse().map_err(|_| WebDriverError::new("no such window", format!("No window {}", handle)))
+}
+
+#[derive(Debug, Deserialize)]
+struct Locator {
+    using: String,
+    value: String,
+}
+
+impl Locator {
+    fn validate(&self) -> Result<(), WebDriverError> {
+        if !LOCATOR_STRATEGIES.contains(&self.using.as_str()) {
+            return Err(WebDriverError::new("invalid argument", format!("Unknown locator strategy {}", self.using)));
+        }
+        if self.value.is_empty() && self.using != "link text" {
+            return Err(WebDriverError::new("invalid argument", format!("Empty {} locator", self.using)));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UrlBody {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HandleBody {
+    handle: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextBody {
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScriptBody {
+    script: String,
+    #[serde(default)]
+    args: Vec<Value>,
+}
+
+async fn status(State(driver): State<WebDriver>) -> Reply {
+    let busy = driver.session.lock().map(|session| session.is_some()).unwrap_or(true);
+    let message = if busy { "A session is already running" } else { "Ready for a new session" };
+    reply(json!({ "ready": !busy, "message": message }))
+}
+
+/// Capabilities are accepted but not matched; Tinker is what you get
+async fn new_session(State(driver): State<WebDriver>, Json(body): Json<Value>) -> Reply {
+    let mut timeouts = Timeouts::default();
+    if let Ok(requested) = serde_json::from_value(body["capabilities"]["alwaysMatch"]["timeouts"].clone()) {
+        timeouts.update(requested);
+    }
+
+    let mut session = driver.session.lock()
+        .map_err(|_| WebDriverError::new("session not created", "Failed to lock the session"))?;
+    if session.is_some() {
+        return Err(WebDriverError::new("session not created", "Tinker runs one WebDriver session at a time"));
+    }
+    let id = new_session_id()?;
+    *session = Some(Session { id: id.clone(), timeouts });
+    info!("WebDriver session {} started", id);
+
+    reply(json!({
+        "sessionId": id,
+        "capabilities": {
+            "browserName": "tinker",
+            "browserVersion": env!("CARGO_PKG_VERSION"),
+            "platformName": platform_name(),
+            "acceptInsecureCerts": false,
+            "pageLoadStrategy": "normal",
+            "setWindowRect": false,
+            "strictFileInteractability": false,
+            "timeouts": timeouts,
+        },
+    }))
+}
+
+async fn delete_session(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    driver.session(&id)?;
+    if let Ok(mut session) = driver.session.lock() {
+        *session = None;
+    }
+    info!("WebDriver session {} ended", id);
+    reply(Value::Null)
+}
+
+async fn get_timeouts(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    reply(json!(driver.session(&id)?.timeouts))
+}
+
+async fn set_timeouts(State(driver): State<WebDriver>, Path(id): Path<String>, Json(body): Json<TimeoutsBody>) -> Reply {
+    driver.session(&id)?;
+    if let Ok(mut session) = driver.session.lock() {
+        if let Some(session) = session.as_mut() {
+            session.timeouts.update(body);
+        }
+    }
+    reply(Value::Null)
+}
+
+async fn navigate_to(State(driver): State<WebDriver>, Path(id): Path<String>, Json(body): Json<UrlBody>) -> Reply {
+    let session = driver.session(&id)?;
+    url::Url::parse(&body.url).map_err(|e| WebDriverError::new("invalid argument", format!("Invalid URL {}: {}", body.url, e)))?;
+    driver.navigate(&session, BrowserCommand::Navigate { url: body.url }).await?;
+    reply(Value::Null)
+}
+
+async fn current_url(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    driver.session(&id)?;
+    driver.page_property("document.URL", |tab| tab.url).await
+}
+
+async fn title(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    driver.session(&id)?;
+    driver.page_property("document.title", |tab| tab.title).await
+}
+
+async fn back(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    let session = driver.session(&id)?;
+    // Going back from the first page does nothing, as in other browsers
+    if driver.active_tab()?.can_go_back {
+        driver.navigate(&session, BrowserCommand::Back).await?;
+    }
+    reply(Value::Null)
+}
+
+async fn forward(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    let session = driver.session(&id)?;
+    if driver.active_tab()?.can_go_forward {
+        driver.navigate(&session, BrowserCommand::Forward).await?;
+    }
+    reply(Value::Null)
+}
+
+async fn refresh(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    let session = driver.session(&id)?;
+    driver.navigate(&session, BrowserCommand::Reload).await?;
+    reply(Value::Null)
+}
+
+async fn window_handle(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    driver.session(&id)?;
+    reply(json!(driver.active_tab()?.id.to_string()))
+}
+
+async fn window_handles(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    driver.session(&id)?;
+    let handles: Vec<String> = driver.browser.tabs().iter().map(|tab| tab.id.to_string()).collect();
+    reply(json!(handles))
+}
+
+async fn switch_to_window(State(driver): State<WebDriver>, Path(id): Path<String>, Json(body): Json<HandleBody>) -> Reply {
+    driver.session(&id)?;
+    let tab = handle_id(&body.handle)?;
+    driver.command(BrowserCommand::SwitchTab { id: tab }).await?;
+    reply(Value::Null)
+}
+
+/// Close the current window and answer with the handles left
+async fn close_window(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    driver.session(&id)?;
+    let tab = driver.active_tab()?.id;
+    driver.command(BrowserCommand::CloseTab { id: tab }).await?;
+    let handles: Vec<String> = driver.browser.tabs().iter().map(|tab| tab.id.to_string()).collect();
+    reply(json!(handles))
+}
+
+/// Open a blank tab without switching to it
+async fn new_window(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    driver.session(&id)?;
+    let current = driver.browser.active_tab().map(|tab| tab.id);
+    let created = match driver.command(BrowserCommand::CreateTab { url: "about:blank".to_string() }).await? {
+        CommandResult::TabCreated { id, .. } => id,
+        other => return Err(WebDriverError::new("unknown error", format!("Unexpected result {:?}", other))),
+    };
+    if let Some(current) = current {
+        driver.command(BrowserCommand::SwitchTab { id: current }).await?;
+    }
+    reply(json!({ "handle": created.to_string(), "type": "tab" }))
+}
+
+async fn find_element(State(driver): State<WebDriver>, Path(id): Path<String>, Json(locator): Json<Locator>) -> Reply {
+    let session = driver.session(&id)?;
+    driver.find(&session, locator, None, false).await
+}
+
+async fn find_elements(State(driver): State<WebDriver>, Path(id): Path<String>, Json(locator): Json<Locator>) -> Reply {
+    let session = driver.session(&id)?;
+    driver.find(&session, locator, None, true).await
+}
+
+async fn find_element_from_element(
+    State(driver): State<WebDriver>,
+    Path((id, element)): Path<(String, String)>,
+    Json(locator): Json<Locator>,
+) -> Reply {
+    let session = driver.session(&id)?;
+    driver.find(&session, locator, Some(element), false).await
+}
+
+async fn find_elements_from_element(
+    State(driver): State<WebDriver>,
+    Path((id, element)): Path<(String, String)>,
+    Json(locator): Json<Locator>,
+) -> Reply {
+    let session = driver.session(&id)?;
+    driver.find(&session, locator, Some(element), true).await
+}
+
+async fn element_click(State(driver): State<WebDriver>, Path((id, element)): Path<(String, String)>) -> Reply {
+    driver.session(&id)?;
+    reply(driver.call("click", json!([element])).await?)
+}
+
+async fn element_send_keys(
+    State(driver): State<WebDriver>,
+    Path((id, element)): Path<(String, String)>,
+    Json(body): Json<TextBody>,
+) -> Reply {
+    driver.session(&id)?;
+    reply(driver.call("sendKeys", json!([element, body.text])).await?)
+}
+
+async fn element_text(State(driver): State<WebDriver>, Path((id, element)): Path<(String, String)>) -> Reply {
+    driver.session(&id)?;
+    reply(driver.call("text", json!([element])).await?)
+}
+
+async fn execute_script(State(driver): State<WebDriver>, Path(id): Path<String>, Json(body): Json<ScriptBody>) -> Reply {
+    driver.session(&id)?;
+    reply(driver.call("execute", json!([body.script, body.args])).await?)
+}
+
+/// The visible part of the page as a base64 PNG
+async fn take_screenshot(State(driver): State<WebDriver>, Path(id): Path<String>) -> Reply {
+    let session = driver.session(&id)?;
+    let token = driver.call("screenshot", json!([])).await?;
+    let timeout = Duration::from_millis(session.timeouts.script);
+    reply(driver.poll("result", json!([token]), timeout).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_map_to_webdriver_codes() {
+        let error = WebDriverError::from(CommandError::new("tab_not_found", "Tab not found: 3"));
+        assert_eq!(error, WebDriverError::new("no such window", "Tab not found: 3"));
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+        assert_eq!(WebDriverError::from(CommandError::new("window", "boom")).error, "unknown error");
+        assert_eq!(WebDriverError::new("invalid selector", "").status(), StatusCode::BAD_REQUEST);
+    }
+
+    const ELEMENT_KEY: &str = "element-6066-11e4-a52e-4f735466cecf";
+
+    #[test]
+    fn test_locators_are_checked_before_reaching_the_page() {
+        let locator = |using: &str, value: &str| Locator { using: using.to_string(), value: value.to_string() };
+        assert!(locator("css selector", "#login").validate().is_ok());
+        assert!(locator("xpath", "//a").validate().is_ok());
+        // A link can have no text
+        assert!(locator("link text", "").validate().is_ok());
+
+        let unknown = locator("id", "login").validate().unwrap_err();
+        assert_eq!((unknown.error.as_str(), unknown.status()), ("invalid argument", StatusCode::BAD_REQUEST));
+        assert_eq!(locator("tag name", "").validate().unwrap_err().error, "invalid argument");
+        assert!(serde_json::from_value::<Locator>(json!({ "using": "css selector" })).is_err());
+    }
+
+    #[test]
+    fn test_call_results_map_to_values_and_errors() {
+        let element = json!([{ ELEMENT_KEY: "e1" }]);
+        assert_eq!(call_result(json!({ "ok": element })), Ok(element.clone()));
+        assert_eq!(call_result(json!({ "ok": null })), Ok(Value::Null));
+
+        let stale = call_result(json!({ "error": "stale element reference", "message": "e1 is gone" })).unwrap_err();
+        assert_eq!(stale, WebDriverError::new("stale element reference", "e1 is gone"));
+        assert_eq!(stale.status(), StatusCode::NOT_FOUND);
+        let thrown = call_result(json!({ "error": "javascript error" })).unwrap_err();
+        assert_eq!((thrown.message.as_str(), thrown.status()), ("", StatusCode::INTERNAL_SERVER_ERROR));
+
+        // Only an empty answer or a missing element is retried
+        assert!(nothing_found(&Ok(json!([]))));
+        assert!(nothing_found(&Err(WebDriverError::new("no such element", ""))));
+        assert!(!nothing_found(&Ok(element)));
+        assert!(!nothing_found(&Ok(json!({ ELEMENT_KEY: "e1" }))));
+        assert!(!nothing_found(&Err(WebDriverError::new("invalid selector", ""))));
+    }
+
+    #[test]
+    fn test_calls_carry_the_driver_script() {
+        let script = call_script("click", &json!(["e\"1"]));
+        assert!(script.starts_with(SCRIPT));
+        assert!(script.ends_with(r#"window.__tinkerWebDriver.call("click", ["e\"1"]);"#));
+    }
+
+    #[test]
+    fn test_timeouts_update_only_what_is_given() {
+        let mut timeouts = Timeouts::default();
+        timeouts.update(serde_json::from_str(r#"{"implicit": 500}"#).unwrap());
+        assert_eq!(timeouts, Timeouts { implicit: 500, ..Timeouts::default() });
+        assert_eq!(serde_json::to_value(timeouts).unwrap()["pageLoad"], 300_000);
+    }
+}
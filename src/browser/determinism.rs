@@ -0,0 +1,189 @@
+//! Deterministic pages for reproducible replays.
+//!
+//! With determinism on, every page in the content view runs
+//! `deterministic.js`, which replaces `Date`, `performance.now`,
+//! `Math.random`, the timer functions and `requestAnimationFrame`. Randomness
+//! comes from the seed in the recording header. Time is `epoch_ms` plus a
+//! virtual clock that runs on the recording's timeline: the recorder's while
+//! recording, and during a replay the same `VirtualClock` the player paces
+//! events by, at the replay's speed and stopped while paused. The engine
+//! hands the page a `ClockReading` with every event and the page runs on from
+//! it, so a page reads the same values at the same point of both runs and
+//! code waiting on the clock still gets there. Timers fire in the order of
+//! their virtual due times. A newly loaded page starts its clock at zero,
+//! running at real speed, until the next event.
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+const SCRIPT: &str = include_str!("../templates/deterministic.js");
+
+/// Seed and clock start shared by a recording and its replays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Determinism {
+    pub seed: u64,
+    /// What `Date.now()` returns at offset zero, in milliseconds since the Unix epoch
+    pub epoch_ms: i64,
+}
+
+impl Determinism {
+    /// A fresh seed, unless one is given, with the clock starting now
+    pub fn new(seed: Option<u64>) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self {
+            seed: seed.unwrap_or(now.as_nanos() as u64),
+            epoch_ms: now.as_millis() as i64,
+        }
+    }
+
+    /// Settings for a run: the recording's, with `seed` overriding its seed when given
+    pub fn resolve(recorded: Option<Self>, seed: Option<u64>) -> Self {
+        match (recorded, seed) {
+            (Some(recorded), None) => recorded,
+            (Some(recorded), Some(seed)) => Self { seed, ..recorded },
+            (None, seed) => Self::new(seed),
+        }
+    }
+
+    /// Initialization script for every page
+    pub fn init_script(&self) -> String {
+        SCRIPT
+            .replace("__TINKER_RANDOM_STATE__", &format!("{:?}", self.random_state()))
+            .replace("__TINKER_EPOCH_MS__", &self.epoch_ms.to_string())
+    }
+
+    /// xoshiro128** state expanded from the seed with splitmix64, as the generator's authors recommend
+    fn random_state(&self) -> [u32; 4] {
+        let mut x = self.seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        let (a, b) = (next(), next());
+        [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32]
+    }
+}
+
+/// Where a page's clock is and how fast it runs from there, relative to real time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockReading {
+    pub offset_ms: u64,
+    /// Zero while the timeline is stopped
+    pub rate: f64,
+}
+
+/// A timeline in milliseconds that runs at `rate` times real time while running
+#[derive(Debug, Clone, Copy)]
+pub struct VirtualClock {
+    /// Position when the clock was last stopped or re-based
+    base_ms: f64,
+    /// When the clock last started running; `None` while stopped
+    since: Option<Instant>,
+    rate: f64,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl VirtualClock {
+    /// A stopped clock at zero
+    pub fn new(rate: f64) -> Self {
+        Self { base_ms: 0.0, since: None, rate }
+    }
+
+    pub fn now_ms(&self) -> f64 {
+        let running = self.since.map_or(0.0, |since| since.elapsed().as_secs_f64() * 1000.0 * self.rate);
+        self.base_ms + running
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.since.is_some()
+    }
+
+    pub fn run(&mut self) {
+        if self.since.is_none() {
+            self.since = Some(Instant::now());
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.base_ms = self.now_ms();
+        self.since = None;
+    }
+
+    /// Jump to `ms`, running on from there if the clock was running
+    pub fn set_ms(&mut self, ms: f64) {
+        self.base_ms = ms;
+        if self.since.is_some() {
+            self.since = Some(Instant::now());
+        }
+    }
+
+    /// Change speed from now on
+    pub fn set_rate(&mut self, rate: f64) {
+        self.set_ms(self.now_ms());
+        self.rate = rate;
+    }
+
+    /// What a page needs to run its clock alongside this one
+    pub fn reading(&self) -> ClockReading {
+        ClockReading {
+            offset_ms: self.now_ms() as u64,
+            rate: if self.is_running() { self.rate } else { 0.0 },
+        }
+    }
+}
+
+/// Script setting a deterministic page's clock to `reading`
+pub fn clock_script(reading: ClockReading) -> String {
+    format!("window.__tinkerClock && window.__tinkerClock.set({}, {});", reading.offset_ms, reading.rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_prefers_the_recording() {
+        let recorded = Determinism { seed: 7, epoch_ms: 1_700_000_000_000 };
+        assert_eq!(Determinism::resolve(Some(recorded), None), recorded);
+        assert_eq!(Determinism::resolve(Some(recorded), Some(9)).seed, 9);
+        assert_eq!(Determinism::resolve(Some(recorded), Some(9)).epoch_ms, recorded.epoch_ms);
+        assert_eq!(Determinism::resolve(None, Some(9)).seed, 9);
+    }
+
+    #[test]
+    fn test_init_script_is_filled_in() {
+        let determinism = Determinism { seed: 42, epoch_ms: 1_700_000_000_000 };
+        let script = determinism.init_script();
+        assert!(!script.contains("__TINKER_"));
+        assert!(script.contains("const EPOCH_MS = 1700000000000;"));
+        // The same seed always yields the same generator
+        assert_eq!(script, Determinism { seed: 42, ..determinism }.init_script());
+        assert_ne!(determinism.random_state(), Determinism { seed: 43, ..determinism }.random_state());
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut clock = VirtualClock::new(2.0);
+        assert_eq!(clock.reading(), ClockReading { offset_ms: 0, rate: 0.0 });
+        clock.run();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(clock.now_ms() >= 40.0);
+        assert_eq!(clock.reading().rate, 2.0);
+
+        clock.stop();
+        let stopped = clock.now_ms();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(clock.now_ms(), stopped);
+        clock.set_ms(1_000.0);
+        assert_eq!(clock.reading(), ClockReading { offset_ms: 1_000, rate: 0.0 });
+        assert_eq!(clock_script(clock.reading()), "window.__tinkerClock && window.__tinkerClock.set(1000, 0);");
+    }
+}
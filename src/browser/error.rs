@@ -1,6 +1,7 @@
-use std::fmt;
 use thiserror::Error;
 use crate::platform::PlatformError;
+use crate::event::CommandError;
+use super::WebViewError;
 
 #[derive(Debug, Error)]
 pub enum BrowserError {
@@ -22,6 +23,9 @@ pub enum BrowserError {
     #[error("Platform error: {0}")]
     Platform(#[from] PlatformError),
 
+    #[error("Recording error: {0}")]
+    Recording(String),
+
     #[error("Other error: {0}")]
     Other(String),
 }
@@ -107,6 +111,42 @@ pub enum JavaScriptError {
 // Result type alias for browser operations
 pub type BrowserResult<T> = Result<T, BrowserError>;
 
+impl BrowserError {
+    /// Stable, machine-readable name of the error category
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BrowserError::Window(_) => "window",
+            BrowserError::Tab(TabError::NotFound { .. }) => "tab_not_found",
+            BrowserError::Tab(_) => "tab",
+            BrowserError::Navigation(_) => "navigation",
+            BrowserError::State(_) => "state",
+            BrowserError::JavaScript(_) => "javascript",
+            BrowserError::Platform(_) => "platform",
+            BrowserError::Recording(_) => "recording",
+            BrowserError::Other(_) => "other",
+        }
+    }
+}
+
+impl From<&BrowserError> for CommandError {
+    fn from(error: &BrowserError) -> Self {
+        CommandError::new(error.kind(), error.to_string())
+    }
+}
+
+// Map engine-level WebView failures onto the typed hierarchy
+impl From<WebViewError> for BrowserError {
+    fn from(error: WebViewError) -> Self {
+        match error {
+            WebViewError::LockError(msg) => StateError::LockFailed(msg).into(),
+            WebViewError::TabError(msg) => TabError::Update(msg).into(),
+            WebViewError::TabBarError(msg) => WindowError::Update(msg).into(),
+            WebViewError::GenericError(msg) => BrowserError::Other(msg),
+            other => WindowError::Creation(other.to_string()).into(),
+        }
+    }
+}
+
 // Implement conversion from string errors
 impl From<String> for BrowserError {
     fn from(error: String) -> Self {
@@ -144,6 +184,14 @@ mod tests {
         assert!(matches!(browser_err, BrowserError::Tab(_)));
     }
 
+    #[test]
+    fn test_command_error_kind() {
+        let err: BrowserError = TabError::NotFound { id: 7 }.into();
+        let command_error = CommandError::from(&err);
+        assert_eq!(command_error.kind, "tab_not_found");
+        assert_eq!(command_error.message, "Tab error: Tab not found: 7");
+    }
+
     #[test]
     fn test_navigation_error() {
         let err = NavigationError::InvalidUrl("invalid url".to_string());
@@ -1,19 +1,28 @@
 use std::collections::VecDeque;
 use chrono::{DateTime, Local};
-use crate::event::BrowserEvent;
+use tokio::sync::broadcast;
+use crate::event::EventEnvelope;
 
 const MAX_EVENTS: usize = 1000;
+/// Events a live subscriber may fall behind by before it misses some
+const LIVE_CAPACITY: usize = 256;
 
 #[derive(Debug)]
 pub struct EventEntry {
     pub timestamp: DateTime<Local>,
-    pub event: BrowserEvent,
+    pub envelope: EventEnvelope,
 }
 
-#[derive(Default)]
 pub struct EventViewer {
     events: VecDeque<EventEntry>,
     max_events: usize,
+    live: broadcast::Sender<EventEnvelope>,
+}
+
+impl Default for EventViewer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl EventViewer {
@@ -21,18 +30,30 @@ impl EventViewer {
         EventViewer {
             events: VecDeque::with_capacity(MAX_EVENTS),
             max_events: MAX_EVENTS,
+            live: broadcast::channel(LIVE_CAPACITY).0,
         }
     }
 
-    pub fn add_event(&mut self, event: BrowserEvent) {
+    /// Every event added from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.live.subscribe()
+    }
+
+    pub fn add_event(&mut self, envelope: EventEnvelope) {
+        // Show the time the event was stamped, not when the viewer saw it
+        let timestamp = DateTime::parse_from_rfc3339(&envelope.timestamp)
+            .map(|timestamp| timestamp.with_timezone(&Local))
+            .unwrap_or_else(|_| Local::now());
         let entry = EventEntry {
-            timestamp: Local::now(),
-            event,
+            timestamp,
+            envelope,
         };
 
         if self.events.len() >= MAX_EVENTS {
             self.events.pop_front();
         }
+        // Nobody listening is not an error
+        let _ = self.live.send(entry.envelope.clone());
         self.events.push_back(entry);
     }
 
@@ -52,6 +73,7 @@ impl EventViewer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::event::{BrowserEvent, EventSequencer};
 
     #[test]
     fn test_event_addition() {
@@ -59,18 +81,31 @@ mod tests {
         let event = BrowserEvent::Navigation {
             url: "https://example.com".to_string(),
         };
-        viewer.add_event(event);
+        viewer.add_event(EventSequencer::default().stamp(event));
         assert_eq!(viewer.events.len(), 1);
+        assert_eq!(viewer.events[0].envelope.sequence, 1);
     }
 
     #[test]
     fn test_max_events() {
         let mut viewer = EventViewer::new();
+        let sequencer = EventSequencer::default();
         for i in 0..MAX_EVENTS + 10 {
-            viewer.add_event(BrowserEvent::Navigation {
+            viewer.add_event(sequencer.stamp(BrowserEvent::Navigation {
                 url: format!("https://example{}.com", i),
-            });
+            }));
         }
         assert_eq!(viewer.events.len(), MAX_EVENTS);
     }
-} 
+
+    #[test]
+    fn test_subscribers_see_new_events() {
+        let mut viewer = EventViewer::new();
+        let sequencer = EventSequencer::default();
+        viewer.add_event(sequencer.stamp(BrowserEvent::Navigation { url: "https://example.com".to_string() }));
+        let mut live = viewer.subscribe();
+        viewer.add_event(sequencer.stamp(BrowserEvent::PageLoaded { url: "https://example.com".to_string() }));
+        assert_eq!(live.try_recv().unwrap().sequence, 2);
+        assert!(live.try_recv().is_err());
+    }
+}
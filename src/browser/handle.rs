@@ -0,0 +1,96 @@
+//! Access to a running engine from other threads, such as the HTTP API.
+//!
+//! Reads come straight from the engine's shared state, so they are answered
+//! even while the event loop is busy. Anything that changes the browser is
+//! queued as a command and runs on the event loop like commands from MQTT,
+//! once the engine's command policy allows it.
+
+use std::sync::{mpsc::Sender, Arc, Mutex};
+use tokio::sync::broadcast;
+use crate::event::{BrowserCommand, BrowserEvent, CommandPolicy, CommandRejection, EventEnvelope, PendingCommand, TabInfo};
+use super::event_viewer::EventViewer;
+use super::tabs::TabManager;
+
+#[derive(Clone)]
+pub struct BrowserHandle {
+    commands: Sender<PendingCommand>,
+    reports: Sender<BrowserEvent>,
+    policy: Arc<Mutex<CommandPolicy>>,
+    tabs: Arc<Mutex<TabManager>>,
+    event_viewer: Arc<Mutex<EventViewer>>,
+}
+
+impl BrowserHandle {
+    pub(super) fn new(
+        commands: Sender<PendingCommand>,
+        reports: Sender<BrowserEvent>,
+        policy: Arc<Mutex<CommandPolicy>>,
+        tabs: Arc<Mutex<TabManager>>,
+        event_viewer: Arc<Mutex<EventViewer>>,
+    ) -> Self {
+        Self { commands, reports, policy, tabs, event_viewer }
+    }
+
+    /// Where to queue commands for the engine
+    pub fn command_sender(&self) -> Sender<PendingCommand> {
+        self.commands.clone()
+    }
+
+    /// Check a command against the engine's policy, under its default rules
+    pub fn authorize(&self, command: &BrowserCommand) -> Result<(), CommandRejection> {
+        match self.policy.lock() {
+            Ok(policy) => policy.authorize(None, command),
+            // A poisoned policy must not turn into an open door
+            Err(_) => Err(CommandRejection::CommandNotAllowed { command: command.name().to_string() }),
+        }
+    }
+
+    /// Have the engine publish an event, such as an audit record, on its next pass
+    pub fn report(&self, event: BrowserEvent) {
+        // A stopped engine has nobody left to tell
+        let _ = self.reports.send(event);
+    }
+
+    /// Every open tab, ordered by id
+    pub fn tabs(&self) -> Vec<TabInfo> {
+        self.tabs.lock().map(|tabs| tab_list(&tabs)).unwrap_or_default()
+    }
+
+    pub fn active_tab(&self) -> Option<TabInfo> {
+        self.tabs().into_iter().find(|tab| tab.active)
+    }
+
+    /// Up to `count` of the latest events, oldest first
+    pub fn recent_events(&self, count: usize) -> Vec<EventEnvelope> {
+        let Ok(viewer) = self.event_viewer.lock() else {
+            return Vec::new();
+        };
+        let mut events: Vec<EventEnvelope> = viewer.get_recent_events(count)
+            .into_iter()
+            .map(|entry| entry.envelope.clone())
+            .collect();
+        events.reverse();
+        events
+    }
+
+    /// Events as they happen, or `None` if the event viewer is unavailable
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<EventEnvelope>> {
+        self.event_viewer.lock().ok().map(|viewer| viewer.subscribe())
+    }
+}
+
+pub(super) fn tab_list(tabs: &TabManager) -> Vec<TabInfo> {
+    let mut list: Vec<TabInfo> = tabs.get_all_tabs()
+        .into_iter()
+        .map(|tab| TabInfo {
+            id: tab.id,
+            url: tab.url.clone(),
+            title: tab.title.clone(),
+            active: tabs.is_active_tab(tab.id),
+            can_go_back: tab.can_go_back(),
+            can_go_forward: tab.can_go_forward(),
+        })
+        .collect();
+    list.sort_by_key(|tab| tab.id);
+    list
+}
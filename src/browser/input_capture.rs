@@ -0,0 +1,127 @@
+//! DOM input capture and replay.
+//!
+//! `input_capture.js` runs in every page of the content view and, while a
+//! recording is under way, reports clicks, typing, form submissions, scrolling
+//! and special keys over IPC. The engine drops input messages arriving while
+//! nothing is recording, so typed text is never published otherwise.
+//! Those messages become input `BrowserEvent`s, and replaying one runs
+//! `input_replay.js` in the page to dispatch the same input again.
+
+use serde_json::{json, Value};
+use crate::event::BrowserEvent;
+
+/// Injected into the content view ahead of the window chrome script
+pub const CAPTURE_SCRIPT: &str = include_str!("../templates/input_capture.js");
+
+const REPLAY_SCRIPT: &str = include_str!("../templates/input_replay.js");
+
+/// Script turning the page's input capture on or off
+pub fn enable_script(on: bool) -> String {
+    format!("window.__tinkerCaptureInput && window.__tinkerCaptureInput({});", on)
+}
+
+/// Input event for an IPC message, or `None` when the message is not about input
+pub fn parse_ipc(data: &Value) -> Option<Result<BrowserEvent, String>> {
+    match data["type"].as_str()? {
+        kind @ ("click" | "textInput" | "formSubmitted" | "scroll" | "keyPress") => Some(parse_input(kind, data)),
+        _ => None,
+    }
+}
+
+fn parse_input(kind: &str, data: &Value) -> Result<BrowserEvent, String> {
+    let text = |key: &str| {
+        data[key].as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format!("{} message missing {}", kind, key))
+    };
+    let number = |key: &str| data[key].as_f64().ok_or_else(|| format!("{} message missing {}", kind, key));
+    let optional_text = |key: &str| data[key].as_str().map(str::to_string);
+
+    Ok(match kind {
+        "click" => BrowserEvent::Click {
+            selector: text("selector")?,
+            x: number("x")?,
+            y: number("y")?,
+            button: data["button"].as_u64().unwrap_or(0) as u16,
+        },
+        "textInput" => BrowserEvent::TextInput {
+            selector: text("selector")?,
+            value: text("value")?,
+            redacted: data["redacted"].as_bool().unwrap_or(false),
+        },
+        "formSubmitted" => BrowserEvent::FormSubmitted { selector: text("selector")? },
+        "scroll" => BrowserEvent::Scrolled { selector: optional_text("selector"), x: number("x")?, y: number("y")? },
+        _ => BrowserEvent::KeyPressed { selector: optional_text("selector"), key: text("key")? },
+    })
+}
+
+/// The IPC message the capture script sends for an input event
+fn ipc_message(event: &BrowserEvent) -> Option<Value> {
+    Some(match event {
+        BrowserEvent::Click { selector, x, y, button } => {
+            json!({ "type": "click", "selector": selector, "x": x, "y": y, "button": button })
+        }
+        BrowserEvent::TextInput { selector, value, redacted } => {
+            json!({ "type": "textInput", "selector": selector, "value": value, "redacted": redacted })
+        }
+        BrowserEvent::FormSubmitted { selector } => json!({ "type": "formSubmitted", "selector": selector }),
+        BrowserEvent::Scrolled { selector, x, y } => json!({ "type": "scroll", "selector": selector, "x": x, "y": y }),
+        BrowserEvent::KeyPressed { selector, key } => json!({ "type": "keyPress", "selector": selector, "key": key }),
+        _ => return None,
+    })
+}
+
+/// Script that dispatches the input event into the page, or `None` for other events
+pub fn replay_script(event: &BrowserEvent) -> Option<String> {
+    ipc_message(event).map(|input| format!("{}\ntinkerReplayInput({});", REPLAY_SCRIPT, input))
+}
+
+/// One-line description of an input event, e.g. `click on #submit`
+pub fn describe(event: &BrowserEvent) -> Option<String> {
+    Some(match event {
+        BrowserEvent::Click { selector, .. } => format!("click on {}", selector),
+        BrowserEvent::TextInput { selector, redacted: true, .. } => format!("type a password into {}", selector),
+        BrowserEvent::TextInput { selector, value, .. } => format!("type {:?} into {}", value, selector),
+        BrowserEvent::FormSubmitted { selector } => format!("submit {}", selector),
+        BrowserEvent::Scrolled { selector, x, y } => {
+            format!("scroll {} to {},{}", selector.as_deref().unwrap_or("the page"), x, y)
+        }
+        BrowserEvent::KeyPressed { selector: Some(selector), key } => format!("press {} in {}", key, selector),
+        BrowserEvent::KeyPressed { selector: None, key } => format!("press {}", key),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input_messages() {
+        let click = json!({ "type": "click", "selector": "#go", "x": 10.5, "y": 20, "button": 0 });
+        match parse_ipc(&click) {
+            Some(Ok(BrowserEvent::Click { selector, x, y, button })) => {
+                assert_eq!((selector.as_str(), x, y, button), ("#go", 10.5, 20.0, 0));
+            }
+            other => panic!("unexpected parse: {:?}", other),
+        }
+
+        let scroll = json!({ "type": "scroll", "selector": null, "x": 0, "y": 300 });
+        assert!(matches!(parse_ipc(&scroll), Some(Ok(BrowserEvent::Scrolled { selector: None, .. }))));
+
+        assert!(matches!(parse_ipc(&json!({ "type": "textInput", "selector": "#q" })), Some(Err(_))));
+        assert!(parse_ipc(&json!({ "type": "pageLoaded", "url": "https://example.com" })).is_none());
+    }
+
+    #[test]
+    fn test_replay_script_round_trips_the_message() {
+        let event = BrowserEvent::KeyPressed { selector: None, key: "Control+s".to_string() };
+        let script = replay_script(&event).unwrap();
+        assert!(script.contains("function tinkerReplayInput(input)"));
+        assert!(script.contains(r#""key":"Control+s""#));
+
+        let message = ipc_message(&event).unwrap();
+        assert!(matches!(parse_ipc(&message), Some(Ok(BrowserEvent::KeyPressed { .. }))));
+        assert!(replay_script(&BrowserEvent::TabClosed { id: 1 }).is_none());
+    }
+}
@@ -1,8 +1,10 @@
 //! Browser engine implementation
 
 use std::{
-    sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, mpsc::{self, Sender, Receiver}},
+    time::Duration,
 };
 use tao::{
     event::{Event, WindowEvent, ElementState},
@@ -10,7 +12,7 @@ use tao::{
     window::{WindowBuilder, Window},
     dpi::LogicalSize,
 };
-use wry::{WebView, WebViewBuilder};
+use wry::{ProxyConfig, ProxyEndpoint, WebView, WebViewBuilder};
 use tracing::{debug, info, error};
 
 #[derive(Debug, thiserror::Error)]
@@ -47,20 +49,50 @@ mod tabs;
 mod event_viewer;
 mod tab_ui;
 mod replay;
+mod replay_executor;
+mod replay_verify;
+mod input_capture;
+mod determinism;
+mod handle;
+mod screenshot;
+pub mod recording_editor;
+pub mod test_generator;
 pub mod keyboard;
+pub mod error;
 
 use self::{
     tabs::TabManager,
     event_viewer::EventViewer,
     tab_ui::TabBar,
-    replay::{EventRecorder, EventPlayer},
+    replay::{EventRecorder, EventPlayer, RecordingHeader, WindowSize},
+    replay_executor::{ReplayAction, ReplayOutcome, ReplayReport},
+    error::{BrowserError, BrowserResult, JavaScriptError, NavigationError, StateError, TabError, WindowError},
 };
 
-use crate::event::{BrowserEvent, EventSystem, BrowserCommand};
+pub use self::determinism::Determinism;
+pub use self::handle::BrowserHandle;
+pub use self::replay::Recording;
+pub use self::replay_verify::{VerifyOptions, VerifyReport};
+
+use crate::network::NetworkRecorder;
+use crate::event::{
+    recording, BrowserEvent, EventEnvelope, EventSequencer, EventSystem, BrowserCommand, CommandError, CommandOutcome, CommandPolicy, CommandResult,
+    PendingCommand, Presence, RecordingControl, RecordingStatus, ReplayControl, ReplayStatus, SeekTarget, SharedTransport, TabInfo,
+    FETCH_CHUNK_BYTES, RECORDING_HEADER_TOPIC, RECORDING_RECORD_TOPIC, RECORDING_STATUS_TOPIC,
+};
+
+/// What `BrowserEngine::on_verified` calls with a verification report
+pub type VerifyCallback = Arc<dyn Fn(&VerifyReport) + Send + Sync>;
+
+/// Size of a newly opened browser window
+const DEFAULT_WINDOW_SIZE: WindowSize = WindowSize { width: 800, height: 600 };
+
+/// Directory recording controls write to unless `recordings_dir` is changed
+pub const DEFAULT_RECORDINGS_DIR: &str = "recordings";
 
 pub struct BrowserEngine {
     pub headless: bool,
-    pub events: Option<Arc<Mutex<EventSystem>>>,
+    pub events: Option<SharedTransport>,
     pub player: Arc<Mutex<EventPlayer>>,
     pub recorder: Arc<Mutex<EventRecorder>>,
     pub event_viewer: Arc<Mutex<EventViewer>>,
@@ -69,13 +101,47 @@ pub struct BrowserEngine {
     pub content_view: Option<Arc<Mutex<WebView>>>,
     pub window: Option<Arc<Window>>,
     pub initial_url: Option<String>,
+    /// Seeded randomness and a virtual clock for every page; set before `run`
+    pub determinism: Option<Determinism>,
+    /// Local proxy every page request goes through; set before `run`
+    pub network_proxy: Option<SocketAddr>,
+    /// Archive of the traffic through `network_proxy` while recording
+    pub network_recorder: Option<Arc<Mutex<NetworkRecorder>>>,
+    /// Where recording controls may create files
+    pub recordings_dir: PathBuf,
     pub running: bool,
+    /// Process exit code once the event loop stops, e.g. from a failed replay verification
+    pub exit_code: i32,
+    /// Shown the report once a verified replay has settled, as `run` never returns it; set before `run`
+    pub on_verified: Option<VerifyCallback>,
+    command_tx: Sender<PendingCommand>,
+    command_rx: Arc<Mutex<Receiver<PendingCommand>>>,
+    ipc_tx: Sender<String>,
+    ipc_rx: Arc<Mutex<Receiver<String>>>,
+    reports_tx: Sender<BrowserEvent>,
+    reports_rx: Arc<Mutex<Receiver<BrowserEvent>>>,
+    /// Rules for commands from the API; transports keep their own copy
+    policy: Arc<Mutex<CommandPolicy>>,
+    last_status: Option<(usize, Option<String>)>,
+    sequencer: Arc<EventSequencer>,
 }
 
 impl BrowserEngine {
-    pub fn new(headless: bool, events: Option<Arc<Mutex<EventSystem>>>, initial_url: Option<String>) -> Self {
+    pub fn new(headless: bool, events: Option<SharedTransport>, initial_url: Option<String>) -> Self {
+        // Commands from every source (MQTT, replay) are queued here and run on the event loop
+        let (command_tx, command_rx) = mpsc::channel();
+        // Page messages arrive on the web view's thread and are handled with the commands
+        let (ipc_tx, ipc_rx) = mpsc::channel();
+        // Events from servers running alongside the engine, published on the event loop
+        let (reports_tx, reports_rx) = mpsc::channel();
+        let mut sequencer = Arc::new(EventSequencer::default());
+
         if let Some(ref events) = events {
-            if let Ok(_events) = events.lock() {
+            if let Ok(mut events) = events.lock() {
+                events.set_command_sender(command_tx.clone());
+                events.set_headless(headless);
+                // Share the sequence so MQTT, the recorder and the viewer agree on numbering
+                sequencer = events.sequencer();
                 info!("Browser engine initialized with event system");
             } else {
                 error!("Failed to lock event system during initialization");
@@ -95,41 +161,168 @@ impl BrowserEngine {
             content_view: None,
             window: None,
             initial_url,
+            determinism: None,
+            network_proxy: None,
+            network_recorder: None,
+            recordings_dir: PathBuf::from(DEFAULT_RECORDINGS_DIR),
             running: true,
+            exit_code: 0,
+            on_verified: None,
+            command_tx,
+            command_rx: Arc::new(Mutex::new(command_rx)),
+            ipc_tx,
+            ipc_rx: Arc::new(Mutex::new(ipc_rx)),
+            reports_tx,
+            reports_rx: Arc::new(Mutex::new(reports_rx)),
+            policy: Arc::new(Mutex::new(CommandPolicy::default())),
+            last_status: None,
+            sequencer,
         }
     }
 
+    /// Sender for queueing commands to be executed by the engine
+    pub fn command_sender(&self) -> Sender<PendingCommand> {
+        self.command_tx.clone()
+    }
+
+    /// Apply `policy` to commands from the event transport and the API
+    pub fn set_command_policy(&mut self, policy: CommandPolicy) {
+        if let Some(events) = &self.events {
+            if let Ok(mut events) = events.lock() {
+                events.set_command_policy(policy.clone());
+            }
+        }
+        if let Ok(mut current) = self.policy.lock() {
+            *current = policy;
+        }
+    }
+
+    /// Shared access for servers running alongside the event loop
+    pub fn handle(&self) -> BrowserHandle {
+        BrowserHandle::new(
+            self.command_tx.clone(),
+            self.reports_tx.clone(),
+            self.policy.clone(),
+            self.tabs.clone(),
+            self.event_viewer.clone(),
+        )
+    }
+
+    /// Sender for messages as the content page posts them through `window.ipc.postMessage`
+    pub fn ipc_sender(&self) -> Sender<String> {
+        self.ipc_tx.clone()
+    }
+
     fn publish_event(&self, event: BrowserEvent) -> Result<(), String> {
+        // Stamp once so every consumer sees the same sequence number and timestamps
+        let envelope = self.sequencer.stamp(event);
+
         // First, add to event viewer for monitoring
         if let Ok(mut viewer) = self.event_viewer.lock() {
-            viewer.add_event(event.clone());
+            viewer.add_event(envelope.clone());
         }
 
+        self.record(envelope.clone());
+
+        if let Some(network) = &self.network_recorder {
+            if let Ok(mut network) = network.lock() {
+                network.observe(&envelope.event);
+            }
+        }
+
+        if let Ok(mut player) = self.player.lock() {
+            player.observe(&envelope.event);
+        }
+        self.sync_page_clock();
+
         // Then publish to event system if available
         if let Some(events) = &self.events {
             if let Ok(mut events) = events.lock() {
-                events.publish(event)
+                events.publish_envelope(&envelope)
                     .map_err(|e| format!("Failed to publish event: {}", e))
             } else {
                 Err("Failed to lock event system".to_string())
             }
         } else {
             // In headless mode or when events are disabled, just log
-            debug!("Event published (no event system): {:?}", event);
+            debug!("Event published (no event system): {:?}", envelope);
             Ok(())
         }
     }
 
+    /// Add an event to the recording, publishing it too when the recording is live
+    fn record(&self, envelope: EventEnvelope) {
+        let live = self.recorder.lock().ok().and_then(|mut recorder| recorder.record_event(envelope));
+        if let Some(record) = live {
+            self.publish_recording_message(RECORDING_RECORD_TOPIC, &record);
+        }
+    }
+
+    /// Publish something other than an event, such as a live recording line, under `base`
+    fn publish_recording_message<T: serde::Serialize>(&self, base: &str, message: &T) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        let payload = match serde_json::to_string(message) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize {} message: {}", base, e);
+                return;
+            }
+        };
+        if let Ok(mut events) = events.lock() {
+            let topic = events.topic(base);
+            if let Err(e) = events.publish_message(&topic, &payload) {
+                error!("Failed to publish to {}: {}", topic, e);
+            }
+        }
+    }
+
+    /// Hand deterministic pages the recording or replay timeline, which they run on from
+    fn sync_page_clock(&self) {
+        if self.determinism.is_none() {
+            return;
+        }
+        let replaying = self.player.lock().ok()
+            .filter(|player| player.is_playing())
+            .map(|player| player.page_clock());
+        let clock = replaying.or_else(|| self.recorder.lock().ok().and_then(|recorder| recorder.page_clock()));
+        let (Some(clock), Some(view)) = (clock, &self.content_view) else {
+            return;
+        };
+        if let Ok(view) = view.lock() {
+            if let Err(e) = view.evaluate_script(&determinism::clock_script(clock)) {
+                debug!("Failed to move the page clock: {}", e);
+            }
+        }
+    }
+
+    fn is_recording(&self) -> bool {
+        self.recorder.lock().map(|recorder| recorder.is_recording()).unwrap_or(false)
+    }
+
+    /// Have the page report input only while a recording is under way
+    fn sync_input_capture(&self) {
+        let Some(view) = &self.content_view else {
+            return;
+        };
+        if let Ok(view) = view.lock() {
+            if let Err(e) = view.evaluate_script(&input_capture::enable_script(self.is_recording())) {
+                debug!("Failed to switch input capture: {}", e);
+            }
+        }
+    }
+
     pub fn navigate(&self, url: &str) -> Result<(), String> {
         info!("Navigating to: {}", url);
 
         // Update the tab URL first
         if let Ok(mut tabs) = self.tabs.lock() {
-            if let Some(tab) = tabs.get_active_tab_mut() {
-                tab.url = url.to_string();
+            if let Some(id) = tabs.get_active_tab().map(|tab| tab.id) {
+                tabs.navigate_tab(id, url.to_string());
                 // Publish URL changed event
                 self.publish_event(BrowserEvent::TabUrlChanged {
-                    id: tab.id,
+                    id,
                     url: url.to_string(),
                 })?;
             }
@@ -169,23 +362,164 @@ impl BrowserEngine {
     }
 
     pub fn init_events(&mut self, broker_url: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let mut events = EventSystem::new(broker_url, "tinker-browser");
+        let mut events = EventSystem::new(broker_url, &crate::event::client_id_for(None))?;
+        events.set_command_sender(self.command_tx.clone());
+        events.set_headless(self.headless);
+        events.set_sequencer(self.sequencer.clone());
         events.connect()?;
         self.events = Some(Arc::new(Mutex::new(events)));
         Ok(())
     }
 
-    pub fn start_recording(&mut self, path: &str) {
-        if let Ok(mut recorder) = self.recorder.lock() {
-            recorder.set_save_path(path.to_string());
-            recorder.start();
-            info!("Started recording to {}", path);
+    fn status_snapshot(&self) -> (usize, Option<String>) {
+        if let Ok(tabs) = self.tabs.lock() {
+            (tabs.get_tab_count(), tabs.get_active_tab().map(|tab| tab.url.clone()))
+        } else {
+            (0, None)
         }
     }
 
+    /// Refresh the retained status record when the tab count or active URL changed
+    pub fn publish_status(&mut self) {
+        let snapshot = self.status_snapshot();
+        if self.last_status.as_ref() == Some(&snapshot) {
+            return;
+        }
+
+        if let Some(events) = &self.events {
+            if let Ok(mut events) = events.lock() {
+                let (tab_count, active_url) = snapshot.clone();
+                let status = events.status(Presence::Online, tab_count, active_url);
+                if let Err(e) = events.publish_status(&status) {
+                    error!("Failed to publish status: {}", e);
+                }
+            }
+        }
+        self.last_status = Some(snapshot);
+    }
+
+    /// Tell the broker we are going away, so dashboards do not rely on the last will
+    pub fn disconnect_events(&self) {
+        if let Some(events) = &self.events {
+            if let Ok(mut events) = events.lock() {
+                let (tab_count, active_url) = self.status_snapshot();
+                if let Err(e) = events.disconnect(tab_count, active_url) {
+                    error!("Failed to disconnect from event broker: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Start streaming events to `path`, replacing any file already there
+    pub fn start_recording(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.record_to(Some(path))?;
+        info!("Started recording to {}", path);
+        Ok(())
+    }
+
+    /// Start a new recording, streamed to `path` or kept in memory
+    fn record_to(&mut self, path: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+        let window_size = match &self.window {
+            Some(window) => {
+                let size = window.inner_size();
+                WindowSize { width: size.width, height: size.height }
+            }
+            None => DEFAULT_WINDOW_SIZE,
+        };
+        let header = RecordingHeader {
+            determinism: self.determinism,
+            ..RecordingHeader::new(self.sequencer.session_id(), self.initial_url.clone(), window_size)
+        };
+
+        let mut recorder = self.recorder.lock().map_err(|_| "Failed to lock recorder")?;
+        recorder.set_save_path(path.map(str::to_string));
+        recorder.start(header.clone())?;
+        let live = recorder.status().live;
+        drop(recorder);
+        self.sync_input_capture();
+        if live {
+            self.publish_recording_message(RECORDING_HEADER_TOPIC, &header);
+        }
+        Ok(())
+    }
+
+    /// Publish the recording as it is captured, or stop doing so. A recording
+    /// under way publishes its header first so followers can start the file.
+    pub fn set_live_recording(&self, live: bool) -> BrowserResult<()> {
+        let mut recorder = self.recorder.lock()
+            .map_err(|_| StateError::LockFailed("Failed to lock recorder".to_string()))?;
+        recorder.set_live(live);
+        let header = recorder.header().filter(|_| live && recorder.is_recording()).cloned();
+        drop(recorder);
+        if let Some(header) = header {
+            self.publish_recording_message(RECORDING_HEADER_TOPIC, &header);
+        }
+        Ok(())
+    }
+
+    /// Apply a recording control. `fetch` answers with the recording itself and
+    /// everything else with the recorder's status, which is also published
+    /// whenever recording starts, stops or goes live.
+    pub fn control_recording(&mut self, control: RecordingControl) -> BrowserResult<CommandResult> {
+        match &control {
+            RecordingControl::Status => {}
+            RecordingControl::Start { path, live } => {
+                let path = path.as_deref().map(|path| self.recording_file(path)).transpose()?;
+                if let Ok(mut recorder) = self.recorder.lock() {
+                    recorder.set_live(*live);
+                }
+                self.record_to(path.as_deref()).map_err(|e| BrowserError::Recording(e.to_string()))?;
+                info!("Started recording {}", path.as_deref().unwrap_or("in memory"));
+            }
+            RecordingControl::Stop => self.stop_recording()?,
+            RecordingControl::Save { path } => {
+                let path = self.recording_file(path)?;
+                self.save_recording(&path).map_err(|e| BrowserError::Recording(e.to_string()))?;
+            }
+            RecordingControl::Fetch { offset } => {
+                let mut recorder = self.recorder.lock()
+                    .map_err(|_| StateError::LockFailed("Failed to lock recorder".to_string()))?;
+                let content = recorder.contents().map_err(|e| BrowserError::Recording(e.to_string()))?;
+                let (content, next) = recording::chunk(&content, *offset, FETCH_CHUNK_BYTES)
+                    .ok_or_else(|| BrowserError::Recording(format!("No recording line starts at offset {}", offset)))?;
+                return Ok(CommandResult::Recording { content: content.to_string(), next });
+            }
+            RecordingControl::SetLive { live } => self.set_live_recording(*live)?,
+        }
+
+        let status = self.recording_status()?;
+        if !matches!(control, RecordingControl::Status | RecordingControl::Save { .. }) {
+            self.publish_recording_message(RECORDING_STATUS_TOPIC, &status);
+        }
+        Ok(CommandResult::RecordingStatus(status))
+    }
+
+    /// Path inside `recordings_dir` for a file named by a recording control,
+    /// with its directory created
+    fn recording_file(&self, path: &str) -> BrowserResult<String> {
+        let path = recording::resolve_path(&self.recordings_dir, path).map_err(BrowserError::Recording)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| BrowserError::Recording(e.to_string()))?;
+        }
+        Ok(path.to_string_lossy().into_owned())
+    }
+
+    pub fn recording_status(&self) -> BrowserResult<RecordingStatus> {
+        self.recorder.lock()
+            .map(|recorder| recorder.status())
+            .map_err(|_| StateError::LockFailed("Failed to lock recorder".to_string()).into())
+    }
+
     pub fn stop_recording(&self) -> Result<(), String> {
+        if let Some(network) = &self.network_recorder {
+            if let Ok(mut network) = network.lock() {
+                network.flush();
+            }
+        }
         if let Ok(mut recorder) = self.recorder.lock() {
             recorder.stop();
+            drop(recorder);
+            self.sync_input_capture();
             info!("Stopped recording");
             Ok(())
         } else {
@@ -194,7 +528,7 @@ impl BrowserEngine {
     }
 
     pub fn save_recording(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        if let Ok(recorder) = self.recorder.lock() {
+        if let Ok(mut recorder) = self.recorder.lock() {
             recorder.save(path)?;
         }
         Ok(())
@@ -208,47 +542,50 @@ impl BrowserEngine {
     }
 
     pub fn start_replay(&mut self) -> Result<(), String> {
+        self.start_replay_from(None, false)
+    }
+
+    /// Start a replay at `seek`, paused if asked, before any event goes out
+    pub fn start_replay_from(&mut self, seek: Option<SeekTarget>, paused: bool) -> Result<(), String> {
         if let Ok(mut player) = self.player.lock() {
             player.start();
-            info!("Started replay");
+            if let Some(to) = seek {
+                player.seek(to);
+            }
+            if paused {
+                player.pause();
+            }
+            info!("Started replay{}", if paused { ", paused" } else { "" });
+            let run = player.run();
+            drop(player);
 
             // Clone necessary handles for the replay thread
             let player = self.player.clone();
-            let cmd_tx = if let Some(ref events) = self.events {
-                if let Ok(events) = events.lock() {
-                    events.get_command_sender()
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
-
-            if let Some(cmd_tx) = cmd_tx {
-                // Spawn replay thread
-                std::thread::spawn(move || {
-                    let mut last_check = Instant::now();
-                    while let Ok(mut player) = player.lock() {
-                        if let Some(event) = player.next_event() {
-                            if let Err(e) = cmd_tx.send(BrowserCommand::PlayEvent { event }) {
+            let cmd_tx = self.command_tx.clone();
+
+            // Spawn replay thread; it hands events to the event loop until the
+            // player stops or starts over, when the next run's thread takes over
+            std::thread::spawn(move || {
+                loop {
+                    let event = match player.lock() {
+                        Ok(mut player) if player.is_playing() && player.run() == run => player.next_event(),
+                        _ => break,
+                    };
+                    match event {
+                        Some(event) => {
+                            if let Err(e) = cmd_tx.send(PendingCommand::new(BrowserCommand::PlayEvent { event })) {
                                 error!("Failed to send replay event: {}", e);
                                 break;
                             }
                         }
-
                         // Sleep a bit to prevent busy waiting
-                        if last_check.elapsed() < Duration::from_millis(10) {
-                            std::thread::sleep(Duration::from_millis(1));
-                        }
-                        last_check = Instant::now();
+                        None => std::thread::sleep(Duration::from_millis(1)),
                     }
-                    info!("Replay completed");
-                });
+                }
+                info!("All recorded events sent for replay");
+            });
 
-                Ok(())
-            } else {
-                Err("No command sender available".to_string())
-            }
+            Ok(())
         } else {
             Err("Failed to lock player".to_string())
         }
@@ -282,7 +619,7 @@ impl BrowserEngine {
         debug!("Building window with WindowBuilder");
         let window = WindowBuilder::new()
             .with_title("Browser")
-            .with_inner_size(LogicalSize::new(800, 600))
+            .with_inner_size(LogicalSize::new(DEFAULT_WINDOW_SIZE.width, DEFAULT_WINDOW_SIZE.height))
             .with_visible(true)
             .with_resizable(true)
             .with_decorations(true)
@@ -371,6 +708,15 @@ impl BrowserEngine {
                 }
                 Event::MainEventsCleared => {
                     debug!("Main events cleared");
+                    if let Ok(mut browser) = browser.lock() {
+                        browser.process_pending_commands();
+                        browser.publish_status();
+                        browser.check_verification();
+                        if !browser.running {
+                            debug!("Shutting down, exiting");
+                            *control_flow = ControlFlow::ExitWithCode(browser.exit_code);
+                        }
+                    }
                     window.request_redraw();
                 }
                 Event::RedrawRequested(_) => {
@@ -400,6 +746,14 @@ impl BrowserEngine {
                     window.set_focus();
                     window.request_redraw();
                 }
+                Event::LoopDestroyed => {
+                    debug!("Event loop exiting");
+                    if let Ok(browser) = browser.lock() {
+                        // Make sure the tail of a streamed recording reaches the disk
+                        let _ = browser.stop_recording();
+                        browser.disconnect_events();
+                    }
+                }
                 _ => {
                     debug!("Other event: {:?}", event);
                 },
@@ -426,6 +780,8 @@ impl BrowserEngine {
             // If this is the first tab, make it active
             if tabs.get_all_tabs().len() == 1 {
                 tabs.switch_to_tab(id);
+                // Release the tab manager before refreshing the views, which locks it again
+                drop(tabs);
                 self.update_tab_visibility()?;
                 self.publish_event(BrowserEvent::TabActivated { id })
                     .map_err(|e| WebViewError::GenericError(e.to_string()))?;
@@ -443,6 +799,7 @@ impl BrowserEngine {
         // First switch the tab in the manager
         if let Ok(mut tabs) = self.tabs.lock() {
             if tabs.switch_to_tab(id) {
+                drop(tabs);
                 // Update WebView content and tab bar
                 self.update_tab_visibility()?;
 
@@ -496,7 +853,12 @@ impl BrowserEngine {
         if let Ok(viewer) = self.event_viewer.lock() {
             viewer.get_recent_events(count)
                 .iter()
-                .map(|entry| format!("[{}] {:?}", entry.timestamp.format("%H:%M:%S"), entry.event))
+                .map(|entry| format!(
+                    "[{} #{}] {:?}",
+                    entry.timestamp.format("%H:%M:%S"),
+                    entry.envelope.sequence,
+                    entry.envelope.event
+                ))
                 .collect()
         } else {
             Vec::new()
@@ -509,44 +871,421 @@ impl BrowserEngine {
         }
     }
 
-    fn handle_command(&mut self, cmd: BrowserCommand) -> Result<(), WebViewError> {
+    /// Execute every queued command, reporting each outcome to whoever is waiting on it
+    pub fn process_pending_commands(&mut self) -> usize {
+        self.process_ipc_messages();
+        self.publish_reports();
+
+        let pending: Vec<PendingCommand> = match self.command_rx.lock() {
+            Ok(rx) => rx.try_iter().collect(),
+            Err(_) => {
+                error!("Failed to lock command queue");
+                return 0;
+            }
+        };
+
+        let count = pending.len();
+        for PendingCommand { command, responder } in pending {
+            debug!("Executing command: {:?}", command);
+            let outcome = match command {
+                // The web view reports script results later, so the reply is sent from its callback
+                BrowserCommand::EvaluateScript { tab_id, script } => {
+                    let reply = responder.clone();
+                    let started = self.evaluate_script(tab_id, &script, move |id, value| {
+                        Self::report_outcome(reply, Ok(CommandResult::ScriptResult { id, value }));
+                    });
+                    match started {
+                        Ok(_) => continue,
+                        Err(e) => Err(CommandError::from(&e)),
+                    }
+                }
+                // So is a screenshot, once the page has rendered it
+                BrowserCommand::Screenshot { tab_id } => {
+                    let reply = responder.clone();
+                    match self.capture(tab_id, move |outcome| Self::report_outcome(reply, outcome)) {
+                        Ok(()) => continue,
+                        Err(e) => Err(CommandError::from(&e)),
+                    }
+                }
+                command => self.handle_command(command).map_err(|e| CommandError::from(&e)),
+            };
+            Self::report_outcome(responder, outcome);
+        }
+        count
+    }
+
+    /// Handle what the page reported since the last pass, ahead of the commands that may depend on it
+    fn process_ipc_messages(&self) {
+        let messages: Vec<String> = match self.ipc_rx.lock() {
+            Ok(rx) => rx.try_iter().collect(),
+            Err(_) => {
+                error!("Failed to lock IPC queue");
+                return;
+            }
+        };
+        for message in messages {
+            if let Err(e) = self.handle_ipc_message(&message) {
+                error!("Failed to handle IPC message: {}", e);
+            }
+        }
+    }
+
+    /// Publish what servers such as the API reported since the last pass
+    fn publish_reports(&self) {
+        let reports: Vec<BrowserEvent> = match self.reports_rx.lock() {
+            Ok(rx) => rx.try_iter().collect(),
+            Err(_) => {
+                error!("Failed to lock report queue");
+                return;
+            }
+        };
+        for event in reports {
+            if let Err(e) = self.publish_event(event) {
+                error!("Failed to publish reported event: {}", e);
+            }
+        }
+    }
+
+    fn report_outcome(responder: Option<Sender<CommandOutcome>>, outcome: CommandOutcome) {
+        match responder {
+            Some(responder) => {
+                // The caller may have timed out and gone away; that is not our error
+                let _ = responder.send(outcome);
+            }
+            None => {
+                if let Err(e) = outcome {
+                    error!("Command failed: {}", e.message);
+                }
+            }
+        }
+    }
+
+    fn active_tab_id(&self) -> BrowserResult<usize> {
+        self.tabs.lock()
+            .map_err(|_| BrowserError::from(WebViewError::LockError("Failed to lock tabs".to_string())))?
+            .get_active_tab()
+            .map(|tab| tab.id)
+            .ok_or_else(|| NavigationError::Failed("No active tab".to_string()).into())
+    }
+
+    /// Move the active tab through its history and show the page it lands on
+    fn step_history(&mut self, forward: bool) -> BrowserResult<CommandResult> {
+        let id = self.active_tab_id()?;
+        let url = {
+            let mut tabs = self.tabs.lock()
+                .map_err(|_| BrowserError::from(WebViewError::LockError("Failed to lock tabs".to_string())))?;
+            if forward { tabs.go_forward(id) } else { tabs.go_back(id) }
+        };
+        let url = url.ok_or_else(|| NavigationError::Failed(format!(
+            "No {} page in tab {}",
+            if forward { "next" } else { "previous" },
+            id
+        )))?;
+
+        self.update_tab_content(id, &url).map_err(NavigationError::Failed)?;
+        self.publish_event(BrowserEvent::Navigation { url: url.clone() })?;
+        if let Some(ref tab_bar) = self.tab_bar {
+            tab_bar.update_tab_url(id, &url);
+        }
+        Ok(CommandResult::Navigated { id, url })
+    }
+
+    /// Drive the browser the way a recorded event did, mapping recorded tab ids to live ones
+    fn replay_event(&mut self, event: BrowserEvent) -> BrowserResult<CommandResult> {
+        let action = ReplayAction::for_event(&event);
+        let result = self.apply_replay_action(&action);
+        // After the action, so the page handles it at the time it did while recording
+        self.sync_page_clock();
+
+        let outcome = match (&action, &result) {
+            (ReplayAction::Derived, _) => ReplayOutcome::Derived,
+            (ReplayAction::Unsupported { reason }, _) => ReplayOutcome::Skipped(reason.to_string()),
+            (_, Ok(_)) => ReplayOutcome::Applied,
+            (_, Err(e)) => ReplayOutcome::Skipped(e.to_string()),
+        };
+        if let Ok(mut player) = self.player.lock() {
+            if player.record_outcome(&event, outcome) {
+                let report = player.executor().report();
+                info!(
+                    "Replay completed: {} applied, {} derived, {} not replayable",
+                    report.applied, report.derived, report.skipped.len()
+                );
+                for skipped in &report.skipped {
+                    info!("  #{} {}: {}", skipped.index, skipped.event_type, skipped.reason);
+                }
+            }
+        }
+        result
+    }
+
+    fn apply_replay_action(&mut self, action: &ReplayAction) -> BrowserResult<CommandResult> {
+        let live_id = |engine: &Self, recorded_id: usize| -> BrowserResult<usize> {
+            engine.player.lock()
+                .map_err(|_| BrowserError::from(WebViewError::LockError("Failed to lock player".to_string())))?
+                .executor()
+                .live_id(recorded_id)
+                .map_err(|e| TabError::InvalidState(e).into())
+        };
+
+        match action {
+            ReplayAction::CreateTab { recorded_id, url } => {
+                let id = self.create_tab(url)?;
+                if let Ok(mut player) = self.player.lock() {
+                    player.executor_mut().map_tab(*recorded_id, id);
+                }
+                Ok(CommandResult::TabCreated { id, url: url.clone() })
+            }
+            ReplayAction::CloseTab { recorded_id } => {
+                let id = live_id(self, *recorded_id)?;
+                self.close_tab(id)?;
+                if let Ok(mut player) = self.player.lock() {
+                    player.executor_mut().forget_tab(*recorded_id);
+                }
+                Ok(CommandResult::TabClosed { id })
+            }
+            ReplayAction::SwitchTab { recorded_id } => {
+                let id = live_id(self, *recorded_id)?;
+                self.switch_to_tab(id)?;
+                Ok(CommandResult::TabSwitched { id })
+            }
+            ReplayAction::Navigate { url } => self.handle_command(BrowserCommand::Navigate { url: url.clone() }),
+            ReplayAction::DispatchInput { summary, script } => {
+                let summary = summary.clone();
+                self.evaluate_script(None, script, move |id, value| {
+                    // The page answers with the selector it used, or with the error that stopped it
+                    debug!("Replayed {} in tab {}: {}", summary, id, value);
+                })?;
+                Ok(CommandResult::Completed)
+            }
+            ReplayAction::Derived | ReplayAction::Unsupported { .. } => Ok(CommandResult::Completed),
+        }
+    }
+
+    /// Check the next replay against the recording; the browser exits with the verdict
+    pub fn verify_replay(&self, options: VerifyOptions) {
+        if let Ok(mut player) = self.player.lock() {
+            player.verify(options);
+        }
+    }
+
+    /// Once a verified replay has settled, return the result and shut down, failing if it differed
+    pub fn check_verification(&mut self) -> Option<VerifyReport> {
+        let (report, options) = self.player.lock().ok()?.take_verify_report()?;
+        if let Some(on_verified) = &self.on_verified {
+            on_verified(&report);
+        }
+        if let Some(path) = &options.report_path {
+            match report.save(path) {
+                Ok(()) => info!("Verification report written to {}", path.display()),
+                Err(e) => {
+                    error!("Failed to write verification report to {}: {}", path.display(), e);
+                    self.exit_code = 1;
+                }
+            }
+        }
+        if !report.passed {
+            self.exit_code = 1;
+        }
+        self.running = false;
+        Some(report)
+    }
+
+    /// Apply a replay control and report where the player ends up
+    pub fn control_replay(&mut self, control: ReplayControl) -> BrowserResult<ReplayStatus> {
+        if control == ReplayControl::Start {
+            self.start_replay()?;
+        }
+        let mut player = self.player.lock()
+            .map_err(|_| StateError::LockFailed("Failed to lock player".to_string()))?;
+        match control {
+            ReplayControl::Status | ReplayControl::Start => {}
+            ReplayControl::Stop => player.stop(),
+            ReplayControl::Pause => player.pause(),
+            ReplayControl::Resume => player.resume(),
+            ReplayControl::Step => player.step(),
+            ReplayControl::Seek { to } => player.seek(to),
+            ReplayControl::SetSpeed { speed } => player.set_speed(speed),
+            ReplayControl::AddBreakpoint { breakpoint } => player.add_breakpoint(breakpoint),
+            ReplayControl::ClearBreakpoints => player.clear_breakpoints(),
+        }
+        let status = player.status();
+        drop(player);
+        // Pausing, seeking and speed changes reach the page's clock too
+        self.sync_page_clock();
+        Ok(status)
+    }
+
+    /// What the current or last replay did, including events it could not reproduce
+    pub fn replay_report(&self) -> ReplayReport {
+        self.player.lock()
+            .map(|player| player.executor().report().clone())
+            .unwrap_or_default()
+    }
+
+    /// Every open tab, ordered by id
+    pub fn tab_list(&self) -> Vec<TabInfo> {
+        self.tabs.lock().map(|tabs| handle::tab_list(&tabs)).unwrap_or_default()
+    }
+
+    /// Run JavaScript in a tab and call `on_result` with its JSON result.
+    ///
+    /// Only the active tab has a live web view, so other tabs are rejected.
+    /// Returns the id of the tab the script runs in.
+    pub fn evaluate_script<F>(&self, tab_id: Option<usize>, script: &str, on_result: F) -> BrowserResult<usize>
+    where
+        F: FnOnce(usize, serde_json::Value) + Send + 'static,
+    {
+        let id = match tab_id {
+            Some(id) => {
+                self.ensure_tab_exists(id)?;
+                id
+            }
+            None => self.active_tab_id()?,
+        };
+        if self.tabs.lock().map(|tabs| !tabs.is_active_tab(id)).unwrap_or(true) {
+            return Err(JavaScriptError::ContextError(format!("Tab {} is not the active tab", id)).into());
+        }
+
+        let view = self.content_view.as_ref()
+            .ok_or_else(|| JavaScriptError::ContextError("No web view to run scripts in".to_string()))?;
+        let view = view.lock()
+            .map_err(|_| BrowserError::from(WebViewError::LockError("Failed to lock web view".to_string())))?;
+
+        // The web view takes a reusable callback; make sure ours only runs once
+        let on_result = Mutex::new(Some(on_result));
+        view.evaluate_script_with_callback(script, move |raw| {
+            let value = serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw));
+            if let Some(on_result) = on_result.lock().ok().and_then(|mut f| f.take()) {
+                on_result(id, value);
+            }
+        }).map_err(|e| JavaScriptError::ExecutionFailed(e.to_string()))?;
+        Ok(id)
+    }
+
+    /// Start capturing a tab as a PNG; `done` gets the outcome once the page has rendered it
+    fn capture<F>(&self, tab_id: Option<usize>, done: F) -> BrowserResult<()>
+    where
+        F: FnOnce(CommandOutcome) + Send + 'static,
+    {
+        if let Some(id) = tab_id {
+            self.ensure_tab_exists(id)?;
+        }
+        if self.content_view.is_none() {
+            return Err(JavaScriptError::ContextError("No web view to capture".to_string()).into());
+        }
+        screenshot::spawn_capture(self.command_sender(), tab_id, done);
+        Ok(())
+    }
+
+    /// Resize the window; the web views follow through `update_webview_bounds`
+    pub fn set_viewport(&self, width: u32, height: u32) -> BrowserResult<()> {
+        if width == 0 || height == 0 {
+            return Err(WindowError::InvalidState(format!("Invalid viewport {}x{}", width, height)).into());
+        }
+        let window = self.window.as_ref()
+            .ok_or_else(|| WindowError::NotFound("No browser window to resize".to_string()))?;
+        window.set_inner_size(LogicalSize::new(width, height));
+        self.update_webview_bounds(window);
+        Ok(())
+    }
+
+    pub fn handle_command(&mut self, cmd: BrowserCommand) -> BrowserResult<CommandResult> {
         match cmd {
             BrowserCommand::CreateTab { url } => {
-                self.create_tab(&url)?;
+                let id = self.create_tab(&url)?;
+                Ok(CommandResult::TabCreated { id, url })
             }
             BrowserCommand::CloseTab { id } => {
+                self.ensure_tab_exists(id)?;
                 self.close_tab(id)?;
+                Ok(CommandResult::TabClosed { id })
             }
             BrowserCommand::SwitchTab { id } => {
+                self.ensure_tab_exists(id)?;
                 self.switch_to_tab(id)?;
+                Ok(CommandResult::TabSwitched { id })
             }
             BrowserCommand::Navigate { url } => {
-                if let Ok(tabs) = self.tabs.lock() {
-                    if let Some(active_tab) = tabs.get_active_tab() {
-                        if let Some(view) = &self.content_view {
-                            if let Ok(view) = view.lock() {
-                                view.load_url(&url);
-                            }
-                        }
-                        // Update tab bar
-                        if let Some(ref tab_bar) = self.tab_bar {
-                            tab_bar.update_tab_url(active_tab.id, &url);
-                        }
-                    }
+                let id = self.tabs.lock()
+                    .map_err(|_| BrowserError::from(WebViewError::LockError("Failed to lock tabs".to_string())))?
+                    .get_active_tab()
+                    .map(|tab| tab.id)
+                    .ok_or_else(|| NavigationError::Failed("No active tab to navigate".to_string()))?;
+
+                self.navigate(&url).map_err(NavigationError::Failed)?;
+
+                // Update tab bar
+                if let Some(ref tab_bar) = self.tab_bar {
+                    tab_bar.update_tab_url(id, &url);
                 }
+                Ok(CommandResult::Navigated { id, url })
             }
             BrowserCommand::RecordEvent { event } => {
-                if let Ok(mut recorder) = self.recorder.lock() {
-                    recorder.record_event(event);
-                }
+                self.record(self.sequencer.stamp(event));
+                Ok(CommandResult::Completed)
             }
-            BrowserCommand::PlayEvent { event } => {
-                if let Ok(mut player) = self.player.lock() {
-                    player.play_event(event);
+            BrowserCommand::PlayEvent { event } => self.replay_event(event),
+            BrowserCommand::Back => self.step_history(false),
+            BrowserCommand::Forward => self.step_history(true),
+            BrowserCommand::Reload => {
+                let id = self.active_tab_id()?;
+                let url = self.get_active_tab().unwrap_or_default();
+                if let Some(view) = &self.content_view {
+                    if let Ok(view) = view.lock() {
+                        view.load_url(&url);
+                    }
                 }
+                self.publish_event(BrowserEvent::Navigation { url: url.clone() })?;
+                Ok(CommandResult::Navigated { id, url })
+            }
+            BrowserCommand::Stop => {
+                let view = self.content_view.as_ref()
+                    .ok_or_else(|| JavaScriptError::ContextError("No web view to stop loading".to_string()))?;
+                let view = view.lock()
+                    .map_err(|_| BrowserError::from(WebViewError::LockError("Failed to lock web view".to_string())))?;
+                view.evaluate_script("window.stop()")
+                    .map_err(|e| JavaScriptError::ExecutionFailed(e.to_string()))?;
+                Ok(CommandResult::Completed)
+            }
+            BrowserCommand::EvaluateScript { tab_id, script } => {
+                // Without a caller to reply to, the result only goes to the log
+                self.evaluate_script(tab_id, &script, |id, value| {
+                    debug!("Script in tab {} returned {}", id, value);
+                })?;
+                Ok(CommandResult::Completed)
+            }
+            BrowserCommand::Screenshot { tab_id } => {
+                // Without a caller to reply to, the outcome only goes to the log
+                self.capture(tab_id, |outcome| match outcome {
+                    Ok(_) => debug!("Captured a screenshot"),
+                    Err(e) => error!("Screenshot failed: {}", e.message),
+                })?;
+                Ok(CommandResult::Completed)
+            }
+            BrowserCommand::SetViewport { width, height } => {
+                self.set_viewport(width, height)?;
+                Ok(CommandResult::ViewportSet { width, height })
+            }
+            BrowserCommand::GetTabs => Ok(CommandResult::Tabs { tabs: self.tab_list() }),
+            BrowserCommand::Replay(control) => Ok(CommandResult::ReplayStatus(self.control_replay(control)?)),
+            BrowserCommand::Recording(control) => self.control_recording(control),
+            BrowserCommand::Shutdown => {
+                info!("Shutdown requested");
+                // The event loop exits once the queued commands have been answered
+                self.running = false;
+                Ok(CommandResult::Completed)
             }
         }
-        Ok(())
+    }
+
+    fn ensure_tab_exists(&self, id: usize) -> BrowserResult<()> {
+        let tabs = self.tabs.lock()
+            .map_err(|_| BrowserError::from(WebViewError::LockError("Failed to lock tabs".to_string())))?;
+        if tabs.get_tab(id).is_some() {
+            Ok(())
+        } else {
+            Err(TabError::NotFound { id }.into())
+        }
     }
 
     fn handle_event(&mut self, event: Event<()>) -> Result<(), WebViewError> {
@@ -587,8 +1326,18 @@ impl BrowserEngine {
         let data: serde_json::Value = serde_json::from_str(msg)
             .map_err(|e| format!("Failed to parse IPC message: {}", e))?;
 
+        if let Some(event) = input_capture::parse_ipc(&data) {
+            // What the user types is nobody's business unless they are recording
+            if !self.is_recording() {
+                return Ok(());
+            }
+            return self.publish_event(event?);
+        }
+
         match data["type"].as_str() {
             Some("pageLoaded") => {
+                // Each page starts with capture off
+                self.sync_input_capture();
                 if let Some(url) = data["url"].as_str() {
                     self.publish_event(BrowserEvent::PageLoaded {
                         url: url.to_string(),
@@ -616,12 +1365,12 @@ impl BrowserEngine {
             }
             Some("navigation") => {
                 if let Some(url) = data["url"].as_str() {
-                    // Update tab URL
+                    // Update tab URL and history
                     if let Ok(mut tabs) = self.tabs.lock() {
-                        if let Some(tab) = tabs.get_active_tab_mut() {
-                            tab.url = url.to_string();
+                        if let Some(id) = tabs.get_active_tab().map(|tab| tab.id) {
+                            tabs.navigate_tab(id, url.to_string());
                             self.publish_event(BrowserEvent::TabUrlChanged {
-                                id: tab.id,
+                                id,
                                 url: url.to_string(),
                             })?;
                         }
@@ -712,11 +1461,30 @@ impl BrowserEngine {
         debug!("Creating WebView with bounds: {:?}", webview_bounds);
 
         debug!("Creating WebView");
-        let builder = WebViewBuilder::new(window)
+        let ipc = self.ipc_tx.clone();
+        let mut builder = WebViewBuilder::new(window)
             .with_bounds(webview_bounds)
             .with_visible(true)  // Ensure WebView is visible
-            .with_transparent(false)
+            .with_transparent(false);
+        // Ahead of everything else, so no script sees the real clock or randomness
+        if let Some(determinism) = &self.determinism {
+            builder = builder.with_initialization_script(&determinism.init_script());
+        }
+        if let Some(proxy) = self.network_proxy {
+            builder = builder.with_proxy_config(ProxyConfig::Http(ProxyEndpoint {
+                host: proxy.ip().to_string(),
+                port: proxy.port().to_string(),
+            }));
+        }
+        let builder = builder
+            // Input capture goes first so it keeps the native IPC channel
+            .with_initialization_script(input_capture::CAPTURE_SCRIPT)
             .with_initialization_script(include_str!("../templates/window_chrome.js"))
+            .with_ipc_handler(move |message| {
+                if ipc.send(message).is_err() {
+                    error!("Browser engine stopped listening for page messages");
+                }
+            })
             .with_html(include_str!("../templates/window_chrome.html"))?;
             
         debug!("Created WebViewBuilder");
@@ -825,6 +1593,18 @@ impl BrowserEngine {
                                     self.switch_to_tab(id).map_err(|e| e.to_string())?;
                                 }
                             }
+                            KeyCommand::Back => {
+                                self.handle_command(BrowserCommand::Back).map_err(|e| e.to_string())?;
+                            }
+                            KeyCommand::Forward => {
+                                self.handle_command(BrowserCommand::Forward).map_err(|e| e.to_string())?;
+                            }
+                            KeyCommand::Refresh => {
+                                self.handle_command(BrowserCommand::Reload).map_err(|e| e.to_string())?;
+                            }
+                            KeyCommand::StopLoading => {
+                                self.handle_command(BrowserCommand::Stop).map_err(|e| e.to_string())?;
+                            }
                             _ => {} // Ignore other commands for now
                         }
                     }
@@ -853,7 +1633,22 @@ impl Clone for BrowserEngine {
             content_view: self.content_view.clone(),
             window: self.window.clone(),
             initial_url: self.initial_url.clone(),
+            determinism: self.determinism,
+            network_proxy: self.network_proxy,
+            network_recorder: self.network_recorder.clone(),
+            recordings_dir: self.recordings_dir.clone(),
             running: self.running,
+            exit_code: self.exit_code,
+            on_verified: self.on_verified.clone(),
+            command_tx: self.command_tx.clone(),
+            command_rx: self.command_rx.clone(),
+            ipc_tx: self.ipc_tx.clone(),
+            ipc_rx: self.ipc_rx.clone(),
+            reports_tx: self.reports_tx.clone(),
+            reports_rx: self.reports_rx.clone(),
+            policy: self.policy.clone(),
+            last_status: self.last_status.clone(),
+            sequencer: self.sequencer.clone(),
         }
     }
 }
@@ -0,0 +1,336 @@
+//! Offline editing of recordings, behind `tinker recording`.
+//!
+//! Every operation works on a loaded `Recording` and leaves the result in
+//! place, so edits can be chained before writing it back out.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use chrono::DateTime;
+use regex::Regex;
+use serde_json::Value;
+use crate::event::{schema, BrowserEvent, SeekTarget, EVENT_SCHEMA_VERSION};
+use super::replay::{EventRecord, Recording, RecordingHeader, RECORDING_FORMAT, RECORDING_FORMAT_VERSION};
+
+/// One line of `tinker recording list`
+pub fn describe(index: usize, record: &EventRecord) -> String {
+    let fields = match serde_json::to_value(&record.envelope.event) {
+        Ok(Value::Object(variant)) => variant.into_iter().next().map(|(_, fields)| fields.to_string()),
+        _ => None,
+    };
+    format!(
+        "{:>5} {:>9} ms  {:<18} {}",
+        index,
+        record.timestamp_ms,
+        record.envelope.event_type,
+        fields.unwrap_or_default()
+    )
+}
+
+/// Index of the first record at or after `target`
+fn position(recording: &Recording, target: SeekTarget) -> usize {
+    match target {
+        SeekTarget::Index(index) => index.min(recording.records.len()),
+        SeekTarget::TimestampMs(ms) => recording.records.iter()
+            .position(|record| record.timestamp_ms >= ms)
+            .unwrap_or(recording.records.len()),
+    }
+}
+
+/// Keep the records from `from` up to, but not including, `to`
+pub fn trim(recording: &mut Recording, from: Option<SeekTarget>, to: Option<SeekTarget>) {
+    let end = to.map_or(recording.records.len(), |to| position(recording, to));
+    let start = from.map_or(0, |from| position(recording, from)).min(end);
+    recording.records.truncate(end);
+    recording.records.drain(..start);
+}
+
+/// Remove every event of the given types, e.g. `title_changed`; returns how many went
+pub fn drop_events(recording: &mut Recording, event_types: &[String]) -> Result<usize, String> {
+    let known: Vec<&str> = schema::event_examples().iter().map(BrowserEvent::event_type).collect();
+    if let Some(unknown) = event_types.iter().find(|event_type| !known.contains(&event_type.as_str())) {
+        return Err(format!("Unknown event type '{}'; expected one of {}", unknown, known.join(", ")));
+    }
+    let before = recording.records.len();
+    recording.records.retain(|record| !event_types.contains(&record.envelope.event_type));
+    Ok(before - recording.records.len())
+}
+
+/// Wall-clock time of a record, in milliseconds since the epoch
+fn wall_clock_ms(record: &EventRecord) -> Option<i64> {
+    DateTime::parse_from_rfc3339(&record.envelope.timestamp)
+        .ok()
+        .map(|timestamp| timestamp.timestamp_millis())
+}
+
+/// Combine recordings into one, keeping the first header.
+///
+/// By default each recording is played after the previous one, `gap_ms`
+/// later. With `interleave`, events are ordered by when they were recorded.
+pub fn merge(recordings: Vec<Recording>, interleave: bool, gap_ms: u64) -> Recording {
+    let header = recordings.iter().find_map(|recording| recording.header.clone());
+    let mut records: Vec<EventRecord> = Vec::new();
+
+    if interleave {
+        let mut timed: Vec<(i64, EventRecord)> = recordings.into_iter()
+            .flat_map(|recording| recording.records)
+            .map(|record| (wall_clock_ms(&record).unwrap_or_default(), record))
+            .collect();
+        timed.sort_by_key(|(wall_clock, _)| *wall_clock);
+        let start = timed.first().map_or(0, |(wall_clock, _)| *wall_clock);
+        records = timed.into_iter()
+            .map(|(wall_clock, mut record)| {
+                record.timestamp_ms = (wall_clock - start).max(0) as u64;
+                record
+            })
+            .collect();
+    } else {
+        for recording in recordings {
+            let offset = records.last().map_or(0, |last| last.timestamp_ms + gap_ms);
+            records.extend(recording.records.into_iter().map(|mut record| {
+                record.timestamp_ms += offset;
+                record
+            }));
+        }
+    }
+
+    // Sequences from different sessions collide; number the merged events afresh
+    for (sequence, record) in records.iter_mut().enumerate() {
+        record.envelope.sequence = sequence as u64 + 1;
+    }
+    Recording { header, records }
+}
+
+/// How to move a recording's events in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retime {
+    /// Start the first event at 0 ms
+    pub zero: bool,
+    /// Multiply every offset, e.g. 0.5 to play twice as fast
+    pub scale: f64,
+    /// Added to every offset after scaling; offsets stop at 0
+    pub shift_ms: i64,
+}
+
+impl Default for Retime {
+    fn default() -> Self {
+        Self {
+            zero: false,
+            scale: 1.0,
+            shift_ms: 0,
+        }
+    }
+}
+
+pub fn retime(recording: &mut Recording, retime: Retime) {
+    let base = match (retime.zero, recording.records.first()) {
+        (true, Some(first)) => first.timestamp_ms,
+        _ => 0,
+    };
+    for record in &mut recording.records {
+        let scaled = record.timestamp_ms.saturating_sub(base) as f64 * retime.scale;
+        record.timestamp_ms = (scaled.round() as i64 + retime.shift_ms).max(0) as u64;
+    }
+}
+
+/// Apply every rule to the URLs in a recording, including URLs inside
+/// recorded commands; returns how many values changed
+pub fn scrub(recording: &mut Recording, rules: &[(Regex, String)]) -> Result<usize, String> {
+    let apply = |text: &str| {
+        rules.iter().fold(text.to_string(), |text, (pattern, replacement)| {
+            pattern.replace_all(&text, replacement.as_str()).into_owned()
+        })
+    };
+    let mut changed = 0;
+
+    if let Some(url) = recording.header.as_mut().and_then(|header| header.initial_url.as_mut()) {
+        let scrubbed = apply(url);
+        if scrubbed != *url {
+            *url = scrubbed;
+            changed += 1;
+        }
+    }
+    for record in &mut recording.records {
+        let mut event = serde_json::to_value(&record.envelope.event).map_err(|e| e.to_string())?;
+        let count = scrub_value(&mut event, None, &apply);
+        if count > 0 {
+            record.envelope.event = serde_json::from_value(event).map_err(|e| e.to_string())?;
+            changed += count;
+        }
+    }
+    Ok(changed)
+}
+
+/// Scrub strings held under `url` or `command` keys, at any depth
+fn scrub_value(value: &mut Value, key: Option<&str>, apply: &impl Fn(&str) -> String) -> usize {
+    match value {
+        Value::String(text) if matches!(key, Some("url" | "command")) => {
+            let scrubbed = apply(text);
+            if scrubbed == *text {
+                return 0;
+            }
+            *text = scrubbed;
+            1
+        }
+        Value::Object(fields) => fields.iter_mut()
+            .map(|(key, value)| scrub_value(value, Some(key), apply))
+            .sum(),
+        Value::Array(items) => items.iter_mut().map(|item| scrub_value(item, key, apply)).sum(),
+        _ => 0,
+    }
+}
+
+/// Parse `--replace` arguments, given as pattern/replacement pairs
+pub fn scrub_rules(pairs: &[String]) -> Result<Vec<(Regex, String)>, String> {
+    pairs.chunks(2)
+        .map(|pair| match pair {
+            [pattern, replacement] => Regex::new(pattern)
+                .map(|pattern| (pattern, replacement.clone()))
+                .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e)),
+            _ => Err("Every --replace needs a pattern and a replacement".to_string()),
+        })
+        .collect()
+}
+
+/// A problem found by `validate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// 1-based line in the file
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Check a file against the streamed recording format, strictly: unlike
+/// loading, a torn last line or a legacy array is reported too
+pub fn validate(path: &Path) -> io::Result<Vec<Problem>> {
+    let text = fs::read_to_string(path)?;
+    let problem = |line: usize, message: String| Problem { line, message };
+
+    if text.trim_start().starts_with('[') {
+        return Ok(vec![problem(1, "legacy JSON array recording; any edit rewrites it in the current format".to_string())]);
+    }
+
+    let mut problems = Vec::new();
+    let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line));
+    match lines.next() {
+        None => problems.push(problem(1, "empty file".to_string())),
+        Some((line, header)) => match serde_json::from_str::<RecordingHeader>(header) {
+            Ok(header) if header.format != RECORDING_FORMAT => {
+                problems.push(problem(line, format!("format is '{}', expected '{}'", header.format, RECORDING_FORMAT)));
+            }
+            Ok(header) if header.format_version > RECORDING_FORMAT_VERSION => problems.push(problem(
+                line,
+                format!("format version {} is newer than {}", header.format_version, RECORDING_FORMAT_VERSION),
+            )),
+            Ok(_) => {}
+            Err(e) => problems.push(problem(line, format!("invalid header: {}", e))),
+        },
+    }
+
+    let mut previous: Option<EventRecord> = None;
+    for (line, text) in lines {
+        if text.trim().is_empty() {
+            continue;
+        }
+        let record = match serde_json::from_str::<EventRecord>(text) {
+            Ok(record) => record,
+            Err(e) => {
+                problems.push(problem(line, format!("invalid record: {}", e)));
+                continue;
+            }
+        };
+        let envelope = &record.envelope;
+        if envelope.event_type != envelope.event.event_type() {
+            problems.push(problem(line, format!(
+                "type '{}' does not match event '{}'", envelope.event_type, envelope.event.event_type()
+            )));
+        }
+        if envelope.schema_version > EVENT_SCHEMA_VERSION {
+            problems.push(problem(line, format!(
+                "schema version {} is newer than {}", envelope.schema_version, EVENT_SCHEMA_VERSION
+            )));
+        }
+        if let Some(previous) = &previous {
+            if record.timestamp_ms < previous.timestamp_ms {
+                problems.push(problem(line, format!(
+                    "timestamp_ms {} is before the previous record's {}", record.timestamp_ms, previous.timestamp_ms
+                )));
+            }
+            if envelope.sequence <= previous.envelope.sequence {
+                problems.push(problem(line, format!(
+                    "sequence {} does not follow {}", envelope.sequence, previous.envelope.sequence
+                )));
+            }
+        }
+        previous = Some(record);
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Navigations to `url` at each offset
+    fn recording(events: &[(u64, &str)]) -> Recording {
+        let events: Vec<(u64, BrowserEvent)> = events.iter()
+            .map(|(timestamp_ms, url)| (*timestamp_ms, BrowserEvent::Navigation { url: url.to_string() }))
+            .collect();
+        Recording::from_events(&events)
+    }
+
+    fn offsets(recording: &Recording) -> Vec<u64> {
+        recording.records.iter().map(|record| record.timestamp_ms).collect()
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut trimmed = recording(&[(0, "a"), (100, "b"), (200, "c"), (300, "d")]);
+        trim(&mut trimmed, Some(SeekTarget::Index(1)), Some(SeekTarget::TimestampMs(300)));
+        assert_eq!(offsets(&trimmed), vec![100, 200]);
+
+        let mut emptied = recording(&[(0, "a"), (100, "b")]);
+        trim(&mut emptied, Some(SeekTarget::Index(5)), None);
+        assert!(emptied.records.is_empty());
+    }
+
+    #[test]
+    fn test_drop_events() {
+        let mut edited = recording(&[(0, "a"), (100, "b")]);
+        assert!(drop_events(&mut edited, &["titel_changed".to_string()]).is_err());
+        assert_eq!(drop_events(&mut edited, &["navigation".to_string()]), Ok(2));
+        assert!(edited.records.is_empty());
+    }
+
+    #[test]
+    fn test_merge_and_retime() {
+        let merged = merge(vec![recording(&[(0, "a"), (100, "b")]), recording(&[(50, "c")])], false, 1_000);
+        assert_eq!(offsets(&merged), vec![0, 100, 1_150]);
+        let sequences: Vec<u64> = merged.records.iter().map(|record| record.envelope.sequence).collect();
+        assert_eq!(sequences, vec![1, 2, 3]);
+
+        let mut retimed = merged;
+        retime(&mut retimed, Retime { zero: true, scale: 0.5, shift_ms: -50 });
+        assert_eq!(offsets(&retimed), vec![0, 0, 525]);
+    }
+
+    #[test]
+    fn test_scrub_urls() {
+        let mut scrubbed = recording(&[(0, "https://internal.corp/login?token=abc"), (10, "about:blank")]);
+        let rules = scrub_rules(&[
+            r"token=[^&]+".to_string(), "token=REDACTED".to_string(),
+            r"internal\.corp".to_string(), "example.com".to_string(),
+        ]).unwrap();
+        assert_eq!(scrub(&mut scrubbed, &rules), Ok(1));
+        assert_eq!(scrubbed.records[0].envelope.event.url(), Some("https://example.com/login?token=REDACTED"));
+        assert!(scrub_rules(&["(".to_string(), String::new()]).is_err());
+        assert!(scrub_rules(&["only-a-pattern".to_string()]).is_err());
+    }
+}
@@ -1,130 +1,881 @@
-use std::fs::File;
-use std::io::{self, BufWriter, BufReader};
+//! Recording and playback of browser events.
+//!
+//! Recordings are JSON Lines: a `RecordingHeader` line followed by one
+//! `EventRecord` per line, appended as events happen so a crash loses at most
+//! the last line. Older recordings, a single JSON array of records, still load.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
-use crate::event::BrowserEvent;
-use tracing::{debug, error};
+use crate::event::{Breakpoint, BrowserEvent, EventEnvelope, EventSequencer, RecordingStatus, ReplayState, ReplayStatus, SeekTarget};
+use super::determinism::{ClockReading, Determinism, VirtualClock};
+use super::replay_executor::{ReplayExecutor, ReplayOutcome};
+use super::replay_verify::{ReplayVerifier, VerifyOptions, VerifyReport};
+use tracing::{debug, error, info, warn};
+
+/// Value of `RecordingHeader::format`, identifying a streamed recording
+pub const RECORDING_FORMAT: &str = "tinker-recording";
+
+/// Version of the streamed recording layout
+pub const RECORDING_FORMAT_VERSION: u32 = 2;
+
+/// How often a streamed recording is forced to disk
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
 
-#[derive(Serialize, Deserialize)]
-struct EventRecord {
-    timestamp_ms: u64,
-    event: BrowserEvent,
+/// Window size in logical pixels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// First line of a streamed recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordingHeader {
+    pub format: String,
+    pub format_version: u32,
+    pub tinker_version: String,
+    /// Wall-clock start of the recording (RFC 3339)
+    pub started_at: String,
+    pub session_id: String,
+    pub initial_url: Option<String>,
+    pub window_size: WindowSize,
+    /// Present when the pages were made deterministic while recording
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub determinism: Option<Determinism>,
+}
+
+impl RecordingHeader {
+    pub fn new(session_id: &str, initial_url: Option<String>, window_size: WindowSize) -> Self {
+        Self {
+            format: RECORDING_FORMAT.to_string(),
+            format_version: RECORDING_FORMAT_VERSION,
+            tinker_version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+            session_id: session_id.to_string(),
+            initial_url,
+            window_size,
+            determinism: None,
+        }
+    }
+}
+
+/// One recorded event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// Offset from the start of the recording, used to pace playback
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub envelope: EventEnvelope,
+}
+
+/// Recordings are either enveloped records or the older bare `{timestamp_ms, event}` form
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredRecord {
+    Enveloped(EventRecord),
+    Legacy { timestamp_ms: u64, event: BrowserEvent },
+}
+
+/// Append-only writer for a streamed recording
+struct RecordingWriter {
+    writer: BufWriter<File>,
+    last_sync: Instant,
+}
+
+impl RecordingWriter {
+    fn create(path: &str, header: &RecordingHeader) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, header)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+        Ok(Self {
+            writer,
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Append one line; it reaches the OS straight away and the disk within `SYNC_INTERVAL`
+    fn append(&mut self, record: &EventRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
 }
 
+/// Records events, streaming them to the save path when one is set and
+/// buffering them in memory otherwise
 #[derive(Default)]
 pub struct EventRecorder {
     events: Vec<EventRecord>,
+    header: Option<RecordingHeader>,
+    writer: Option<RecordingWriter>,
     start_time: Option<Instant>,
     save_path: Option<String>,
+    /// File the current or last recording was streamed to; it stays there after `stop`
+    streamed_to: Option<String>,
     is_recording: bool,
+    /// Whether records are handed back from `record_event` for publishing
+    live: bool,
+    /// Events in the current or last recording, streamed or buffered
+    count: usize,
 }
 
 impl EventRecorder {
-    pub fn start(&mut self) {
-        self.start_time = Some(Instant::now());
+    /// Start a new recording, creating the save file if one was set
+    pub fn start(&mut self, header: RecordingHeader) -> io::Result<()> {
         self.events.clear();
+        self.writer = match &self.save_path {
+            Some(path) => Some(RecordingWriter::create(path, &header)?),
+            None => None,
+        };
+        self.streamed_to = self.writer.as_ref().and(self.save_path.clone());
+        self.header = Some(header);
+        self.start_time = Some(Instant::now());
+        self.count = 0;
         self.is_recording = true;
         debug!("Started recording events");
+        Ok(())
     }
 
     pub fn stop(&mut self) {
         self.is_recording = false;
+        if let Some(mut writer) = self.writer.take() {
+            if let Err(e) = writer.sync() {
+                error!("Failed to sync recording: {}", e);
+            }
+        }
         debug!("Stopped recording events");
     }
 
-    pub fn set_save_path(&mut self, path: String) {
-        self.save_path = Some(path);
+    /// Where the next recording is streamed; `None` keeps it in memory
+    pub fn set_save_path(&mut self, path: Option<String>) {
+        self.save_path = path;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.is_recording
+    }
+
+    pub fn set_live(&mut self, live: bool) {
+        self.live = live;
+    }
+
+    pub fn header(&self) -> Option<&RecordingHeader> {
+        self.header.as_ref()
     }
 
-    pub fn record_event(&mut self, event: BrowserEvent) {
-        if self.is_recording {
-            if let Some(start) = self.start_time {
-                let elapsed = start.elapsed();
-                self.events.push(EventRecord {
-                    timestamp_ms: elapsed.as_millis() as u64,
-                    event,
-                });
-                debug!("Recorded event at {:?}", elapsed);
+    pub fn status(&self) -> RecordingStatus {
+        RecordingStatus {
+            recording: self.is_recording,
+            live: self.live,
+            events: self.count,
+            path: self.streamed_to.clone(),
+        }
+    }
+
+    /// The recording's timeline, which deterministic pages use as their clock while recording
+    pub fn page_clock(&self) -> Option<ClockReading> {
+        let start = self.start_time.filter(|_| self.is_recording)?;
+        Some(ClockReading { offset_ms: start.elapsed().as_millis() as u64, rate: 1.0 })
+    }
+
+    /// Record an event; in live mode the record is returned for publishing
+    pub fn record_event(&mut self, envelope: EventEnvelope) -> Option<EventRecord> {
+        if !self.is_recording {
+            return None;
+        }
+        let start = self.start_time?;
+        let elapsed = start.elapsed();
+        let record = EventRecord {
+            timestamp_ms: elapsed.as_millis() as u64,
+            envelope,
+        };
+        self.count += 1;
+        debug!("Recorded event at {:?}", elapsed);
+        let live = self.live.then(|| record.clone());
+        match self.writer.as_mut() {
+            Some(writer) => {
+                if let Err(e) = writer.append(&record) {
+                    error!("Failed to write recorded event: {}", e);
+                }
             }
+            None => self.events.push(record),
         }
+        live
     }
 
-    pub fn save(&self, path: &str) -> io::Result<()> {
-        let file = File::create(path)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, &self.events)?;
+    /// The recording in the file format, header first
+    pub fn contents(&mut self) -> io::Result<String> {
+        if let Some(path) = self.sync_streamed()? {
+            return fs::read_to_string(path);
+        }
+
+        let header = self.header.clone()
+            .unwrap_or_else(|| RecordingHeader::new("", None, WindowSize { width: 0, height: 0 }));
+        let mut text = serde_json::to_string(&header)?;
+        text.push('\n');
+        for record in &self.events {
+            text.push_str(&serde_json::to_string(record)?);
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    /// Write the recording to `path`. A streamed recording is already on disk
+    /// and is copied, unless `path` is that same file under another name; a
+    /// buffered one is written out in the streamed format.
+    pub fn save(&mut self, path: &str) -> io::Result<()> {
+        if let Some(source) = self.sync_streamed()? {
+            // Copying a file onto itself truncates it before reading it
+            if !same_file(Path::new(source), Path::new(path)) {
+                fs::copy(source, path)?;
+            }
+            return Ok(());
+        }
+
+        let header = self.header.clone()
+            .unwrap_or_else(|| RecordingHeader::new("", None, WindowSize { width: 0, height: 0 }));
+        let mut writer = RecordingWriter::create(path, &header)?;
+        for record in &self.events {
+            writer.append(record)?;
+        }
+        writer.sync()?;
         debug!("Saved {} events to {}", self.events.len(), path);
         Ok(())
     }
+
+    /// Path of a streamed recording, with everything recorded so far on disk
+    fn sync_streamed(&mut self) -> io::Result<Option<&str>> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.sync()?;
+        }
+        Ok(self.streamed_to.as_deref())
+    }
 }
 
-#[derive(Default)]
+impl Drop for EventRecorder {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.as_mut() {
+            let _ = writer.sync();
+        }
+    }
+}
+
+/// Whether both paths name one existing file, through `.`, `..` or symlinks
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Read a recording in either format. A torn last line, as left by a crash, is skipped.
+fn read_recording(path: &Path) -> io::Result<(Option<RecordingHeader>, Vec<StoredRecord>)> {
+    let text = fs::read_to_string(path)?;
+
+    // Legacy recordings are one JSON array
+    if text.trim_start().starts_with('[') {
+        return Ok((None, serde_json::from_str(&text)?));
+    }
+
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    let mut header = None;
+    let mut records = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if index == 0 {
+            if let Ok(parsed) = serde_json::from_str::<RecordingHeader>(line) {
+                header = Some(parsed);
+                continue;
+            }
+        }
+        match serde_json::from_str::<StoredRecord>(line) {
+            Ok(record) => records.push(record),
+            Err(e) if index + 1 == lines.len() => {
+                warn!("Skipping incomplete last record in {}: {}", path.display(), e);
+            }
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid record {} in {}: {}", index + 1, path.display(), e),
+                ));
+            }
+        }
+    }
+    Ok((header, records))
+}
+
+/// A whole recording in memory, for inspecting and editing it offline
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    /// Absent for legacy recordings
+    pub header: Option<RecordingHeader>,
+    pub records: Vec<EventRecord>,
+}
+
+impl Recording {
+    /// Read a recording in either format; legacy records get a fresh sequence in file order
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let (header, records) = read_recording(path)?;
+        let sequencer = EventSequencer::default();
+        let records = records.into_iter()
+            .map(|record| match record {
+                StoredRecord::Enveloped(record) => record,
+                StoredRecord::Legacy { timestamp_ms, event } => EventRecord {
+                    timestamp_ms,
+                    envelope: sequencer.stamp(event),
+                },
+            })
+            .collect();
+        Ok(Self { header, records })
+    }
+
+    /// A recording without a header, of `events` at their offsets in milliseconds
+    #[cfg(test)]
+    pub(crate) fn from_events(events: &[(u64, BrowserEvent)]) -> Self {
+        let sequencer = EventSequencer::default();
+        let records = events.iter()
+            .map(|(timestamp_ms, event)| EventRecord {
+                timestamp_ms: *timestamp_ms,
+                envelope: sequencer.stamp(event.clone()),
+            })
+            .collect();
+        Self { header: None, records }
+    }
+
+    /// Write in the streamed format; legacy recordings get a blank header
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let header = self.header.clone()
+            .unwrap_or_else(|| RecordingHeader::new("", None, WindowSize { width: 0, height: 0 }));
+        serde_json::to_writer(&mut out, &header)?;
+        out.write_all(b"\n")?;
+        for record in &self.records {
+            serde_json::to_writer(&mut out, record)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        self.write_to(BufWriter::new(&file))?;
+        file.sync_data()
+    }
+}
+
+/// Plays a loaded recording back on a virtual timeline that can be paused,
+/// stepped, seeked and stopped at breakpoints
 pub struct EventPlayer {
+    header: Option<RecordingHeader>,
     events: Vec<EventRecord>,
-    start_time: Option<Instant>,
     current_index: usize,
     speed: f32,
-    is_playing: bool,
+    state: ReplayState,
+    /// The timeline events are paced by and deterministic pages read; it only runs while playing
+    clock: VirtualClock,
+    breakpoints: Vec<Breakpoint>,
+    /// Event held back by a breakpoint; `resume` and `step` let it through
+    held_at: Option<usize>,
+    pending_steps: usize,
+    dispatched: usize,
+    /// Counts `start`s, so a feeder thread can tell its replay was restarted
+    run: u64,
+    executor: ReplayExecutor,
+    verifier: Option<ReplayVerifier>,
+}
+
+impl Default for EventPlayer {
+    fn default() -> Self {
+        Self {
+            header: None,
+            events: Vec::new(),
+            current_index: 0,
+            speed: 1.0,
+            state: ReplayState::Stopped,
+            clock: VirtualClock::default(),
+            breakpoints: Vec::new(),
+            held_at: None,
+            pending_steps: 0,
+            dispatched: 0,
+            run: 0,
+            executor: ReplayExecutor::default(),
+            verifier: None,
+        }
+    }
 }
 
 impl EventPlayer {
     pub fn load(&mut self, path: &str) -> io::Result<()> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        self.events = serde_json::from_reader(reader)?;
+        let recording = Recording::load(Path::new(path))?;
+        self.header = recording.header;
+        self.events = recording.records;
         self.current_index = 0;
         debug!("Loaded {} events from {}", self.events.len(), path);
         Ok(())
     }
 
+    /// Header of the loaded recording; legacy recordings have none
+    pub fn header(&self) -> Option<&RecordingHeader> {
+        self.header.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
     pub fn start(&mut self) {
-        self.start_time = Some(Instant::now());
         self.current_index = 0;
-        self.is_playing = true;
+        self.clock = VirtualClock::new(self.speed as f64);
+        self.held_at = None;
+        self.pending_steps = 0;
+        self.dispatched = 0;
+        self.executor = ReplayExecutor::default();
+        if let Some(verifier) = self.verifier.as_mut() {
+            verifier.reset(self.events.iter().map(|record| (record.timestamp_ms, &record.envelope.event)));
+        }
+        self.state = ReplayState::Playing;
+        self.clock.run();
+        self.run += 1;
         debug!("Started event playback");
     }
 
+    /// Which `start` the current or last replay came from
+    pub fn run(&self) -> u64 {
+        self.run
+    }
+
     pub fn stop(&mut self) {
-        self.is_playing = false;
+        self.clock.stop();
+        self.state = ReplayState::Stopped;
         debug!("Stopped event playback");
     }
 
+    /// Freeze the timeline; `resume` continues from the same position
+    pub fn pause(&mut self) {
+        if self.state == ReplayState::Playing {
+            self.clock.stop();
+            self.state = ReplayState::Paused;
+            debug!("Paused event playback at {} ms", self.position_ms() as u64);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.state == ReplayState::Paused {
+            self.clock.run();
+            self.state = ReplayState::Playing;
+            debug!("Resumed event playback at {} ms", self.position_ms() as u64);
+        }
+    }
+
+    /// Pause and let exactly one more event through
+    pub fn step(&mut self) {
+        if self.state == ReplayState::Stopped {
+            return;
+        }
+        self.pause();
+        self.pending_steps += 1;
+    }
+
+    /// Jump to an event or offset; events in between are not replayed
+    pub fn seek(&mut self, target: SeekTarget) {
+        let (index, position_ms) = match target {
+            SeekTarget::Index(index) => {
+                let index = index.min(self.events.len());
+                let position = self.events.get(index)
+                    .or(self.events.last())
+                    .map_or(0, |record| record.timestamp_ms);
+                (index, position)
+            }
+            SeekTarget::TimestampMs(ms) => {
+                let index = self.events.iter()
+                    .position(|record| record.timestamp_ms >= ms)
+                    .unwrap_or(self.events.len());
+                (index, ms)
+            }
+        };
+        self.current_index = index;
+        self.clock.set_ms(position_ms as f64);
+        self.held_at = None;
+        debug!("Seeked to event {} at {} ms", index, position_ms);
+    }
+
     pub fn set_speed(&mut self, speed: f32) {
         self.speed = speed.max(0.1).min(10.0);
+        self.clock.set_rate(self.speed as f64);
         debug!("Set playback speed to {}", speed);
     }
 
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Position on the recording's timeline
+    pub fn position_ms(&self) -> f64 {
+        self.clock.now_ms()
+    }
+
+    /// The timeline as deterministic pages run it, stopped unless playing
+    pub fn page_clock(&self) -> ClockReading {
+        self.clock.reading()
+    }
+
+    pub fn state(&self) -> ReplayState {
+        self.state
+    }
+
+    /// Whether a replay is under way, possibly paused
+    pub fn is_playing(&self) -> bool {
+        self.state != ReplayState::Stopped
+    }
+
+    pub fn status(&self) -> ReplayStatus {
+        ReplayStatus {
+            state: self.state,
+            index: self.current_index,
+            total: self.events.len(),
+            position_ms: self.position_ms() as u64,
+            speed: self.speed,
+            breakpoints: self.breakpoints.clone(),
+            held_at: self.held_at,
+        }
+    }
+
+    /// The next event whose time has come, if any
     pub fn next_event(&mut self) -> Option<BrowserEvent> {
-        if !self.is_playing {
+        if self.state == ReplayState::Stopped {
             return None;
         }
+        let index = self.current_index;
+        let Some(record) = self.events.get(index) else {
+            self.stop();
+            return None;
+        };
 
-        if let Some(start) = self.start_time {
-            if self.current_index < self.events.len() {
-                let record = &self.events[self.current_index];
-                let elapsed = start.elapsed();
-                let target_time = Duration::from_millis(
-                    (record.timestamp_ms as f32 / self.speed) as u64
-                );
-
-                if elapsed >= target_time {
-                    self.current_index += 1;
-                    debug!("Playing event at {:?}", elapsed);
-                    return Some(record.event.clone());
-                }
-            } else {
-                self.stop();
+        if self.pending_steps > 0 {
+            self.pending_steps -= 1;
+            self.clock.set_ms(self.position_ms().max(record.timestamp_ms as f64));
+        } else {
+            if self.state == ReplayState::Paused {
+                return None;
+            }
+            let previous_ms = index.checked_sub(1).map(|previous| self.events[previous].timestamp_ms);
+            let hit = self.held_at != Some(index) && self.breakpoints.iter()
+                .any(|breakpoint| breakpoint.matches(index, record.timestamp_ms, previous_ms, &record.envelope));
+            if hit {
+                info!("Replay paused at breakpoint before event {} ({})", index, record.envelope.event_type);
+                self.held_at = Some(index);
+                self.pause();
+                return None;
+            }
+            if self.position_ms() < record.timestamp_ms as f64 {
+                return None;
             }
         }
-        None
+
+        let event = record.envelope.event.clone();
+        self.current_index += 1;
+        self.held_at = None;
+        self.dispatched += 1;
+        debug!("Playing event {} at {} ms", index, self.position_ms() as u64);
+        Some(event)
     }
 
-    pub fn play_event(&mut self, event: BrowserEvent) {
-        if self.is_playing {
-            if let Some(start) = self.start_time {
-                let elapsed = start.elapsed();
-                debug!("Playing event at {:?}: {:?}", elapsed, event);
-            }
+    /// Tab mapping and report for the current replay
+    pub fn executor(&self) -> &ReplayExecutor {
+        &self.executor
+    }
+
+    pub fn executor_mut(&mut self) -> &mut ReplayExecutor {
+        &mut self.executor
+    }
+
+    /// Note how a played event was handled; true once the replay has ended and every event it sent is handled
+    pub fn record_outcome(&mut self, event: &BrowserEvent, outcome: ReplayOutcome) -> bool {
+        debug!("Replayed {}: {:?}", event.event_type(), outcome);
+        self.executor.record(event, outcome);
+        self.current_index >= self.events.len() && self.executor.report().total() >= self.dispatched
+    }
+
+    /// Compare the events emitted during the next replay with the recording
+    pub fn verify(&mut self, options: VerifyOptions) {
+        self.verifier = Some(ReplayVerifier::new(options));
+    }
+
+    /// Capture an event the browser emitted, if a verified replay is under way or settling
+    pub fn observe(&mut self, event: &BrowserEvent) {
+        let Some(verifier) = self.verifier.as_ref().filter(|verifier| verifier.is_armed()) else {
+            return;
+        };
+        // After the replay ends the timeline keeps running while late page events arrive
+        let settling_ms = verifier.settling().as_secs_f64() * 1000.0 * self.speed as f64;
+        let offset_ms = (self.position_ms() + settling_ms) as u64;
+        let recorded_tab = event.tab_id().and_then(|id| self.executor.recorded_id(id));
+        if let Some(verifier) = self.verifier.as_mut() {
+            verifier.observe(offset_ms, event, recorded_tab);
         }
     }
-} 
+
+    /// The verification report, once the replay has stopped and settled; taken at most once
+    pub fn take_verify_report(&mut self) -> Option<(VerifyReport, VerifyOptions)> {
+        let replay_done = self.state == ReplayState::Stopped && self.executor.report().total() >= self.dispatched;
+        let verifier = self.verifier.as_mut().filter(|verifier| verifier.is_armed())?;
+        if replay_done {
+            verifier.finish();
+        }
+        if !verifier.is_due() {
+            return None;
+        }
+        let verifier = self.verifier.take()?;
+        Some((verifier.report(), verifier.options().clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("tinker-{}-{}.jsonl", name, std::process::id()))
+            .display()
+            .to_string()
+    }
+
+    fn header() -> RecordingHeader {
+        RecordingHeader::new("session", Some("https://example.com".to_string()), WindowSize { width: 800, height: 600 })
+    }
+
+    #[test]
+    fn test_recording_streams_to_disk() {
+        let path = temp_path("stream");
+        let sequencer = EventSequencer::default();
+        let mut recorder = EventRecorder::default();
+        recorder.set_save_path(Some(path.clone()));
+        recorder.start(header()).unwrap();
+        recorder.record_event(sequencer.stamp(BrowserEvent::TabCreated { id: 0, url: "https://example.com".to_string() }));
+        recorder.record_event(sequencer.stamp(BrowserEvent::Navigation { url: "https://example.com/next".to_string() }));
+
+        // Readable while still recording, without stopping or saving
+        let mut player = EventPlayer::default();
+        player.load(&path).unwrap();
+        assert_eq!(player.header().unwrap().initial_url.as_deref(), Some("https://example.com"));
+        assert_eq!(player.len(), 2);
+        assert!(recorder.events.is_empty());
+
+        recorder.stop();
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_save_after_stop_keeps_the_recording() {
+        let (path, copy) = (temp_path("stopped"), temp_path("stopped-copy"));
+        let sequencer = EventSequencer::default();
+        let mut recorder = EventRecorder::default();
+        recorder.set_save_path(Some(path.clone()));
+        recorder.start(header()).unwrap();
+        recorder.record_event(sequencer.stamp(BrowserEvent::TabCreated { id: 0, url: "https://example.com".to_string() }));
+        recorder.stop();
+
+        // Saving to the streamed file or elsewhere leaves every event in place
+        recorder.save(&path).unwrap();
+        recorder.save(&copy).unwrap();
+        for saved in [&path, &copy] {
+            let mut player = EventPlayer::default();
+            player.load(saved).unwrap();
+            assert_eq!(player.len(), 1);
+        }
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&copy);
+    }
+
+    #[test]
+    fn test_save_to_an_alias_of_the_streamed_file() {
+        let path = temp_path("aliased");
+        let dir = Path::new(&path).parent().unwrap();
+        let alias = dir.join(".").join(Path::new(&path).file_name().unwrap()).display().to_string();
+        let sequencer = EventSequencer::default();
+        let mut recorder = EventRecorder::default();
+        recorder.set_save_path(Some(path.clone()));
+        recorder.start(header()).unwrap();
+        recorder.record_event(sequencer.stamp(BrowserEvent::TabCreated { id: 0, url: "https://example.com".to_string() }));
+
+        // Saving onto the live file keeps it, and recording carries on in it
+        recorder.save(&alias).unwrap();
+        recorder.record_event(sequencer.stamp(BrowserEvent::TabClosed { id: 0 }));
+        recorder.stop();
+        let mut player = EventPlayer::default();
+        player.load(&path).unwrap();
+        assert_eq!(player.len(), 2);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_live_records_and_contents() {
+        let sequencer = EventSequencer::default();
+        let mut recorder = EventRecorder::default();
+        recorder.start(header()).unwrap();
+        assert!(recorder.record_event(sequencer.stamp(BrowserEvent::TabClosed { id: 0 })).is_none());
+        recorder.set_live(true);
+        let live = recorder.record_event(sequencer.stamp(BrowserEvent::TabActivated { id: 1 })).unwrap();
+        assert_eq!(live.envelope.event_type, "tab_activated");
+        recorder.stop();
+
+        let status = recorder.status();
+        assert_eq!((status.recording, status.live, status.events, status.path), (false, true, 2, None));
+        // A buffered recording comes back in the file format, header first
+        let contents = recorder.contents().unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(Some(&serde_json::from_str::<RecordingHeader>(lines[0]).unwrap()), recorder.header());
+        assert_eq!(lines[2], serde_json::to_string(&live).unwrap());
+    }
+
+    #[test]
+    fn test_load_skips_torn_last_line() {
+        let path = temp_path("torn");
+        let sequencer = EventSequencer::default();
+        let record = EventRecord {
+            timestamp_ms: 5,
+            envelope: sequencer.stamp(BrowserEvent::TabClosed { id: 0 }),
+        };
+        let text = format!(
+            "{}\n{}\n{{\"timestamp_ms\":9,\"schema_ver",
+            serde_json::to_string(&header()).unwrap(),
+            serde_json::to_string(&record).unwrap()
+        );
+        fs::write(&path, text).unwrap();
+
+        let mut player = EventPlayer::default();
+        player.load(&path).unwrap();
+        assert_eq!(player.len(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    fn player_with(offsets: &[u64]) -> EventPlayer {
+        let sequencer = EventSequencer::default();
+        let events = offsets.iter()
+            .enumerate()
+            .map(|(id, &timestamp_ms)| EventRecord {
+                timestamp_ms,
+                envelope: sequencer.stamp(if id == 2 {
+                    BrowserEvent::Error { message: "boom".to_string() }
+                } else {
+                    BrowserEvent::TabActivated { id }
+                }),
+            })
+            .collect();
+        EventPlayer { events, ..EventPlayer::default() }
+    }
+
+    #[test]
+    fn test_pause_preserves_timeline() {
+        let mut player = player_with(&[0, 60_000]);
+        player.start();
+        assert!(player.next_event().is_some());
+        player.pause();
+        let paused_at = player.position_ms();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(player.position_ms(), paused_at);
+        assert!(player.next_event().is_none());
+
+        player.resume();
+        assert_eq!(player.state(), ReplayState::Playing);
+        assert!(player.position_ms() < 1_000.0);
+    }
+
+    #[test]
+    fn test_step_and_seek() {
+        let mut player = player_with(&[0, 60_000, 120_000, 180_000]);
+        player.start();
+        player.step();
+        assert!(player.next_event().is_some());
+        // Far in the future, but a step lets it through anyway
+        player.step();
+        assert!(player.next_event().is_some());
+        assert!(player.next_event().is_none());
+        assert_eq!(player.status().position_ms, 60_000);
+
+        player.seek(SeekTarget::TimestampMs(150_000));
+        assert_eq!(player.status().index, 3);
+        player.seek(SeekTarget::Index(1));
+        assert_eq!(player.status().position_ms, 60_000);
+    }
+
+    #[test]
+    fn test_page_clock_follows_the_timeline() {
+        let mut player = player_with(&[0, 40, 60_000]);
+        player.set_speed(2.0);
+        player.start();
+        assert!(player.next_event().is_some());
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(player.next_event().is_some());
+        // Pages run on between events, at the replay's speed
+        let clock = player.page_clock();
+        assert!(clock.offset_ms >= 100);
+        assert_eq!(clock.rate, 2.0);
+
+        // A step pauses the timeline at the stepped event, and the page clock with it
+        player.step();
+        assert!(player.next_event().is_some());
+        assert_eq!(player.page_clock(), ClockReading { offset_ms: 60_000, rate: 0.0 });
+
+        player.start();
+        assert!(player.page_clock().offset_ms < 60_000);
+    }
+
+    #[test]
+    fn test_breakpoint_holds_event_until_resumed() {
+        let mut player = player_with(&[0, 0, 0, 0]);
+        player.add_breakpoint(Breakpoint::EventType("error".to_string()));
+        player.start();
+        assert!(player.next_event().is_some());
+        assert!(player.next_event().is_some());
+
+        // Stops before the error...
+        assert!(player.next_event().is_none());
+        assert_eq!(player.state(), ReplayState::Paused);
+        assert_eq!(player.status().held_at, Some(2));
+
+        // ...and lets it through on resume
+        player.resume();
+        assert!(matches!(player.next_event(), Some(BrowserEvent::Error { .. })));
+        assert!(player.next_event().is_some());
+        assert!(player.next_event().is_none());
+        assert_eq!(player.state(), ReplayState::Stopped);
+    }
+
+    #[test]
+    fn test_load_legacy_array() {
+        let path = temp_path("legacy");
+        fs::write(&path, r#"[{"timestamp_ms":0,"event":{"TabActivated":{"id":3}}}]"#).unwrap();
+
+        let mut player = EventPlayer::default();
+        player.load(&path).unwrap();
+        assert!(player.header().is_none());
+        assert_eq!(player.len(), 1);
+        assert_eq!(player.events[0].envelope.tab_id, Some(3));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,187 @@
+//! Turns recorded events back into browser actions.
+//!
+//! Every `BrowserEvent` variant maps to one `ReplayAction`. Events that record
+//! something the user or a controller did are replayed; events that follow
+//! from those (page loads, titles, URL changes) are `Derived` and happen again
+//! on their own; the rest cannot be replayed and are listed in the report.
+
+use std::collections::HashMap;
+use serde::Serialize;
+use crate::event::BrowserEvent;
+use super::input_capture;
+
+/// What replaying one recorded event does
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayAction {
+    CreateTab { recorded_id: usize, url: String },
+    CloseTab { recorded_id: usize },
+    SwitchTab { recorded_id: usize },
+    /// Navigate the active tab
+    Navigate { url: String },
+    /// Run a script in the active tab that dispatches captured page input
+    DispatchInput { summary: String, script: String },
+    /// Reproduced by replaying another event
+    Derived,
+    /// Nothing in the browser can reproduce it
+    Unsupported { reason: &'static str },
+}
+
+impl ReplayAction {
+    pub fn for_event(event: &BrowserEvent) -> Self {
+        match event {
+            BrowserEvent::TabCreated { id, url } => ReplayAction::CreateTab { recorded_id: *id, url: url.clone() },
+            BrowserEvent::TabClosed { id } => ReplayAction::CloseTab { recorded_id: *id },
+            BrowserEvent::TabActivated { id } => ReplayAction::SwitchTab { recorded_id: *id },
+            BrowserEvent::Navigation { url } => ReplayAction::Navigate { url: url.clone() },
+            // Navigation publishes the tab's URL change, and the page reports loads and titles itself
+            BrowserEvent::TabUrlChanged { .. }
+            | BrowserEvent::PageLoaded { .. }
+            | BrowserEvent::TitleChanged { .. }
+            | BrowserEvent::TabTitleChanged { .. } => ReplayAction::Derived,
+            BrowserEvent::Error { .. } => ReplayAction::Unsupported { reason: "errors are not reproduced" },
+            BrowserEvent::CommandReceived { .. }
+            | BrowserEvent::CommandExecuted { .. }
+            | BrowserEvent::CommandRejected { .. }
+            | BrowserEvent::ApiAudit { .. } => ReplayAction::Unsupported {
+                reason: "command bookkeeping; the command's own events are replayed instead",
+            },
+            BrowserEvent::TextInput { redacted: true, .. } => ReplayAction::Unsupported {
+                reason: "password values are not recorded",
+            },
+            BrowserEvent::Click { .. }
+            | BrowserEvent::TextInput { .. }
+            | BrowserEvent::FormSubmitted { .. }
+            | BrowserEvent::Scrolled { .. }
+            | BrowserEvent::KeyPressed { .. } => match (input_capture::describe(event), input_capture::replay_script(event)) {
+                (Some(summary), Some(script)) => ReplayAction::DispatchInput { summary, script },
+                _ => ReplayAction::Unsupported { reason: "not page input" },
+            },
+        }
+    }
+}
+
+/// How a replayed event was handled
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayOutcome {
+    Applied,
+    Derived,
+    Skipped(String),
+}
+
+/// A recorded event the replay could not reproduce
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SkippedEvent {
+    /// Position of the event in the replay
+    pub index: usize,
+    pub event_type: String,
+    pub reason: String,
+}
+
+/// Summary of a replay
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReplayReport {
+    pub applied: usize,
+    pub derived: usize,
+    pub skipped: Vec<SkippedEvent>,
+}
+
+impl ReplayReport {
+    pub fn total(&self) -> usize {
+        self.applied + self.derived + self.skipped.len()
+    }
+}
+
+/// Replay state: which live tab each recorded tab became, and what happened so far
+#[derive(Debug, Default)]
+pub struct ReplayExecutor {
+    tab_ids: HashMap<usize, usize>,
+    report: ReplayReport,
+}
+
+impl ReplayExecutor {
+    /// Live tab standing in for a recorded one
+    pub fn live_id(&self, recorded_id: usize) -> Result<usize, String> {
+        self.tab_ids.get(&recorded_id)
+            .copied()
+            .ok_or_else(|| format!("Tab {} was not created during the replay", recorded_id))
+    }
+
+    /// Recorded tab a live one stands in for
+    pub fn recorded_id(&self, live_id: usize) -> Option<usize> {
+        self.tab_ids.iter()
+            .find(|(_, live)| **live == live_id)
+            .map(|(recorded, _)| *recorded)
+    }
+
+    pub fn map_tab(&mut self, recorded_id: usize, live_id: usize) {
+        self.tab_ids.insert(recorded_id, live_id);
+    }
+
+    pub fn forget_tab(&mut self, recorded_id: usize) {
+        self.tab_ids.remove(&recorded_id);
+    }
+
+    pub fn record(&mut self, event: &BrowserEvent, outcome: ReplayOutcome) {
+        match outcome {
+            ReplayOutcome::Applied => self.report.applied += 1,
+            ReplayOutcome::Derived => self.report.derived += 1,
+            ReplayOutcome::Skipped(reason) => {
+                let index = self.report.total();
+                self.report.skipped.push(SkippedEvent {
+                    index,
+                    event_type: event.event_type().to_string(),
+                    reason,
+                });
+            }
+        }
+    }
+
+    pub fn report(&self) -> &ReplayReport {
+        &self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actions_for_events() {
+        let created = BrowserEvent::TabCreated { id: 4, url: "https://example.com".to_string() };
+        assert_eq!(
+            ReplayAction::for_event(&created),
+            ReplayAction::CreateTab { recorded_id: 4, url: "https://example.com".to_string() }
+        );
+        assert_eq!(ReplayAction::for_event(&BrowserEvent::PageLoaded { url: String::new() }), ReplayAction::Derived);
+        assert!(matches!(
+            ReplayAction::for_event(&BrowserEvent::Error { message: String::new() }),
+            ReplayAction::Unsupported { .. }
+        ));
+
+        let click = BrowserEvent::Click { selector: "#go".to_string(), x: 1.0, y: 2.0, button: 0 };
+        match ReplayAction::for_event(&click) {
+            ReplayAction::DispatchInput { summary, script } => {
+                assert_eq!(summary, "click on #go");
+                assert!(script.contains("tinkerReplayInput("));
+            }
+            other => panic!("unexpected action: {:?}", other),
+        }
+        let password = BrowserEvent::TextInput { selector: "#pw".to_string(), value: String::new(), redacted: true };
+        assert!(matches!(ReplayAction::for_event(&password), ReplayAction::Unsupported { .. }));
+    }
+
+    #[test]
+    fn test_report_indexes_skipped_events() {
+        let mut executor = ReplayExecutor::default();
+        let error = BrowserEvent::Error { message: "boom".to_string() };
+        executor.record(&BrowserEvent::TabActivated { id: 0 }, ReplayOutcome::Applied);
+        executor.record(&BrowserEvent::TitleChanged { title: String::new() }, ReplayOutcome::Derived);
+        executor.record(&error, ReplayOutcome::Skipped("errors are not reproduced".to_string()));
+
+        let report = executor.report();
+        assert_eq!(report.total(), 3);
+        assert_eq!(report.skipped[0].index, 2);
+        assert_eq!(report.skipped[0].event_type, "error");
+        assert!(executor.live_id(7).is_err());
+    }
+}
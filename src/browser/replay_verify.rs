@@ -0,0 +1,371 @@
+//! Checks that a replay reproduced the recording.
+//!
+//! While verifying, the events the browser emits during a replay are captured
+//! with their offset on the recording's timeline. Once the replay has finished
+//! and settled, they are aligned with the recorded events of the same types:
+//! an expected event matches an observed one with the same fields (URLs
+//! normalized, ignored fields dropped) within the timing window.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use url::Url;
+use crate::event::BrowserEvent;
+
+/// Event types compared unless configured otherwise; the ones a page produces on its own
+pub const VERIFIED_EVENTS: [&str; 3] = ["page_loaded", "title_changed", "tab_url_changed"];
+
+/// How tolerant the comparison is
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyOptions {
+    pub event_types: Vec<String>,
+    /// How far, in timeline milliseconds, an observed event may drift from the recorded one
+    pub window_ms: u64,
+    /// Fields left out of the comparison, e.g. `title`
+    pub ignore_fields: Vec<String>,
+    /// Compare URLs without their query string
+    pub ignore_query: bool,
+    /// How long to keep capturing after the last replayed event, for pages still loading
+    pub settle: Duration,
+    /// Where to write the JSON report
+    pub report_path: Option<PathBuf>,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            event_types: VERIFIED_EVENTS.iter().map(|event_type| event_type.to_string()).collect(),
+            window_ms: 5_000,
+            ignore_fields: Vec::new(),
+            ignore_query: false,
+            settle: Duration::from_secs(2),
+            report_path: None,
+        }
+    }
+}
+
+impl VerifyOptions {
+    pub fn verifies(&self, event_type: &str) -> bool {
+        self.event_types.iter().any(|verified| verified == event_type)
+    }
+
+    /// Comparable form of an event's fields, with its tab id replaced by `tab_id` if given
+    fn fields(&self, event: &BrowserEvent, tab_id: Option<usize>) -> Map<String, Value> {
+        // Events serialize as `{"Variant": {fields}}`
+        let mut fields = match serde_json::to_value(event) {
+            Ok(Value::Object(variant)) => match variant.into_iter().next() {
+                Some((_, Value::Object(fields))) => fields,
+                _ => Map::new(),
+            },
+            _ => Map::new(),
+        };
+        if let Some(tab_id) = tab_id {
+            fields.insert("id".to_string(), Value::from(tab_id));
+        }
+        for field in &self.ignore_fields {
+            fields.remove(field);
+        }
+        if let Some(Value::String(url)) = fields.get_mut("url") {
+            *url = normalize_url(url, self.ignore_query);
+        }
+        fields
+    }
+}
+
+/// Canonical form of a URL: lowercase scheme and host, no fragment, and no query if asked
+pub fn normalize_url(url: &str, ignore_query: bool) -> String {
+    match Url::parse(url.trim()) {
+        Ok(mut url) => {
+            url.set_fragment(None);
+            if ignore_query {
+                url.set_query(None);
+            }
+            url.to_string()
+        }
+        Err(_) => url.trim().to_string(),
+    }
+}
+
+/// An event and where on the recording's timeline it happened
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimedEvent {
+    pub offset_ms: u64,
+    pub event_type: String,
+    pub fields: Map<String, Value>,
+}
+
+impl fmt::Display for TimedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.event_type, Value::Object(self.fields.clone()))
+    }
+}
+
+/// One way the replay differed from the recording
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Difference {
+    /// Recorded but never seen during the replay
+    Missing { expected: TimedEvent },
+    /// Seen during the replay but not in the recording
+    Unexpected { observed: TimedEvent },
+    /// Seen, but outside the timing window
+    Mistimed { expected: TimedEvent, observed_ms: u64 },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Missing { expected } => {
+                write!(f, "- missing    {} at {} ms", expected, expected.offset_ms)
+            }
+            Difference::Unexpected { observed } => {
+                write!(f, "+ unexpected {} at {} ms", observed, observed.offset_ms)
+            }
+            Difference::Mistimed { expected, observed_ms } => write!(
+                f,
+                "~ mistimed   {} expected at {} ms, observed at {} ms",
+                expected, expected.offset_ms, observed_ms
+            ),
+        }
+    }
+}
+
+/// Outcome of a verified replay
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VerifyReport {
+    pub passed: bool,
+    pub expected: usize,
+    pub observed: usize,
+    pub matched: usize,
+    pub differences: Vec<Difference>,
+}
+
+impl VerifyReport {
+    /// Write the report as JSON
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, json)
+    }
+}
+
+/// Readable diff, one line per difference
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Replay verification {}: {} of {} expected events matched, {} observed",
+            if self.passed { "passed" } else { "FAILED" },
+            self.matched,
+            self.expected,
+            self.observed
+        )?;
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// Captures events during a replay and compares them with the recording
+#[derive(Debug)]
+pub struct ReplayVerifier {
+    options: VerifyOptions,
+    expected: Vec<TimedEvent>,
+    observed: Vec<TimedEvent>,
+    /// Set once a replay starts; nothing is captured before
+    armed: bool,
+    /// When the replay ended; the report is due once `settle` has passed
+    finished_at: Option<Instant>,
+}
+
+impl ReplayVerifier {
+    pub fn new(options: VerifyOptions) -> Self {
+        Self {
+            options,
+            expected: Vec::new(),
+            observed: Vec::new(),
+            armed: false,
+            finished_at: None,
+        }
+    }
+
+    pub fn options(&self) -> &VerifyOptions {
+        &self.options
+    }
+
+    /// Start over with the recorded events of a new replay
+    pub fn reset<'a>(&mut self, recorded: impl IntoIterator<Item = (u64, &'a BrowserEvent)>) {
+        self.expected = recorded.into_iter()
+            .filter_map(|(offset_ms, event)| self.timed(offset_ms, event, None))
+            .collect();
+        self.observed.clear();
+        self.armed = true;
+        self.finished_at = None;
+    }
+
+    fn timed(&self, offset_ms: u64, event: &BrowserEvent, tab_id: Option<usize>) -> Option<TimedEvent> {
+        let event_type = event.event_type();
+        self.options.verifies(event_type).then(|| TimedEvent {
+            offset_ms,
+            event_type: event_type.to_string(),
+            fields: self.options.fields(event, tab_id),
+        })
+    }
+
+    /// Capture an event emitted by the browser; `recorded_tab` is the recorded id of its live tab
+    pub fn observe(&mut self, offset_ms: u64, event: &BrowserEvent, recorded_tab: Option<usize>) {
+        if let Some(observed) = self.timed(offset_ms, event, recorded_tab) {
+            self.observed.push(observed);
+        }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    /// Note that the replay ended; later calls keep the first time
+    pub fn finish(&mut self) {
+        if self.armed {
+            self.finished_at.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Time spent settling since the replay ended
+    pub fn settling(&self) -> Duration {
+        self.finished_at.map(|at| at.elapsed()).unwrap_or_default()
+    }
+
+    /// Whether the replay ended long enough ago for late page events to have arrived
+    pub fn is_due(&self) -> bool {
+        self.finished_at.is_some_and(|at| at.elapsed() >= self.options.settle)
+    }
+
+    /// Align expected and observed events in order
+    pub fn report(&self) -> VerifyReport {
+        let mut used = vec![false; self.observed.len()];
+        let mut differences = Vec::new();
+        let mut matched = 0;
+
+        for expected in &self.expected {
+            let closest = self.observed.iter()
+                .enumerate()
+                .filter(|(i, observed)| {
+                    !used[*i] && observed.event_type == expected.event_type && observed.fields == expected.fields
+                })
+                .min_by_key(|(_, observed)| observed.offset_ms.abs_diff(expected.offset_ms));
+            match closest {
+                Some((i, observed)) => {
+                    used[i] = true;
+                    if observed.offset_ms.abs_diff(expected.offset_ms) <= self.options.window_ms {
+                        matched += 1;
+                    } else {
+                        differences.push(Difference::Mistimed {
+                            expected: expected.clone(),
+                            observed_ms: observed.offset_ms,
+                        });
+                    }
+                }
+                None => differences.push(Difference::Missing { expected: expected.clone() }),
+            }
+        }
+        differences.extend(self.observed.iter()
+            .zip(used)
+            .filter(|(_, used)| !used)
+            .map(|(observed, _)| Difference::Unexpected { observed: observed.clone() }));
+
+        VerifyReport {
+            passed: differences.is_empty(),
+            expected: self.expected.len(),
+            observed: self.observed.len(),
+            matched,
+            differences,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded(url: &str) -> BrowserEvent {
+        BrowserEvent::PageLoaded { url: url.to_string() }
+    }
+
+    fn verifier(options: VerifyOptions, recorded: &[(u64, BrowserEvent)]) -> ReplayVerifier {
+        let mut verifier = ReplayVerifier::new(options);
+        verifier.reset(recorded.iter().map(|(offset, event)| (*offset, event)));
+        verifier
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url("HTTPS://Example.COM", false), "https://example.com/");
+        assert_eq!(normalize_url("https://example.com/a?b=1#top", false), "https://example.com/a?b=1");
+        assert_eq!(normalize_url("https://example.com/a?b=1", true), "https://example.com/a");
+        assert_eq!(normalize_url("not a url ", false), "not a url");
+    }
+
+    #[test]
+    fn test_matching_replay_passes() {
+        let recorded = [
+            (0, BrowserEvent::TabActivated { id: 0 }),
+            (100, loaded("https://example.com")),
+            (150, BrowserEvent::TitleChanged { title: "Example".to_string() }),
+        ];
+        let mut verifier = verifier(VerifyOptions::default(), &recorded);
+        // Out of order and a little late, but within the window
+        verifier.observe(900, &BrowserEvent::TitleChanged { title: "Example".to_string() }, None);
+        verifier.observe(800, &loaded("https://example.com/#intro"), None);
+        verifier.observe(800, &BrowserEvent::TabActivated { id: 3 }, Some(0));
+
+        let report = verifier.report();
+        assert!(report.passed, "{}", report);
+        assert_eq!((report.expected, report.observed, report.matched), (2, 2, 2));
+    }
+
+    #[test]
+    fn test_differences() {
+        let recorded = [
+            (100, loaded("https://example.com/a")),
+            (200, loaded("https://example.com/b")),
+            (300, BrowserEvent::TitleChanged { title: "B".to_string() }),
+        ];
+        let options = VerifyOptions { window_ms: 1_000, ..VerifyOptions::default() };
+        let mut verifier = verifier(options, &recorded);
+        verifier.observe(150, &loaded("https://example.com/a"), None);
+        verifier.observe(5_000, &loaded("https://example.com/b"), None);
+        verifier.observe(320, &BrowserEvent::TitleChanged { title: "C".to_string() }, None);
+
+        let report = verifier.report();
+        assert!(!report.passed);
+        assert_eq!(report.matched, 1);
+        assert!(matches!(report.differences[0], Difference::Mistimed { observed_ms: 5_000, .. }));
+        assert!(matches!(report.differences[1], Difference::Missing { .. }));
+        assert!(matches!(report.differences[2], Difference::Unexpected { .. }));
+
+        let text = report.to_string();
+        assert!(text.starts_with("Replay verification FAILED: 1 of 3"));
+        assert!(text.contains(r#"- missing    title_changed {"title":"B"} at 300 ms"#));
+    }
+
+    #[test]
+    fn test_tab_ids_and_ignored_fields() {
+        let url_changed = |id| BrowserEvent::TabUrlChanged { id, url: "https://example.com/".to_string() };
+        let recorded = [(0, url_changed(0))];
+
+        // Live tab 5 stands in for recorded tab 0
+        let mut mapped = verifier(VerifyOptions::default(), &recorded);
+        mapped.observe(10, &url_changed(5), Some(0));
+        assert!(mapped.report().passed);
+
+        let options = VerifyOptions { ignore_fields: vec!["id".to_string()], ..VerifyOptions::default() };
+        let mut ignored = verifier(options, &recorded);
+        ignored.observe(10, &url_changed(5), None);
+        assert!(ignored.report().passed);
+    }
+}
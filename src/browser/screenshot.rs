@@ -0,0 +1,115 @@
+//! Page captures for the `screenshot` command.
+//!
+//! `webdriver.js` re-renders the page's DOM into a PNG, as it does for the
+//! WebDriver and DevTools endpoints, so the same caveats apply: it is not
+//! pixels from the screen. Rendering finishes after the script has returned,
+//! so a capture runs on a thread of its own and polls the page through the
+//! engine's command queue until the image is ready.
+
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use crate::event::{BrowserCommand, CommandError, CommandOutcome, CommandResult, PendingCommand};
+
+const DRIVER_SCRIPT: &str = include_str!("../templates/webdriver.js");
+
+/// How long rendering a page may take
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Capture a tab, the active one when `tab_id` is `None`, and hand the outcome to `done`
+pub(super) fn spawn_capture<F>(commands: Sender<PendingCommand>, tab_id: Option<usize>, done: F)
+where
+    F: FnOnce(CommandOutcome) + Send + 'static,
+{
+    thread::spawn(move || done(capture(&commands, tab_id)));
+}
+
+fn capture(commands: &Sender<PendingCommand>, tab_id: Option<usize>) -> CommandOutcome {
+    let (id, token) = call(commands, tab_id, "screenshot", json!([]))?;
+    let deadline = Instant::now() + CAPTURE_TIMEOUT;
+    loop {
+        // The result is kept by the page that rendered it
+        if let (_, Value::String(png)) = call(commands, Some(id), "result", json!([token]))? {
+            return Ok(CommandResult::Screenshot { id, png });
+        }
+        if Instant::now() >= deadline {
+            return Err(CommandError::new(
+                "timeout",
+                format!("Page was not captured within {} ms", CAPTURE_TIMEOUT.as_millis()),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Run one `webdriver.js` command, answering with the tab it ran in and its value
+fn call(commands: &Sender<PendingCommand>, tab_id: Option<usize>, name: &str, args: Value) -> Result<(usize, Value), CommandError> {
+    let script = format!("{}\nwindow.__tinkerWebDriver.call({}, {});", DRIVER_SCRIPT, json!(name), args);
+    let (pending, outcome) = PendingCommand::with_responder(BrowserCommand::EvaluateScript { tab_id, script });
+    commands.send(pending)
+        .map_err(|_| CommandError::new("unavailable", "No browser engine is accepting commands"))?;
+    let result = match outcome.recv_timeout(CAPTURE_TIMEOUT) {
+        Ok(outcome) => outcome?,
+        Err(RecvTimeoutError::Timeout) => {
+            return Err(CommandError::new("timeout", format!("Page did not answer within {} ms", CAPTURE_TIMEOUT.as_millis())));
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            return Err(CommandError::new("dropped", "Browser engine dropped the command without a result"));
+        }
+    };
+    match result {
+        CommandResult::ScriptResult { id, value } => driver_result(value).map(|value| (id, value)),
+        other => Err(CommandError::new("javascript", format!("Unexpected script result {:?}", other))),
+    }
+}
+
+/// What a `webdriver.js` call answered: `{"ok": value}` or `{"error": code, "message": text}`
+fn driver_result(value: Value) -> Result<Value, CommandError> {
+    match value["error"].as_str() {
+        Some(_) => Err(CommandError::new("javascript", value["message"].as_str().unwrap_or_default())),
+        None => Ok(value["ok"].clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_capture_polls_until_the_page_has_rendered() {
+        let (commands, queue) = mpsc::channel::<PendingCommand>();
+        let (done, outcome) = mpsc::channel();
+        spawn_capture(commands, None, move |result| done.send(result).unwrap());
+
+        // The page answers the capture with a token, then once with nothing, then with the image
+        let answers = [json!({"ok": 1}), json!({"ok": null}), json!({"ok": "iVBORw0KGgo="})];
+        for (index, value) in answers.into_iter().enumerate() {
+            let PendingCommand { command, responder } = queue.recv().unwrap();
+            let BrowserCommand::EvaluateScript { tab_id, script } = command else {
+                panic!("Expected a script");
+            };
+            // Polls go to the tab that rendered the image
+            assert_eq!(tab_id, if index == 0 { None } else { Some(3) });
+            assert!(script.ends_with(if index == 0 { r#"call("screenshot", []);"# } else { r#"call("result", [1]);"# }));
+            responder.unwrap().send(Ok(CommandResult::ScriptResult { id: 3, value })).unwrap();
+        }
+        assert_eq!(outcome.recv().unwrap(), Ok(CommandResult::Screenshot { id: 3, png: "iVBORw0KGgo=".to_string() }));
+    }
+
+    #[test]
+    fn test_page_errors_end_the_capture() {
+        let (commands, queue) = mpsc::channel::<PendingCommand>();
+        let (done, outcome) = mpsc::channel();
+        spawn_capture(commands, Some(0), move |result| done.send(result).unwrap());
+
+        let failed = json!({"error": "unable to capture screen", "message": "The page could not be rendered"});
+        queue.recv().unwrap().responder.unwrap()
+            .send(Ok(CommandResult::ScriptResult { id: 0, value: failed }))
+            .unwrap();
+        assert_eq!(outcome.recv().unwrap(), Err(CommandError::new("javascript", "The page could not be rendered")));
+    }
+}
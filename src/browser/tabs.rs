@@ -9,6 +9,20 @@ pub struct Tab {
     pub url: String,
     pub title: String,
     pub webview: Option<Arc<Mutex<WebView>>>,
+    /// URLs visited in this tab, oldest first
+    pub history: Vec<String>,
+    /// Position of the current page in `history`
+    pub history_index: usize,
+}
+
+impl Tab {
+    pub fn can_go_back(&self) -> bool {
+        self.history_index > 0
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        self.history_index + 1 < self.history.len()
+    }
 }
 
 impl fmt::Debug for Tab {
@@ -17,6 +31,7 @@ impl fmt::Debug for Tab {
             .field("id", &self.id)
             .field("url", &self.url)
             .field("title", &self.title)
+            .field("history_index", &self.history_index)
             .field("webview", &if self.webview.is_some() { "Some(WebView)" } else { "None" })
             .finish()
     }
@@ -44,6 +59,8 @@ impl TabManager {
 
         let tab = Tab {
             id,
+            history: vec![url.clone()],
+            history_index: 0,
             url,
             title: String::new(),
             webview: None,
@@ -129,6 +146,44 @@ impl TabManager {
         }
     }
 
+    /// Make `url` the current page of a tab, dropping any forward history
+    pub fn navigate_tab(&mut self, id: usize, url: String) -> bool {
+        if let Some(tab) = self.tabs.get_mut(&id) {
+            // Pages report their own navigation too; don't record the same page twice
+            if tab.history.get(tab.history_index) != Some(&url) {
+                tab.history.truncate(tab.history_index + 1);
+                tab.history.push(url.clone());
+                tab.history_index = tab.history.len() - 1;
+            }
+            tab.url = url;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Step back in a tab's history, returning the URL to show
+    pub fn go_back(&mut self, id: usize) -> Option<String> {
+        let tab = self.tabs.get_mut(&id)?;
+        if !tab.can_go_back() {
+            return None;
+        }
+        tab.history_index -= 1;
+        tab.url = tab.history[tab.history_index].clone();
+        Some(tab.url.clone())
+    }
+
+    /// Step forward in a tab's history, returning the URL to show
+    pub fn go_forward(&mut self, id: usize) -> Option<String> {
+        let tab = self.tabs.get_mut(&id)?;
+        if !tab.can_go_forward() {
+            return None;
+        }
+        tab.history_index += 1;
+        tab.url = tab.history[tab.history_index].clone();
+        Some(tab.url.clone())
+    }
+
     pub fn get_tab_mut(&mut self, id: usize) -> Option<&mut Tab> {
         self.tabs.get_mut(&id)
     }
@@ -194,6 +249,25 @@ mod tests {
         assert_eq!(manager.get_tab_info(id).unwrap().url, "https://new-url.com");
     }
 
+    #[test]
+    fn test_tab_history() {
+        let mut manager = TabManager::new();
+        let id = manager.create_tab("https://example.com/1".to_string());
+        manager.navigate_tab(id, "https://example.com/2".to_string());
+        manager.navigate_tab(id, "https://example.com/2".to_string());
+        manager.navigate_tab(id, "https://example.com/3".to_string());
+
+        assert_eq!(manager.go_back(id).as_deref(), Some("https://example.com/2"));
+        assert_eq!(manager.go_back(id).as_deref(), Some("https://example.com/1"));
+        assert_eq!(manager.go_back(id), None);
+        assert_eq!(manager.go_forward(id).as_deref(), Some("https://example.com/2"));
+
+        // Navigating from the middle of the history drops the forward entries
+        manager.navigate_tab(id, "https://example.com/4".to_string());
+        assert_eq!(manager.go_forward(id), None);
+        assert_eq!(manager.get_tab(id).unwrap().history.len(), 3);
+    }
+
     #[test]
     fn test_get_all_tabs() {
         let mut manager = TabManager::new();
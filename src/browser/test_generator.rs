@@ -0,0 +1,438 @@
+//! Turns a recording into an integration test.
+//!
+//! The recording is reduced to the tab, navigation and input steps a replay would
+//! perform (see `ReplayAction`), each with the page URL and title the
+//! recording saw before the next step.
+//!
+//! Two flavours are generated. The Rust test drives a headless `BrowserEngine`
+//! through the library API; no page runs there, so it checks tabs and URLs,
+//! checks titles only once a page has reported one, and notes page input in
+//! comments. The Node.js script sends the same steps over the MQTT command
+//! protocol to a running Tinker and also waits for each page to reach its
+//! recorded URL and title.
+
+use std::fmt::Write;
+use serde_json::json;
+use crate::event::{BrowserEvent, COMMAND_TOPIC, DEFAULT_COMMAND_TIMEOUT_MS};
+use super::replay::Recording;
+use super::replay_executor::ReplayAction;
+
+/// One replayable step and what followed it in the recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub offset_ms: u64,
+    pub action: ReplayAction,
+    /// URL the step sends its tab to
+    pub url: Option<String>,
+    /// Last URL a page finished loading before the next step
+    pub loaded_url: Option<String>,
+    /// Last page title before the next step
+    pub title: Option<String>,
+}
+
+/// Steps to reproduce a recording, and how many events had no step
+pub fn steps(recording: &Recording) -> (Vec<Step>, usize) {
+    let mut steps: Vec<Step> = Vec::new();
+    let mut unsupported = 0;
+
+    for record in &recording.records {
+        let event = &record.envelope.event;
+        match ReplayAction::for_event(event) {
+            ReplayAction::Derived => {
+                let Some(step) = steps.last_mut() else {
+                    continue;
+                };
+                match event {
+                    BrowserEvent::PageLoaded { url } => step.loaded_url = Some(url.clone()),
+                    BrowserEvent::TitleChanged { title } | BrowserEvent::TabTitleChanged { title, .. } => {
+                        step.title = Some(title.clone());
+                    }
+                    _ => {}
+                }
+            }
+            ReplayAction::Unsupported { .. } => unsupported += 1,
+            action => {
+                let url = match &action {
+                    ReplayAction::CreateTab { url, .. } | ReplayAction::Navigate { url } => Some(url.clone()),
+                    _ => None,
+                };
+                steps.push(Step {
+                    offset_ms: record.timestamp_ms,
+                    action,
+                    url,
+                    loaded_url: None,
+                    title: None,
+                });
+            }
+        }
+    }
+    (steps, unsupported)
+}
+
+/// Tabs still open at the end of the recording
+fn open_tabs(steps: &[Step]) -> usize {
+    steps.iter().fold(0usize, |open, step| match step.action {
+        ReplayAction::CreateTab { .. } => open + 1,
+        ReplayAction::CloseTab { .. } => open.saturating_sub(1),
+        _ => open,
+    })
+}
+
+/// Names a generated test function cannot take: Rust keywords, reserved
+/// words and the helpers `rust_test` defines next to it
+const TAKEN_NAMES: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "union", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+    "active", "expect_title", "run", "tab",
+];
+
+/// Identifier-safe form of a test name.
+///
+/// Names that would not start with a letter or that are taken get a
+/// `recording_` prefix; `r#` cannot help, since `self` and friends have no raw form.
+fn identifier(name: &str) -> String {
+    let ident: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    match ident.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() && !TAKEN_NAMES.contains(&ident.as_str()) => ident,
+        _ => format!("recording_{}", ident),
+    }
+}
+
+/// A Rust integration test for `tests/`, using the `tinker` library
+pub fn rust_test(recording: &Recording, source: &str, name: &str) -> String {
+    let (steps, unsupported) = steps(recording);
+    let mut out = String::new();
+
+    let _ = writeln!(out, "//! Generated by `tinker recording generate` from {}", source);
+    if unsupported > 0 {
+        let _ = writeln!(out, "//! Left out {} recorded events that cannot be replayed", unsupported);
+    }
+    out.push_str(
+"
+// Not every recording needs every helper
+#![allow(dead_code, unused_mut)]
+
+use std::collections::HashMap;
+use tinker::browser::BrowserEngine;
+use tinker::event::{BrowserCommand, CommandResult, TabInfo};
+
+fn run(browser: &mut BrowserEngine, command: BrowserCommand) -> CommandResult {
+    let description = format!(\"{:?}\", command);
+    browser.handle_command(command).unwrap_or_else(|e| panic!(\"{} failed: {}\", description, e))
+}
+
+fn tab(browser: &BrowserEngine, id: usize) -> Option<TabInfo> {
+    browser.tab_list().into_iter().find(|tab| tab.id == id)
+}
+
+fn active(browser: &BrowserEngine) -> TabInfo {
+    browser.tab_list().into_iter().find(|tab| tab.active).expect(\"a tab is active\")
+}
+
+// Titles come from pages, which a headless engine does not run, so a tab
+// without one has nothing to compare yet
+fn expect_title(browser: &BrowserEngine, title: &str) {
+    let tab = active(browser);
+    if !tab.title.is_empty() {
+        assert_eq!(tab.title, title);
+    }
+}
+");
+
+    let _ = writeln!(out, "\n#[test]\nfn {}() {{", identifier(name));
+    out.push_str("    let mut browser = BrowserEngine::new(true, None, None);\n");
+    out.push_str("    // Recorded tab id -> tab id in this run\n");
+    out.push_str("    let mut tabs: HashMap<usize, usize> = HashMap::new();\n");
+
+    for step in &steps {
+        let _ = writeln!(out, "\n    // {} ms", step.offset_ms);
+        match &step.action {
+            ReplayAction::CreateTab { recorded_id, url } => {
+                let _ = writeln!(out, "    let id = match run(&mut browser, BrowserCommand::CreateTab {{ url: {:?}.to_string() }}) {{", url);
+                out.push_str("        CommandResult::TabCreated { id, .. } => id,\n");
+                out.push_str("        other => panic!(\"unexpected result: {:?}\", other),\n");
+                out.push_str("    };\n");
+                let _ = writeln!(out, "    tabs.insert({}, id);", recorded_id);
+                if let Some(url) = &step.url {
+                    let _ = writeln!(out, "    assert_eq!(tab(&browser, id).unwrap().url, {:?});", url);
+                }
+            }
+            ReplayAction::SwitchTab { recorded_id } => {
+                let _ = writeln!(out, "    run(&mut browser, BrowserCommand::SwitchTab {{ id: tabs[&{}] }});", recorded_id);
+                let _ = writeln!(out, "    assert_eq!(active(&browser).id, tabs[&{}]);", recorded_id);
+            }
+            ReplayAction::CloseTab { recorded_id } => {
+                let _ = writeln!(out, "    let id = tabs.remove(&{}).expect(\"tab was created\");", recorded_id);
+                out.push_str("    run(&mut browser, BrowserCommand::CloseTab { id });\n");
+                out.push_str("    assert!(tab(&browser, id).is_none());\n");
+            }
+            ReplayAction::Navigate { url } => {
+                let _ = writeln!(out, "    run(&mut browser, BrowserCommand::Navigate {{ url: {:?}.to_string() }});", url);
+                if let Some(url) = &step.url {
+                    let _ = writeln!(out, "    assert_eq!(active(&browser).url, {:?});", url);
+                }
+            }
+            ReplayAction::DispatchInput { summary, .. } => {
+                let _ = writeln!(out, "    // Page input: {}", summary);
+            }
+            ReplayAction::Derived | ReplayAction::Unsupported { .. } => {}
+        }
+        // Only a running page produces these
+        if let Some(url) = step.loaded_url.as_ref().filter(|loaded| step.url.as_ref() != Some(*loaded)) {
+            let _ = writeln!(out, "    // The page finished loading at {:?}", url);
+        }
+        // A new background tab is not active, so only check titles in the active tab
+        match &step.title {
+            Some(title) if matches!(step.action, ReplayAction::CreateTab { .. }) => {
+                let _ = writeln!(out, "    // The new tab's title became {:?}", title);
+            }
+            Some(title) => {
+                let _ = writeln!(out, "    expect_title(&browser, {:?});", title);
+            }
+            None => {}
+        }
+    }
+
+    let _ = writeln!(out, "\n    assert_eq!(browser.tab_list().len(), {});", open_tabs(&steps));
+    out.push_str("}\n");
+    out
+}
+
+/// JavaScript string literal for `text`
+fn js_string(text: &str) -> String {
+    serde_json::to_string(text).unwrap_or_default()
+}
+
+/// A Node.js script that repeats the steps over MQTT against a running Tinker
+pub fn js_script(recording: &Recording, source: &str, name: &str) -> String {
+    let (steps, unsupported) = steps(recording);
+    let mut out = String::new();
+
+    let _ = writeln!(out, "// Generated by `tinker recording generate --lang js` from {}", source);
+    out.push_str("//\n// Start Tinker with --broker-url, then run: npm install mqtt && node <this file>\n");
+    out.push_str("// TINKER_BROKER and TINKER_INSTANCE select the broker and the instance id.\n");
+    if unsupported > 0 {
+        let _ = writeln!(out, "// Left out {} recorded events that cannot be replayed", unsupported);
+    }
+    let _ = write!(out, "
+const assert = require('assert');
+const mqtt = require('mqtt');
+
+const NAME = {name};
+const BROKER = process.env.TINKER_BROKER || 'mqtt://localhost:1883';
+const PREFIX = process.env.TINKER_INSTANCE ? `tinker/${{process.env.TINKER_INSTANCE}}/` : '';
+const COMMAND_TOPIC = PREFIX + {command_topic};
+const REPLY_TOPIC = `tinker-test/${{process.pid}}/reply`;
+const TIMEOUT_MS = {timeout};
+
+const client = mqtt.connect(BROKER);
+const pending = new Map();
+let nextRequest = 0;
+
+client.on('message', (topic, payload) => {{
+  const response = JSON.parse(payload.toString());
+  const waiter = pending.get(response.request_id);
+  if (waiter) {{
+    pending.delete(response.request_id);
+    waiter(response);
+  }}
+}});
+
+// Send a command and resolve with its result, failing on an error reply or timeout
+function send(command) {{
+  const request_id = `${{NAME}}-${{nextRequest++}}`;
+  return new Promise((resolve, reject) => {{
+    const timer = setTimeout(() => reject(new Error(`No reply to ${{JSON.stringify(command)}}`)), TIMEOUT_MS);
+    pending.set(request_id, (response) => {{
+      clearTimeout(timer);
+      if (response.success) resolve(response.result);
+      else reject(new Error(`${{JSON.stringify(command)}} failed: ${{response.error.message}}`));
+    }});
+    client.publish(COMMAND_TOPIC, JSON.stringify({{ request_id, reply_to: REPLY_TOPIC, command }}));
+  }});
+}}
+
+async function tabs() {{
+  return (await send('get_tabs')).tabs.tabs;
+}}
+
+// URLs compare without fragments, the way the replay verifier does
+function sameUrl(a, b) {{
+  try {{
+    const [x, y] = [new URL(a), new URL(b)];
+    x.hash = '';
+    y.hash = '';
+    return x.href === y.href;
+  }} catch (e) {{
+    return a === b;
+  }}
+}}
+
+// Poll the active tab until it matches, since pages load and retitle on their own time
+async function expectActive(description, check) {{
+  const deadline = Date.now() + TIMEOUT_MS;
+  let tab;
+  while (Date.now() < deadline) {{
+    tab = (await tabs()).find((t) => t.active);
+    if (tab && check(tab)) return tab;
+    await new Promise((resolve) => setTimeout(resolve, 200));
+  }}
+  assert.fail(`${{description}}; active tab is ${{JSON.stringify(tab)}}`);
+}}
+",
+        name = js_string(name),
+        command_topic = js_string(COMMAND_TOPIC),
+        timeout = DEFAULT_COMMAND_TIMEOUT_MS,
+    );
+
+    out.push_str("\nasync function main() {\n  // Recorded tab id -> tab id in this run\n  const ids = {};\n");
+    for step in &steps {
+        let _ = writeln!(out, "\n  // {} ms", step.offset_ms);
+        match &step.action {
+            ReplayAction::CreateTab { recorded_id, url } => {
+                let _ = writeln!(
+                    out,
+                    "  ids[{}] = (await send({})).tab_created.id;",
+                    recorded_id,
+                    json!({ "create_tab": { "url": url } })
+                );
+            }
+            ReplayAction::SwitchTab { recorded_id } => {
+                let _ = writeln!(out, "  await send({{ switch_tab: {{ id: ids[{}] }} }});", recorded_id);
+                let _ = writeln!(
+                    out,
+                    "  await expectActive('tab {} is active', (tab) => tab.id === ids[{}]);",
+                    recorded_id, recorded_id
+                );
+            }
+            ReplayAction::CloseTab { recorded_id } => {
+                let _ = writeln!(out, "  await send({{ close_tab: {{ id: ids[{}] }} }});", recorded_id);
+                let _ = writeln!(out, "  assert(!(await tabs()).some((tab) => tab.id === ids[{}]));", recorded_id);
+            }
+            ReplayAction::Navigate { url } => {
+                let _ = writeln!(out, "  await send({});", json!({ "navigate": { "url": url } }));
+            }
+            ReplayAction::DispatchInput { summary, script } => {
+                let _ = writeln!(out, "  // {}", summary);
+                let _ = writeln!(out, "  await send({});", json!({ "evaluate_script": { "script": script } }));
+            }
+            ReplayAction::Derived | ReplayAction::Unsupported { .. } => {}
+        }
+        // A new background tab is not active, so only check pages in the active tab
+        if matches!(step.action, ReplayAction::CreateTab { .. }) {
+            continue;
+        }
+        if let Some(url) = &step.loaded_url {
+            let _ = writeln!(
+                out,
+                "  await expectActive({}, (tab) => sameUrl(tab.url, {}));",
+                js_string(&format!("page loads {}", url)),
+                js_string(url)
+            );
+        }
+        if let Some(title) = &step.title {
+            let _ = writeln!(
+                out,
+                "  await expectActive({}, (tab) => tab.title === {});",
+                js_string(&format!("title becomes {}", title)),
+                js_string(title)
+            );
+        }
+    }
+    out.push_str("}\n\n");
+    out.push_str(
+"client.on('connect', () => {
+  client.subscribe(REPLY_TOPIC, (err) => {
+    if (err) throw err;
+    main()
+      .then(() => {
+        console.log(`${NAME}: passed`);
+        client.end();
+      })
+      .catch((e) => {
+        console.error(`${NAME}: ${e.message}`);
+        client.end();
+        process.exitCode = 1;
+      });
+  });
+});
+");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Recording {
+        Recording::from_events(&[
+            (0, BrowserEvent::TabCreated { id: 0, url: "about:blank".to_string() }),
+            (100, BrowserEvent::TabActivated { id: 0 }),
+            (200, BrowserEvent::Navigation { url: "https://example.com".to_string() }),
+            (300, BrowserEvent::TabUrlChanged { id: 0, url: "https://example.com".to_string() }),
+            (400, BrowserEvent::PageLoaded { url: "https://example.com/".to_string() }),
+            (500, BrowserEvent::TitleChanged { title: "Example \"Domain\"".to_string() }),
+            (600, BrowserEvent::Click { selector: "#more".to_string(), x: 12.0, y: 30.0, button: 0 }),
+            (700, BrowserEvent::TabCreated { id: 1, url: "https://example.org".to_string() }),
+            (800, BrowserEvent::TabClosed { id: 1 }),
+            (900, BrowserEvent::Error { message: "boom".to_string() }),
+        ])
+    }
+
+    #[test]
+    fn test_steps_collect_expectations() {
+        let (steps, unsupported) = steps(&session());
+        assert_eq!(unsupported, 1);
+        assert_eq!(steps.len(), 6);
+
+        let navigate = &steps[2];
+        assert_eq!(navigate.action, ReplayAction::Navigate { url: "https://example.com".to_string() });
+        assert_eq!(navigate.url.as_deref(), Some("https://example.com"));
+        assert_eq!(navigate.loaded_url.as_deref(), Some("https://example.com/"));
+        assert_eq!(navigate.title.as_deref(), Some("Example \"Domain\""));
+        assert_eq!(open_tabs(&steps), 1);
+    }
+
+    #[test]
+    fn test_rust_test() {
+        let code = rust_test(&session(), "session.jsonl", "2024 session");
+        assert!(code.contains("fn recording_2024_session() {"));
+        assert!(code.contains(r#"BrowserCommand::Navigate { url: "https://example.com".to_string() }"#));
+        assert!(code.contains(r#"expect_title(&browser, "Example \"Domain\"");"#));
+        assert!(code.contains("// Page input: click on #more"));
+        assert!(code.contains("assert_eq!(browser.tab_list().len(), 1);"));
+    }
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(identifier("Checkout flow"), "checkout_flow");
+        assert_eq!(identifier("2024 session"), "recording_2024_session");
+        assert_eq!(identifier("_private"), "recording__private");
+        assert_eq!(identifier("Self"), "recording_self");
+        assert_eq!(identifier("type"), "recording_type");
+        assert_eq!(identifier("run"), "recording_run");
+        assert_eq!(identifier(""), "recording_");
+    }
+
+    // tests/generated_recording_tests.rs is this output, so cargo compiles and
+    // runs what the generator writes; regenerate it when the generator changes
+    #[test]
+    fn test_rust_test_matches_the_compiled_fixture() {
+        let code = rust_test(&session(), "session.jsonl", "2024 session");
+        assert_eq!(code, include_str!("../../tests/generated_recording_tests.rs"));
+    }
+
+    #[test]
+    fn test_js_script() {
+        let script = js_script(&session(), "session.jsonl", "session");
+        assert!(script.contains(r#"await send({"navigate":{"url":"https://example.com"}});"#));
+        assert!(script.contains(r#"(tab) => tab.title === "Example \"Domain\"""#));
+        assert!(script.contains("  // click on #more\n  await send({\"evaluate_script\":{\"script\":"));
+        assert!(script.contains("const COMMAND_TOPIC = PREFIX + \"browser/command\";"));
+    }
+}
@@ -0,0 +1,422 @@
+//! Access control for incoming commands.
+//!
+//! A `CommandPolicy` maps command sources to the commands and URLs they may
+//! use. A source is named by the `source` field of a `CommandRequest`; sources
+//! configured with a `secret` must also send a `token`, `<expires>.<signature>`
+//! where `expires` is a Unix time in seconds at most five minutes ahead and
+//! `signature` the hex HMAC-SHA256 of `<source>:<request_id>:<expires>:<command>`,
+//! the command being compact JSON with sorted keys (see `sign_request`). A
+//! token is good for that one command, once, until it expires. Requests from
+//! unknown or unnamed sources, and bare commands, fall under the `default` rules.
+//! Recording controls that write files also need `write_files`, which `*`
+//! does not imply.
+//!
+//! ```json
+//! {
+//!   "default": { "commands": ["get_tabs"] },
+//!   "sources": {
+//!     "ci": { "secret": "change-me", "commands": ["*"], "urls": ["https://*.example.com/*"] }
+//!   }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ring::hmac;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use super::{BrowserCommand, BrowserEvent, CommandError, CommandRequest};
+
+/// Longest a request token may be valid for, in seconds
+pub const MAX_TOKEN_LIFETIME_SECS: u64 = 300;
+
+/// Signed requests already accepted, by source and request id, with when their tokens expire.
+/// Shared between clones of a policy and ignored when comparing them.
+#[derive(Debug, Clone, Default)]
+pub struct SeenRequests(Arc<Mutex<HashMap<(String, String), u64>>>);
+
+impl PartialEq for SeenRequests {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl SeenRequests {
+    /// Remember a request, or say it was seen before; expired entries are forgotten
+    fn first_sighting(&self, source: &str, request_id: &str, expires: u64, now: u64) -> bool {
+        let Ok(mut seen) = self.0.lock() else {
+            return false;
+        };
+        seen.retain(|_, until| *until >= now);
+        seen.insert((source.to_string(), request_id.to_string()), expires).is_none()
+    }
+}
+
+/// What one source may do
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceRules {
+    /// Shared secret for verifying request tokens; without one the `source` name is trusted as sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// Allowed command names, e.g. `navigate`, or `*` for all
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// URL patterns (`*` matches anything) allowed for `navigate`, `create_tab` and the pages `play_event` loads; any URL when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub urls: Option<Vec<String>>,
+    /// May start or save recordings to files on the browser's machine
+    #[serde(default)]
+    pub write_files: bool,
+}
+
+impl SourceRules {
+    pub fn allow_all() -> Self {
+        Self {
+            secret: None,
+            commands: vec!["*".to_string()],
+            urls: None,
+            write_files: false,
+        }
+    }
+
+    pub fn allows_command(&self, name: &str) -> bool {
+        self.commands.iter().any(|allowed| allowed == "*" || allowed == name)
+    }
+
+    pub fn allows_url(&self, url: &str) -> bool {
+        match &self.urls {
+            Some(patterns) => patterns.iter().any(|pattern| wildcard_match(pattern, url)),
+            None => true,
+        }
+    }
+}
+
+/// Which commands are accepted, and from whom
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandPolicy {
+    /// Ignore incoming commands entirely and only publish events
+    #[serde(default)]
+    pub observer: bool,
+    /// Rules for requests without a configured source; nothing is allowed when omitted
+    #[serde(default)]
+    pub default: SourceRules,
+    #[serde(default)]
+    pub sources: HashMap<String, SourceRules>,
+    #[serde(skip)]
+    pub seen: SeenRequests,
+}
+
+/// Without a policy file every command is accepted, as before access control existed
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        Self {
+            observer: false,
+            default: SourceRules::allow_all(),
+            sources: HashMap::new(),
+            seen: SeenRequests::default(),
+        }
+    }
+}
+
+impl CommandPolicy {
+    /// Read a policy from a JSON file
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read command policy {}: {}", path.display(), e))?;
+        serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid command policy {}: {}", path.display(), e).into())
+    }
+
+    /// Ignore every incoming command and only publish events
+    pub fn observer() -> Self {
+        Self {
+            observer: true,
+            ..Self::default()
+        }
+    }
+
+    /// Check a command against the rules for whoever sent it
+    pub fn authorize(&self, request: Option<&CommandRequest>, command: &BrowserCommand) -> Result<(), CommandRejection> {
+        if self.observer {
+            return Err(CommandRejection::ObserverMode);
+        }
+        let source = request.and_then(|request| request.source.as_deref());
+        let rules = match source.and_then(|source| self.sources.get(source).map(|rules| (source, rules))) {
+            Some((source, rules)) => {
+                if let (Some(secret), Some(request)) = (&rules.secret, request) {
+                    let now = unix_now();
+                    let verified = verify_token(secret, source, request, command, now)
+                        .filter(|expires| self.seen.first_sighting(source, &request.request_id, *expires, now));
+                    if verified.is_none() {
+                        return Err(CommandRejection::InvalidToken { source: source.to_string() });
+                    }
+                }
+                rules
+            }
+            None => &self.default,
+        };
+
+        if !rules.allows_command(command.name()) {
+            return Err(CommandRejection::CommandNotAllowed { command: command.name().to_string() });
+        }
+        if let (BrowserCommand::Recording(control), false) = (command, rules.write_files) {
+            if let Some(path) = control.writes_file() {
+                return Err(CommandRejection::FileNotAllowed { path: path.to_string() });
+            }
+        }
+        match command.url() {
+            Some(url) if !rules.allows_url(url) => Err(CommandRejection::UrlNotAllowed { url: url.to_string() }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Why a command was refused
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CommandRejection {
+    CommandNotAllowed { command: String },
+    UrlNotAllowed { url: String },
+    FileNotAllowed { path: String },
+    InvalidToken { source: String },
+    /// The browser only publishes events
+    ObserverMode,
+}
+
+impl fmt::Display for CommandRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandRejection::CommandNotAllowed { command } => write!(f, "Command '{}' is not allowed", command),
+            CommandRejection::UrlNotAllowed { url } => write!(f, "URL '{}' is not allowed", url),
+            CommandRejection::FileNotAllowed { path } => write!(f, "Writing the file '{}' is not allowed", path),
+            CommandRejection::InvalidToken { source } => write!(f, "Missing or invalid token for source '{}'", source),
+            CommandRejection::ObserverMode => write!(f, "Observer mode ignores commands"),
+        }
+    }
+}
+
+impl From<&CommandRejection> for CommandError {
+    fn from(rejection: &CommandRejection) -> Self {
+        CommandError::new("forbidden", rejection.to_string())
+    }
+}
+
+/// Event published when a command is refused
+pub fn rejected_event(command: &str, request: Option<&CommandRequest>, reason: CommandRejection) -> BrowserEvent {
+    BrowserEvent::CommandRejected {
+        command: command.to_string(),
+        source: request.and_then(|request| request.source.clone()),
+        reason,
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or_default()
+}
+
+/// What a token signs: the request's identity, its expiry and the exact command
+fn signed_message(source: &str, request_id: &str, expires: u64, command: &BrowserCommand) -> String {
+    // Going through `Value` sorts object keys, so other languages can rebuild the same text
+    let command = serde_json::to_value(command).map(|command| command.to_string()).unwrap_or_default();
+    format!("{}:{}:{}:{}", source, request_id, expires, command)
+}
+
+/// Token a source with `secret` sends along with request `request_id` for
+/// `command`, valid until `expires` (Unix seconds)
+pub fn sign_request(secret: &str, source: &str, request_id: &str, command: &BrowserCommand, expires: u64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, signed_message(source, request_id, expires, command).as_bytes());
+    let signature: String = tag.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}.{}", expires, signature)
+}
+
+/// When a request's token expires, if it is genuine and current
+fn verify_token(secret: &str, source: &str, request: &CommandRequest, command: &BrowserCommand, now: u64) -> Option<u64> {
+    let (expires, signature) = request.token.as_deref()?.split_once('.')?;
+    let expires: u64 = expires.parse().ok()?;
+    if expires < now || expires > now + MAX_TOKEN_LIFETIME_SECS {
+        return None;
+    }
+    let tag = decode_hex(signature)?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let message = signed_message(source, &request.request_id, expires, command);
+    hmac::verify(&key, message.as_bytes(), &tag).ok().map(|_| expires)
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok().filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+/// Match `text` against `pattern`, where `*` matches any run of characters
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::RecordingControl;
+
+    fn request(id: &str, source: Option<&str>, token: Option<String>, command: BrowserCommand) -> CommandRequest {
+        CommandRequest {
+            request_id: id.to_string(),
+            reply_to: None,
+            timeout_ms: None,
+            source: source.map(str::to_string),
+            token,
+            command,
+        }
+    }
+
+    fn navigate(url: &str) -> BrowserCommand {
+        BrowserCommand::Navigate { url: url.to_string() }
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("https://*.example.com/*", "https://docs.example.com/a/b"));
+        assert!(!wildcard_match("https://*.example.com/*", "https://example.org/"));
+        assert!(wildcard_match("about:blank", "about:blank"));
+        assert!(!wildcard_match("about:blank", "about:blankx"));
+        assert!(wildcard_match("a*a", "aa"));
+        assert!(!wildcard_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn test_default_policy_allows_everything() {
+        let policy = CommandPolicy::default();
+        assert!(policy.authorize(None, &navigate("https://anywhere.test")).is_ok());
+    }
+
+    #[test]
+    fn test_source_rules() {
+        let policy: CommandPolicy = serde_json::from_str(r#"{
+            "default": { "commands": ["get_tabs"] },
+            "sources": {
+                "ci": { "secret": "s3cret", "commands": ["navigate"], "urls": ["https://*.example.com/*"] },
+                "dashboard": { "commands": ["*"] }
+            }
+        }"#).unwrap();
+
+        // Unnamed sources get the default rules
+        assert!(policy.authorize(None, &BrowserCommand::GetTabs).is_ok());
+        assert_eq!(
+            policy.authorize(None, &navigate("https://example.com/")),
+            Err(CommandRejection::CommandNotAllowed { command: "navigate".to_string() })
+        );
+
+        // Sources with a secret must sign the request
+        let expires = unix_now() + 60;
+        let command = navigate("https://www.example.com/");
+        let token = sign_request("s3cret", "ci", "req-1", &command, expires);
+        let signed = request("req-1", Some("ci"), Some(token), command.clone());
+        assert!(policy.authorize(Some(&signed), &signed.command).is_ok());
+
+        let forged = request("req-2", Some("ci"), Some(sign_request("guess", "ci", "req-2", &command, expires)), command.clone());
+        assert_eq!(
+            policy.authorize(Some(&forged), &forged.command),
+            Err(CommandRejection::InvalidToken { source: "ci".to_string() })
+        );
+
+        let evil = navigate("https://evil.test/");
+        let elsewhere = request("req-3", Some("ci"), Some(sign_request("s3cret", "ci", "req-3", &evil, expires)), evil);
+        assert_eq!(
+            policy.authorize(Some(&elsewhere), &elsewhere.command),
+            Err(CommandRejection::UrlNotAllowed { url: "https://evil.test/".to_string() })
+        );
+
+        let dashboard = request("req-4", Some("dashboard"), None, BrowserCommand::Shutdown);
+        assert!(policy.authorize(Some(&dashboard), &dashboard.command).is_ok());
+    }
+
+    #[test]
+    fn test_played_events_are_checked_against_urls() {
+        let policy: CommandPolicy = serde_json::from_str(r#"{
+            "default": { "commands": ["play_event"], "urls": ["https://example.com/*"] }
+        }"#).unwrap();
+        let play = |event| BrowserCommand::PlayEvent { event };
+
+        let allowed = play(BrowserEvent::Navigation { url: "https://example.com/a".to_string() });
+        assert!(policy.authorize(None, &allowed).is_ok());
+        for event in [
+            BrowserEvent::Navigation { url: "https://evil.test/".to_string() },
+            BrowserEvent::TabCreated { id: 1, url: "https://evil.test/".to_string() },
+        ] {
+            assert_eq!(
+                policy.authorize(None, &play(event)),
+                Err(CommandRejection::UrlNotAllowed { url: "https://evil.test/".to_string() })
+            );
+        }
+        assert!(policy.authorize(None, &play(BrowserEvent::TabClosed { id: 1 })).is_ok());
+    }
+
+    #[test]
+    fn test_tokens_cover_one_command_once() {
+        let policy: CommandPolicy = serde_json::from_str(r#"{
+            "sources": { "ci": { "secret": "s3cret", "commands": ["*"] } }
+        }"#).unwrap();
+        let rejected = Err(CommandRejection::InvalidToken { source: "ci".to_string() });
+        let expires = unix_now() + 60;
+
+        // A token signed for one command is no good for another
+        let token = sign_request("s3cret", "ci", "req-1", &BrowserCommand::GetTabs, expires);
+        let swapped = request("req-1", Some("ci"), Some(token.clone()), navigate("https://evil.test/"));
+        assert_eq!(policy.authorize(Some(&swapped), &swapped.command), rejected);
+
+        // ...and works only once for its own
+        let signed = request("req-1", Some("ci"), Some(token), BrowserCommand::GetTabs);
+        assert!(policy.authorize(Some(&signed), &signed.command).is_ok());
+        assert_eq!(policy.authorize(Some(&signed), &signed.command), rejected);
+
+        // Expired tokens, and ones valid too far ahead, are refused
+        for expires in [unix_now() - 1, unix_now() + MAX_TOKEN_LIFETIME_SECS + 60] {
+            let token = sign_request("s3cret", "ci", "req-2", &BrowserCommand::GetTabs, expires);
+            let late = request("req-2", Some("ci"), Some(token), BrowserCommand::GetTabs);
+            assert_eq!(policy.authorize(Some(&late), &late.command), rejected);
+        }
+    }
+
+    #[test]
+    fn test_writing_files_needs_permission() {
+        let save = BrowserCommand::Recording(RecordingControl::Save { path: "run.jsonl".to_string() });
+        let in_memory = BrowserCommand::Recording(RecordingControl::Start { path: None, live: true });
+        let policy = CommandPolicy::default();
+        assert_eq!(
+            policy.authorize(None, &save),
+            Err(CommandRejection::FileNotAllowed { path: "run.jsonl".to_string() })
+        );
+        assert!(policy.authorize(None, &in_memory).is_ok());
+
+        let policy: CommandPolicy = serde_json::from_str(r#"{
+            "default": { "commands": ["recording"], "write_files": true }
+        }"#).unwrap();
+        assert!(policy.authorize(None, &save).is_ok());
+    }
+}
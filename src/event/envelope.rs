@@ -0,0 +1,135 @@
+//! Versioned envelope wrapping every published `BrowserEvent`
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use super::BrowserEvent;
+
+/// Bumped whenever the envelope layout changes incompatibly
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// An event plus the metadata consumers need to order and deduplicate it
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EventEnvelope {
+    pub schema_version: u32,
+    /// Increases by one for every event stamped in this session
+    pub sequence: u64,
+    /// RFC 3339 wall-clock time the event was stamped
+    pub timestamp: String,
+    /// Milliseconds since the session started, unaffected by clock changes
+    pub monotonic_ms: u64,
+    /// Unique per browser process; `(session_id, sequence)` identifies an event
+    pub session_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// Tab the event belongs to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tab_id: Option<usize>,
+    /// Snake-case event name, e.g. `tab_created`
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub event: BrowserEvent,
+}
+
+/// Stamps events with sequence numbers and timestamps for one session.
+///
+/// Shared by everything that emits events so MQTT, the recorder and the
+/// event viewer all see the same sequence.
+#[derive(Debug)]
+pub struct EventSequencer {
+    session_id: String,
+    instance_id: Option<String>,
+    started: Instant,
+    next_sequence: AtomicU64,
+    /// Last activated tab, used for events that don't name one
+    active_tab: Mutex<Option<usize>>,
+}
+
+impl Default for EventSequencer {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl EventSequencer {
+    pub fn new(instance_id: Option<String>) -> Self {
+        let session_id = format!("{}-{}", std::process::id(), Utc::now().timestamp_millis());
+        Self {
+            session_id,
+            instance_id,
+            started: Instant::now(),
+            next_sequence: AtomicU64::new(1),
+            active_tab: Mutex::new(None),
+        }
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Wrap an event in an envelope with the next sequence number
+    pub fn stamp(&self, event: BrowserEvent) -> EventEnvelope {
+        let tab_id = match self.active_tab.lock() {
+            Ok(mut active_tab) => {
+                match event {
+                    BrowserEvent::TabActivated { id } => *active_tab = Some(id),
+                    BrowserEvent::TabClosed { id } if *active_tab == Some(id) => *active_tab = None,
+                    _ => {}
+                }
+                event.tab_id().or(*active_tab)
+            }
+            Err(_) => event.tab_id(),
+        };
+
+        EventEnvelope {
+            schema_version: EVENT_SCHEMA_VERSION,
+            sequence: self.next_sequence.fetch_add(1, Ordering::SeqCst),
+            timestamp: Utc::now().to_rfc3339(),
+            monotonic_ms: self.started.elapsed().as_millis() as u64,
+            session_id: self.session_id.clone(),
+            instance_id: self.instance_id.clone(),
+            tab_id,
+            event_type: event.event_type().to_string(),
+            event,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_and_tab() {
+        let sequencer = EventSequencer::new(Some("ci".to_string()));
+
+        let created = sequencer.stamp(BrowserEvent::TabCreated { id: 2, url: "about:blank".to_string() });
+        let activated = sequencer.stamp(BrowserEvent::TabActivated { id: 2 });
+        let navigation = sequencer.stamp(BrowserEvent::Navigation { url: "https://example.com".to_string() });
+
+        assert_eq!((created.sequence, activated.sequence, navigation.sequence), (1, 2, 3));
+        assert!(navigation.monotonic_ms >= created.monotonic_ms);
+        assert_eq!(navigation.tab_id, Some(2));
+        assert_eq!(navigation.instance_id.as_deref(), Some("ci"));
+        assert_eq!(navigation.session_id, created.session_id);
+
+        sequencer.stamp(BrowserEvent::TabClosed { id: 2 });
+        let error = sequencer.stamp(BrowserEvent::Error { message: "boom".to_string() });
+        assert_eq!(error.tab_id, None);
+    }
+
+    #[test]
+    fn test_envelope_json() {
+        let envelope = EventSequencer::default().stamp(BrowserEvent::PageLoaded { url: "https://example.com".to_string() });
+        let json: serde_json::Value = serde_json::to_value(&envelope).unwrap();
+
+        assert_eq!(json["schema_version"], EVENT_SCHEMA_VERSION);
+        assert_eq!(json["sequence"], 1);
+        assert_eq!(json["type"], "page_loaded");
+        assert_eq!(json["event"]["PageLoaded"]["url"], "https://example.com");
+        assert!(json.get("instance_id").is_none());
+    }
+}
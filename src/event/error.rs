@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EventError {
+    #[error("Invalid broker URL '{url}': {reason}")]
+    InvalidBrokerUrl {
+        url: String,
+        reason: String,
+    },
+
+    #[error("Unsupported broker URL scheme '{0}' (expected mqtt:// or mqtts://)")]
+    UnsupportedScheme(String),
+
+    #[error("Failed to read TLS file {}: {source}", path.display())]
+    TlsFile {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Invalid TLS configuration: {0}")]
+    InvalidTls(String),
+}
+
+// Result type alias for event system operations
+pub type EventResult<T> = Result<T, EventError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_messages() {
+        let err = EventError::UnsupportedScheme("http".to_string());
+        assert_eq!(err.to_string(), "Unsupported broker URL scheme 'http' (expected mqtt:// or mqtts://)");
+
+        let err = EventError::InvalidBrokerUrl {
+            url: "mqtt://".to_string(),
+            reason: "empty host".to_string(),
+        };
+        assert_eq!(err.to_string(), "Invalid broker URL 'mqtt://': empty host");
+    }
+}
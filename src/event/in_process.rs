@@ -0,0 +1,203 @@
+//! In-process event transport, for tests and for embedding Tinker without a broker
+
+use std::sync::{Arc, mpsc::{self, Receiver, Sender}};
+use std::time::Duration;
+use tracing::{debug, error};
+use super::acl;
+use super::transport::{self, CommandDispatcher, Subscribers, TransportMessage, TransportResult};
+use super::{
+    client_id_for, BrowserCommand, BrowserEvent, CommandError, CommandOutcome, CommandPolicy,
+    CommandRequest, CommandResponse, EventEnvelope, EventSequencer, EventSystem, EventTransport,
+    InstanceStatus, PendingCommand, Presence, DEFAULT_REPLY_TOPIC, STATUS_TOPIC,
+};
+
+/// Delivers events to local subscribers over channels and takes commands
+/// straight from the caller. Topics are the same as with MQTT.
+#[derive(Clone, Default)]
+pub struct InProcessTransport {
+    instance_id: Option<String>,
+    headless: bool,
+    commands: CommandDispatcher,
+    subscribers: Subscribers,
+    sequencer: Arc<EventSequencer>,
+}
+
+impl InProcessTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Namespace every topic under `tinker/<instance>/`
+    pub fn with_instance_id(mut self, instance_id: Option<&str>) -> Self {
+        self.instance_id = instance_id.map(transport::sanitize_instance_id);
+        self.sequencer = Arc::new(EventSequencer::new(self.instance_id.clone()));
+        self
+    }
+
+    /// Send a message to every matching subscriber
+    pub fn deliver(&self, topic: &str, payload: &str) {
+        debug!("Delivering to {}: {}", topic, payload);
+        self.subscribers.deliver(topic, payload);
+    }
+
+    /// Run a correlated command; the response arrives once the engine has executed it
+    pub fn request(&self, request: CommandRequest) -> Receiver<CommandResponse> {
+        let (tx, rx) = mpsc::channel();
+        let command_str = serde_json::to_string(&request).unwrap_or_default();
+        let command = request.command.clone();
+        self.run(command_str, Some(request), command, move |_, response| {
+            let _ = tx.send(response);
+        });
+        rx
+    }
+
+    /// Run a command payload exactly as it would arrive on the command topic.
+    ///
+    /// `reply` is called with the reply topic and response for correlated
+    /// requests; the response goes to `reply` only, never to subscribers.
+    pub fn execute<F>(&self, payload: &str, reply: F)
+    where
+        F: FnOnce(String, CommandResponse) + Send + 'static,
+    {
+        // Like MQTT, an observer ignores what arrives before even reading it
+        if self.commands.is_observer() {
+            debug!("Observer mode, ignoring command: {}", payload);
+            return;
+        }
+        match transport::parse_command(payload) {
+            Ok((request, command)) => self.run(payload.to_string(), request, command, reply),
+            Err(error) => {
+                self.publish_event(BrowserEvent::CommandReceived { command: payload.to_string() });
+                for event in transport::command_finished_events(payload, &Err(error)) {
+                    self.publish_event(event);
+                }
+            }
+        }
+    }
+
+    fn run<F>(&self, command_str: String, request: Option<CommandRequest>, command: BrowserCommand, reply: F)
+    where
+        F: FnOnce(String, CommandResponse) + Send + 'static,
+    {
+        if self.commands.is_observer() {
+            debug!("Observer mode, ignoring command: {}", command_str);
+            return;
+        }
+        self.publish_event(BrowserEvent::CommandReceived { command: command_str.clone() });
+
+        if let Err(rejection) = self.commands.authorize(request.as_ref(), &command) {
+            debug!("Rejected command: {}", rejection);
+            let error = CommandError::from(&rejection);
+            self.publish_event(acl::rejected_event(&command_str, request.as_ref(), rejection));
+            self.finish(&command_str, request, Err(error), Duration::ZERO, reply);
+            return;
+        }
+
+        let timeout = transport::command_timeout(request.as_ref());
+        let transport = self.clone();
+        self.commands.dispatch(command, timeout, move |outcome, duration| {
+            transport.finish(&command_str, request, outcome, duration, reply);
+        });
+    }
+
+    fn finish<F>(&self, command_str: &str, request: Option<CommandRequest>, outcome: CommandOutcome, duration: Duration, reply: F)
+    where
+        F: FnOnce(String, CommandResponse),
+    {
+        for event in transport::command_finished_events(command_str, &outcome) {
+            self.publish_event(event);
+        }
+
+        // The caller always has its own reply sink, so replies are not broadcast
+        if let Some(request) = request {
+            let topic = request.reply_to.clone()
+                .unwrap_or_else(|| self.topic(DEFAULT_REPLY_TOPIC));
+            let mut response = CommandResponse::new(request.request_id, outcome, duration);
+            response.instance_id = self.instance_id.clone();
+            reply(topic, response);
+        }
+    }
+
+    fn publish_event(&self, event: BrowserEvent) {
+        self.deliver_envelope(&self.sequencer.stamp(event));
+    }
+
+    fn deliver_envelope(&self, envelope: &EventEnvelope) {
+        match serde_json::to_string(envelope) {
+            Ok(payload) => self.deliver(&self.topic(EventSystem::base_topic(&envelope.event)), &payload),
+            Err(e) => error!("Failed to serialize event: {}", e),
+        }
+    }
+}
+
+impl EventTransport for InProcessTransport {
+    fn describe(&self) -> String {
+        "in-process".to_string()
+    }
+
+    fn connect(&mut self) -> TransportResult<()> {
+        self.publish_status(&self.status(Presence::Online, 0, None))
+    }
+
+    fn publish_envelope(&mut self, envelope: &EventEnvelope) -> TransportResult<()> {
+        self.deliver_envelope(envelope);
+        Ok(())
+    }
+
+    fn subscribe(&mut self, filter: &str) -> TransportResult<Receiver<TransportMessage>> {
+        Ok(self.subscribers.add(filter))
+    }
+
+    fn set_command_sender(&mut self, sender: Sender<PendingCommand>) {
+        self.commands.set_sender(sender);
+    }
+
+    fn set_command_policy(&mut self, policy: CommandPolicy) {
+        self.commands.set_policy(policy);
+    }
+
+    fn set_headless(&mut self, headless: bool) {
+        self.headless = headless;
+    }
+
+    fn topic(&self, base: &str) -> String {
+        transport::namespaced_topic(self.instance_id.as_deref(), base)
+    }
+
+    fn sequencer(&self) -> Arc<EventSequencer> {
+        self.sequencer.clone()
+    }
+
+    fn set_sequencer(&mut self, sequencer: Arc<EventSequencer>) {
+        self.sequencer = sequencer;
+    }
+
+    fn status(&self, presence: Presence, tab_count: usize, active_url: Option<String>) -> InstanceStatus {
+        InstanceStatus {
+            presence,
+            instance_id: self.instance_id.clone(),
+            client_id: client_id_for(self.instance_id.as_deref()),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            headless: self.headless,
+            tab_count,
+            active_url,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    fn publish_status(&mut self, status: &InstanceStatus) -> TransportResult<()> {
+        let payload = serde_json::to_string(status)?;
+        self.deliver(&self.topic(STATUS_TOPIC), &payload);
+        Ok(())
+    }
+
+    fn publish_message(&mut self, topic: &str, payload: &str) -> TransportResult<()> {
+        self.deliver(topic, payload);
+        Ok(())
+    }
+
+    fn disconnect(&mut self, tab_count: usize, active_url: Option<String>) -> TransportResult<()> {
+        self.publish_status(&self.status(Presence::Disconnecting, tab_count, active_url.clone()))?;
+        self.publish_status(&self.status(Presence::Offline, tab_count, active_url))
+    }
+}
@@ -0,0 +1,264 @@
+//! Bounded queue for events published while the broker is unreachable
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+/// Events kept while disconnected before the oldest are dropped
+pub const DEFAULT_OUTBOX_CAPACITY: usize = 1000;
+
+/// A message waiting to be published, serialized when it was first published
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub topic: String,
+    pub payload: String,
+    /// RFC 3339 time the entry was queued
+    pub queued_at: String,
+}
+
+/// Counters describing what happened to events published while offline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct OutboxStats {
+    /// Entries currently waiting to be flushed
+    pub pending: usize,
+    /// Entries ever queued
+    pub queued: u64,
+    /// Entries published after reconnecting
+    pub flushed: u64,
+    /// Entries discarded because the outbox was full
+    pub dropped: u64,
+}
+
+/// Bounded, optionally disk-backed FIFO of unpublished events.
+///
+/// With a file, every entry is appended as a JSON line so queued events
+/// survive a restart; the file is rewritten after each flush.
+pub struct Outbox {
+    entries: VecDeque<OutboxEntry>,
+    capacity: usize,
+    path: Option<PathBuf>,
+    stats: OutboxStats,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new(DEFAULT_OUTBOX_CAPACITY)
+    }
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            path: None,
+            stats: OutboxStats::default(),
+        }
+    }
+
+    /// Create an outbox persisted at `path`, loading entries left by a previous run
+    pub fn with_file(capacity: usize, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut outbox = Self::new(capacity);
+
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<OutboxEntry>(&line) {
+                    Ok(entry) => outbox.push_entry(entry),
+                    Err(e) => error!("Skipping unreadable outbox entry in {}: {}", path.display(), e),
+                }
+            }
+            if !outbox.entries.is_empty() {
+                info!("Loaded {} queued events from {}", outbox.entries.len(), path.display());
+            }
+        }
+
+        outbox.path = Some(path);
+        outbox.persist_all();
+        Ok(outbox)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> OutboxStats {
+        OutboxStats {
+            pending: self.entries.len(),
+            ..self.stats
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &OutboxEntry> {
+        self.entries.iter()
+    }
+
+    /// Queue a message, dropping the oldest one if the outbox is full
+    pub fn push(&mut self, topic: &str, payload: &str) {
+        let entry = OutboxEntry {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            queued_at: Utc::now().to_rfc3339(),
+        };
+
+        let dropped_before = self.stats.dropped;
+        self.push_entry(entry);
+
+        if self.stats.dropped != dropped_before {
+            // The front of the file is gone, so append is not enough
+            self.persist_all();
+        } else if let Some(entry) = self.entries.back() {
+            self.append(entry);
+        }
+    }
+
+    fn push_entry(&mut self, entry: OutboxEntry) {
+        if self.capacity == 0 {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+            self.stats.dropped += 1;
+        }
+        debug!("Queued event for {} while offline", entry.topic);
+        self.entries.push_back(entry);
+        self.stats.queued += 1;
+    }
+
+    /// Hand entries to `publish` oldest first, stopping at the first failure.
+    /// Returns how many entries were published.
+    pub fn flush<F>(&mut self, mut publish: F) -> usize
+    where
+        F: FnMut(&OutboxEntry) -> bool,
+    {
+        let mut flushed = 0;
+        while let Some(entry) = self.entries.front() {
+            if !publish(entry) {
+                break;
+            }
+            self.entries.pop_front();
+            flushed += 1;
+        }
+
+        if flushed > 0 {
+            self.stats.flushed += flushed as u64;
+            self.persist_all();
+        }
+        flushed
+    }
+
+    fn append(&self, entry: &OutboxEntry) {
+        let Some(path) = &self.path else { return };
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                let line = serde_json::to_string(entry)?;
+                writeln!(file, "{}", line)
+            });
+        if let Err(e) = result {
+            error!("Failed to append to outbox file {}: {}", path.display(), e);
+        }
+    }
+
+    fn persist_all(&self) {
+        let Some(path) = &self.path else { return };
+        let result = (|| -> io::Result<()> {
+            let tmp = path.with_extension("tmp");
+            {
+                let mut writer = BufWriter::new(File::create(&tmp)?);
+                for entry in &self.entries {
+                    writeln!(writer, "{}", serde_json::to_string(entry)?)?;
+                }
+                writer.flush()?;
+            }
+            fs::rename(&tmp, path)
+        })();
+        if let Err(e) = result {
+            error!("Failed to write outbox file {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flush_in_order() {
+        let mut outbox = Outbox::new(10);
+        outbox.push("browser/navigation", "1");
+        outbox.push("browser/navigation", "2");
+        outbox.push("browser/error", "3");
+
+        let mut published = Vec::new();
+        assert_eq!(outbox.flush(|entry| {
+            published.push(entry.payload.clone());
+            true
+        }), 3);
+        assert_eq!(published, vec!["1", "2", "3"]);
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.stats(), OutboxStats { pending: 0, queued: 3, flushed: 3, dropped: 0 });
+    }
+
+    #[test]
+    fn test_flush_stops_at_failure() {
+        let mut outbox = Outbox::new(10);
+        outbox.push("a", "1");
+        outbox.push("b", "2");
+
+        assert_eq!(outbox.flush(|entry| entry.topic == "a"), 1);
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox.entries().next().unwrap().payload, "2");
+    }
+
+    #[test]
+    fn test_bounded_drops_oldest() {
+        let mut outbox = Outbox::new(2);
+        outbox.push("a", "1");
+        outbox.push("a", "2");
+        outbox.push("a", "3");
+
+        let payloads: Vec<_> = outbox.entries().map(|e| e.payload.as_str()).collect();
+        assert_eq!(payloads, vec!["2", "3"]);
+        assert_eq!(outbox.stats().dropped, 1);
+        assert_eq!(outbox.stats().queued, 3);
+    }
+
+    #[test]
+    fn test_disk_backed_survives_restart() {
+        let path = std::env::temp_dir().join(format!("tinker-outbox-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        {
+            let mut outbox = Outbox::with_file(10, &path).unwrap();
+            outbox.push("a", "1");
+            outbox.push("b", "2");
+        }
+
+        let mut outbox = Outbox::with_file(10, &path).unwrap();
+        assert_eq!(outbox.len(), 2);
+        outbox.flush(|_| true);
+
+        let outbox = Outbox::with_file(10, &path).unwrap();
+        assert!(outbox.is_empty());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,163 @@
+//! Replay control protocol: pause, resume, step, seek and breakpoints.
+//!
+//! Sent as `{"replay": {"action": "pause"}}` on the command topic or through
+//! the HTTP API; every control answers with the player's `ReplayStatus`.
+
+use std::fmt;
+use std::str::FromStr;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use super::EventEnvelope;
+
+/// Where a seek lands
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SeekTarget {
+    /// Position in the recording, 0 being the first event
+    Index(usize),
+    /// First event at or after this offset from the start of the recording
+    TimestampMs(u64),
+}
+
+/// Pause before an event matching the predicate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Breakpoint {
+    /// Events of this type, e.g. `error` or `navigation`
+    EventType(String),
+    Index(usize),
+    /// The first event at or after this offset
+    TimestampMs(u64),
+    /// Events whose URL contains this text
+    UrlContains(String),
+}
+
+impl Breakpoint {
+    /// Whether the event at `index` should be held back. `previous_ms` is the
+    /// offset of the event before it, so time breakpoints fire only once.
+    pub fn matches(&self, index: usize, timestamp_ms: u64, previous_ms: Option<u64>, envelope: &EventEnvelope) -> bool {
+        match self {
+            Breakpoint::EventType(event_type) => envelope.event_type == *event_type,
+            Breakpoint::Index(at) => index == *at,
+            Breakpoint::TimestampMs(at) => {
+                timestamp_ms >= *at && !matches!(previous_ms, Some(previous) if previous >= *at)
+            }
+            Breakpoint::UrlContains(text) => envelope.event.url().is_some_and(|url| url.contains(text.as_str())),
+        }
+    }
+}
+
+/// Parses the CLI form: `error`, `type:error`, `index:12`, `time:5000` or `url:example.com`
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let parse_number = |value: &str| value.parse::<u64>().map_err(|_| format!("Invalid number in breakpoint '{}'", text));
+        match text.split_once(':') {
+            Some(("type", value)) => Ok(Breakpoint::EventType(value.to_string())),
+            Some(("index", value)) => Ok(Breakpoint::Index(parse_number(value)? as usize)),
+            Some(("time", value)) => Ok(Breakpoint::TimestampMs(parse_number(value)?)),
+            Some(("url", value)) => Ok(Breakpoint::UrlContains(value.to_string())),
+            Some((kind, _)) => Err(format!("Unknown breakpoint kind '{}'; use type, index, time or url", kind)),
+            None if text.is_empty() => Err("Empty breakpoint".to_string()),
+            None => Ok(Breakpoint::EventType(text.to_string())),
+        }
+    }
+}
+
+/// Parses the CLI form: `12` or `index:12` for an event, `time:5000` for an offset
+impl FromStr for SeekTarget {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = text.split_once(':').unwrap_or(("index", text));
+        let value = value.parse::<u64>().map_err(|_| format!("Invalid seek target '{}'", text))?;
+        match kind {
+            "index" => Ok(SeekTarget::Index(value as usize)),
+            "time" => Ok(SeekTarget::TimestampMs(value)),
+            _ => Err(format!("Unknown seek target '{}'; use index:N or time:MS", text)),
+        }
+    }
+}
+
+/// A replay control command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ReplayControl {
+    Status,
+    /// Start the loaded recording from the beginning
+    Start,
+    Stop,
+    /// Freeze the replay clock
+    Pause,
+    Resume,
+    /// Play exactly one event, pausing first if needed
+    Step,
+    /// Jump without replaying the events in between
+    Seek { to: SeekTarget },
+    SetSpeed { speed: f32 },
+    AddBreakpoint { breakpoint: Breakpoint },
+    ClearBreakpoints,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayState {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl fmt::Display for ReplayState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayState::Stopped => write!(f, "stopped"),
+            ReplayState::Playing => write!(f, "playing"),
+            ReplayState::Paused => write!(f, "paused"),
+        }
+    }
+}
+
+/// Where the player is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReplayStatus {
+    pub state: ReplayState,
+    /// Index of the next event to play
+    pub index: usize,
+    pub total: usize,
+    /// Position on the recording's timeline
+    pub position_ms: u64,
+    pub speed: f32,
+    pub breakpoints: Vec<Breakpoint>,
+    /// Breakpoint-held event, released by `resume` or `step`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held_at: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_breakpoints() {
+        assert_eq!("error".parse(), Ok(Breakpoint::EventType("error".to_string())));
+        assert_eq!("index:3".parse(), Ok(Breakpoint::Index(3)));
+        assert_eq!("time:1500".parse(), Ok(Breakpoint::TimestampMs(1500)));
+        assert_eq!("url:example.com".parse(), Ok(Breakpoint::UrlContains("example.com".to_string())));
+        assert!("index:x".parse::<Breakpoint>().is_err());
+        assert!("when:now".parse::<Breakpoint>().is_err());
+    }
+
+    #[test]
+    fn test_parse_seek_targets() {
+        assert_eq!("7".parse(), Ok(SeekTarget::Index(7)));
+        assert_eq!("time:250".parse(), Ok(SeekTarget::TimestampMs(250)));
+        assert!("frame:2".parse::<SeekTarget>().is_err());
+    }
+
+    #[test]
+    fn test_control_wire_format() {
+        let control: ReplayControl = serde_json::from_str(r#"{"action":"seek","to":{"timestamp_ms":500}}"#).unwrap();
+        assert_eq!(control, ReplayControl::Seek { to: SeekTarget::TimestampMs(500) });
+    }
+}
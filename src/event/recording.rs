@@ -0,0 +1,138 @@
+//! Recording control protocol: start, stop, save and fetch recordings remotely.
+//!
+//! Sent as `{"recording": {"action": "start", "live": true}}` on the command
+//! topic. A live recording also publishes its header to
+//! `browser/recording/header` and every `EventRecord` to
+//! `browser/recording/record` as it is captured, each payload being the line
+//! the recording file gets, so a follower can rebuild the file by appending them.
+//!
+//! Paths name files inside the browser's recordings directory; absolute paths
+//! and `..` are refused, and writing files at all needs `write_files` in the
+//! sender's command policy rules. `fetch` replies in chunks of whole lines, so
+//! a recording of any size fits in MQTT packets.
+
+use std::path::{Component, Path, PathBuf};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Where a live recording publishes its header
+pub const RECORDING_HEADER_TOPIC: &str = "browser/recording/header";
+
+/// Where a live recording publishes each record
+pub const RECORDING_RECORD_TOPIC: &str = "browser/recording/record";
+
+/// Where the recorder's status goes whenever recording starts or stops
+pub const RECORDING_STATUS_TOPIC: &str = "browser/recording/status";
+
+/// Most of a recording one `fetch` reply carries, in bytes
+pub const FETCH_CHUNK_BYTES: usize = 64 * 1024;
+
+/// A recording control command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RecordingControl {
+    Status,
+    /// Start a new recording, streamed to `path` in the recordings directory
+    /// when given and kept in memory otherwise
+    Start {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        /// Publish the recording as it is captured
+        #[serde(default)]
+        live: bool,
+    },
+    /// Stop recording; what was captured can still be saved or fetched
+    Stop,
+    /// Write the recording to `path` in the recordings directory
+    Save { path: String },
+    /// Reply with the recording as JSON Lines, from byte `offset` on; the
+    /// reply's `next` is the offset of the rest, if any
+    Fetch {
+        #[serde(default)]
+        offset: usize,
+    },
+    /// Turn publishing of the current recording on or off
+    SetLive { live: bool },
+}
+
+/// What the recorder is doing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RecordingStatus {
+    pub recording: bool,
+    pub live: bool,
+    /// Events in the current or last recording
+    pub events: usize,
+    /// File the recording is streamed to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl RecordingControl {
+    /// File the control would create or overwrite, if any
+    pub fn writes_file(&self) -> Option<&str> {
+        match self {
+            RecordingControl::Start { path: Some(path), .. } | RecordingControl::Save { path } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Place a path sent with a recording control inside `dir`
+pub fn resolve_path(dir: &Path, path: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    let inside = relative.components().all(|component| matches!(component, Component::Normal(_)));
+    if path.is_empty() || !inside {
+        return Err(format!("Recording path '{}' must be a relative path inside the recordings directory", path));
+    }
+    Ok(dir.join(relative))
+}
+
+/// Whole lines of `content` from byte `offset`, at most `limit` bytes unless a
+/// single line is longer, and where the rest starts. `None` for a bad offset.
+pub fn chunk(content: &str, offset: usize, limit: usize) -> Option<(&str, Option<usize>)> {
+    let rest = content.get(offset..)?;
+    if rest.len() <= limit {
+        return Some((rest, None));
+    }
+    let bytes = rest.as_bytes();
+    let end = bytes[..limit].iter().rposition(|&byte| byte == b'\n')
+        .or_else(|| bytes.iter().position(|&byte| byte == b'\n'))
+        .map_or(rest.len(), |newline| newline + 1);
+    let next = offset + end;
+    Some((&rest[..end], (next < content.len()).then_some(next)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_wire_format() {
+        let control: RecordingControl = serde_json::from_str(r#"{"action":"start"}"#).unwrap();
+        assert_eq!(control, RecordingControl::Start { path: None, live: false });
+        let control: RecordingControl = serde_json::from_str(r#"{"action":"save","path":"run.jsonl"}"#).unwrap();
+        assert_eq!(control, RecordingControl::Save { path: "run.jsonl".to_string() });
+        let control: RecordingControl = serde_json::from_str(r#"{"action":"fetch"}"#).unwrap();
+        assert_eq!(control, RecordingControl::Fetch { offset: 0 });
+    }
+
+    #[test]
+    fn test_paths_stay_in_the_recordings_directory() {
+        let dir = Path::new("recordings");
+        assert_eq!(resolve_path(dir, "runs/a.jsonl").unwrap(), dir.join("runs/a.jsonl"));
+        assert!(resolve_path(dir, "/etc/passwd").is_err());
+        assert!(resolve_path(dir, "../a.jsonl").is_err());
+        assert!(resolve_path(dir, "runs/../../a.jsonl").is_err());
+        assert!(resolve_path(dir, "").is_err());
+    }
+
+    #[test]
+    fn test_chunks_are_whole_lines() {
+        let content = "header\nfirst\nsecond\n";
+        assert_eq!(chunk(content, 0, 14), Some(("header\nfirst\n", Some(13))));
+        assert_eq!(chunk(content, 13, 14), Some(("second\n", None)));
+        // A line longer than the limit still goes out whole
+        assert_eq!(chunk(content, 0, 3), Some(("header\n", Some(7))));
+        assert_eq!(chunk(content, 99, 14), None);
+    }
+}
@@ -0,0 +1,249 @@
+//! JSON Schema and AsyncAPI documents for the event protocol.
+//!
+//! Generated from the Rust types so clients can validate against exactly
+//! what Tinker sends and accepts. The copies shipped in `schemas/` are
+//! checked against these by `tests/schema_tests.rs`; regenerate them with
+//! `tinker schema --out-dir schemas`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde_json::{json, Map, Value};
+use super::{
+    BrowserCommand, BrowserEvent, CommandRejection, CommandRequest, CommandResponse, EventEnvelope,
+    EventSystem, InstanceStatus, OutboxStats, BROADCAST_COMMAND_TOPIC, COMMAND_TOPIC,
+    DEFAULT_REPLY_TOPIC, EVENT_SCHEMA_VERSION, OUTBOX_TOPIC, RECORDING_HEADER_TOPIC, RECORDING_RECORD_TOPIC,
+    RECORDING_STATUS_TOPIC, STATUS_TOPIC, TOPIC_ROOT,
+};
+
+/// File name of the AsyncAPI document
+pub const ASYNCAPI_FILE: &str = "asyncapi.json";
+
+/// JSON Schema (draft 2019-09) for one type
+pub fn schema_for<T: JsonSchema>() -> Value {
+    let generator = SchemaSettings::draft2019_09().into_generator();
+    let schema = generator.into_root_schema_for::<T>();
+    serde_json::to_value(schema).unwrap_or(Value::Null)
+}
+
+/// Every generated document, keyed by the file name it is shipped under
+pub fn documents() -> Vec<(&'static str, Value)> {
+    vec![
+        ("browser_command.schema.json", schema_for::<BrowserCommand>()),
+        ("command_request.schema.json", schema_for::<CommandRequest>()),
+        ("command_response.schema.json", schema_for::<CommandResponse>()),
+        ("browser_event.schema.json", schema_for::<BrowserEvent>()),
+        ("event_envelope.schema.json", schema_for::<EventEnvelope>()),
+        ("instance_status.schema.json", schema_for::<InstanceStatus>()),
+        (ASYNCAPI_FILE, asyncapi()),
+    ]
+}
+
+/// Write every document into `dir`, creating it if needed
+pub fn write_all(dir: &Path) -> io::Result<Vec<String>> {
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for (name, document) in documents() {
+        let path = dir.join(name);
+        fs::write(&path, to_pretty(&document))?;
+        written.push(path.display().to_string());
+    }
+    Ok(written)
+}
+
+/// Stable, newline-terminated rendering used for shipped files
+pub fn to_pretty(document: &Value) -> String {
+    let mut text = serde_json::to_string_pretty(document).unwrap_or_default();
+    text.push('\n');
+    text
+}
+
+/// One example of every event, used for the AsyncAPI channels
+pub fn event_examples() -> Vec<BrowserEvent> {
+    let url = "https://example.com".to_string();
+    vec![
+        BrowserEvent::Navigation { url: url.clone() },
+        BrowserEvent::PageLoaded { url: url.clone() },
+        BrowserEvent::TitleChanged { title: "Example Domain".to_string() },
+        BrowserEvent::TabCreated { id: 1, url: url.clone() },
+        BrowserEvent::TabClosed { id: 1 },
+        BrowserEvent::TabActivated { id: 1 },
+        BrowserEvent::TabUrlChanged { id: 1, url },
+        BrowserEvent::TabTitleChanged { id: 1, title: "Example Domain".to_string() },
+        BrowserEvent::Error { message: "Navigation failed".to_string() },
+        BrowserEvent::CommandReceived { command: "{\"navigate\":{\"url\":\"https://example.com\"}}".to_string() },
+        BrowserEvent::CommandExecuted { command: "{\"navigate\":{\"url\":\"https://example.com\"}}".to_string(), success: true },
+        BrowserEvent::CommandRejected {
+            command: "{\"navigate\":{\"url\":\"https://example.org\"}}".to_string(),
+            source: Some("ci".to_string()),
+            reason: CommandRejection::UrlNotAllowed { url: "https://example.org".to_string() },
+        },
+        BrowserEvent::Click { selector: "#submit".to_string(), x: 120.0, y: 48.5, button: 0 },
+        BrowserEvent::TextInput { selector: "input[name=\"q\"]".to_string(), value: "tinker".to_string(), redacted: false },
+        BrowserEvent::FormSubmitted { selector: "#search".to_string() },
+        BrowserEvent::Scrolled { selector: None, x: 0.0, y: 640.0 },
+        BrowserEvent::KeyPressed { selector: Some("input[name=\"q\"]".to_string()), key: "Enter".to_string() },
+        BrowserEvent::ApiAudit { token: "ci".to_string(), action: "POST /navigate".to_string(), success: true },
+    ]
+}
+
+/// A fixed envelope so the shipped document does not change from run to run
+fn example_envelope(event: BrowserEvent) -> EventEnvelope {
+    EventEnvelope {
+        schema_version: EVENT_SCHEMA_VERSION,
+        sequence: 1,
+        timestamp: "2024-01-01T00:00:00+00:00".to_string(),
+        monotonic_ms: 0,
+        session_id: "4242-1704067200000".to_string(),
+        instance_id: None,
+        tab_id: event.tab_id(),
+        event_type: event.event_type().to_string(),
+        event,
+    }
+}
+
+fn message_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/messages/{}", name) })
+}
+
+/// AsyncAPI 2.6 description of the MQTT topics, from the browser's point of view
+pub fn asyncapi() -> Value {
+    let mut channels = Map::new();
+    let mut messages = Map::new();
+
+    for event in event_examples() {
+        let name = event.event_type();
+        let topic = EventSystem::base_topic(&event);
+        channels.insert(topic.to_string(), json!({
+            "description": format!("`{}` events", name),
+            "subscribe": { "message": message_ref(name) },
+        }));
+        messages.insert(name.to_string(), json!({
+            "name": name,
+            "payload": { "$ref": "#/components/schemas/EventEnvelope" },
+            "examples": [{ "payload": example_envelope(event) }],
+        }));
+    }
+
+    let commands = json!({
+        "description": "Commands for the browser, either bare or wrapped in a correlated request",
+        "publish": { "message": message_ref("command") },
+    });
+    channels.insert(COMMAND_TOPIC.to_string(), commands.clone());
+    channels.insert(BROADCAST_COMMAND_TOPIC.to_string(), commands);
+    channels.insert(DEFAULT_REPLY_TOPIC.to_string(), json!({
+        "description": "Replies to requests that do not name their own `reply_to`",
+        "subscribe": { "message": message_ref("command_response") },
+    }));
+    channels.insert(STATUS_TOPIC.to_string(), json!({
+        "description": "Retained presence record, set to offline by the MQTT last will",
+        "subscribe": { "message": message_ref("instance_status") },
+    }));
+    channels.insert(OUTBOX_TOPIC.to_string(), json!({
+        "description": "Outbox counters, published after queued events are flushed",
+        "subscribe": { "message": message_ref("outbox_stats") },
+    }));
+
+    channels.insert(RECORDING_HEADER_TOPIC.to_string(), json!({
+        "description": "First line of a live recording, published when it starts or goes live",
+        "subscribe": { "message": message_ref("recording_line") },
+    }));
+    channels.insert(RECORDING_RECORD_TOPIC.to_string(), json!({
+        "description": "Each further line of a live recording, as the event is captured",
+        "subscribe": { "message": message_ref("recording_line") },
+    }));
+    channels.insert(RECORDING_STATUS_TOPIC.to_string(), json!({
+        "description": "Recorder status, published when recording starts, stops or goes live",
+        "subscribe": { "message": message_ref("recording_status") },
+    }));
+
+    messages.insert("command".to_string(), json!({
+        "name": "command",
+        "payload": { "oneOf": [
+            { "$ref": "#/components/schemas/CommandRequest" },
+            { "$ref": "#/components/schemas/BrowserCommand" },
+        ] },
+    }));
+    messages.insert("command_response".to_string(), json!({
+        "name": "command_response",
+        "payload": { "$ref": "#/components/schemas/CommandResponse" },
+    }));
+    messages.insert("instance_status".to_string(), json!({
+        "name": "instance_status",
+        "payload": { "$ref": "#/components/schemas/InstanceStatus" },
+    }));
+    messages.insert("recording_line".to_string(), json!({
+        "name": "recording_line",
+        "summary": "One line of a recording file: the header, then an event envelope with its `timestamp_ms` offset",
+        "payload": { "type": "object" },
+    }));
+    messages.insert("recording_status".to_string(), json!({
+        "name": "recording_status",
+        "payload": { "$ref": "#/components/schemas/RecordingStatus" },
+    }));
+    messages.insert("outbox_stats".to_string(), json!({
+        "name": "outbox_stats",
+        "payload": { "$ref": "#/components/schemas/OutboxStats" },
+    }));
+
+    json!({
+        "asyncapi": "2.6.0",
+        "info": {
+            "title": "Tinker browser events",
+            "version": EVENT_SCHEMA_VERSION.to_string(),
+            "description": format!(
+                "MQTT topics used by Tinker. When started with --instance-id, every \
+                 browser/... channel is prefixed with {}/<instance>/.",
+                TOPIC_ROOT
+            ),
+        },
+        "defaultContentType": "application/json",
+        "channels": channels,
+        "components": {
+            "messages": messages,
+            "schemas": component_schemas(),
+        },
+    })
+}
+
+/// All payload schemas with their definitions hoisted next to them
+fn component_schemas() -> Value {
+    let mut generator = SchemaSettings::draft2019_09()
+        .with(|settings| settings.definitions_path = "#/components/schemas/".to_string())
+        .into_generator();
+    // Referencing each payload type registers it, and everything it uses, as a definition
+    generator.subschema_for::<EventEnvelope>();
+    generator.subschema_for::<CommandRequest>();
+    generator.subschema_for::<CommandResponse>();
+    generator.subschema_for::<InstanceStatus>();
+    generator.subschema_for::<OutboxStats>();
+    serde_json::to_value(generator.take_definitions()).unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_examples_cover_every_event() {
+        let schema = schema_for::<BrowserEvent>();
+        let variants = schema["oneOf"].as_array().map(Vec::len).unwrap_or_default();
+        assert_eq!(event_examples().len(), variants);
+
+        let mut types: Vec<_> = event_examples().iter().map(BrowserEvent::event_type).collect();
+        types.dedup();
+        assert_eq!(types.len(), variants);
+    }
+
+    #[test]
+    fn test_asyncapi_channels() {
+        let document = asyncapi();
+        let channels = document["channels"].as_object().unwrap();
+        assert!(channels.contains_key("browser/navigation"));
+        assert!(channels.contains_key(COMMAND_TOPIC));
+        assert!(channels.contains_key(RECORDING_RECORD_TOPIC));
+        assert!(document["components"]["schemas"]["EventEnvelope"].is_object());
+        assert!(document["components"]["schemas"]["BrowserCommand"].is_object());
+    }
+}
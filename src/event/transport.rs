@@ -0,0 +1,287 @@
+//! Transport-independent pieces of the event system.
+//!
+//! `EventTransport` is what the browser engine talks to. MQTT (`EventSystem`),
+//! the in-process channel backend and the WebSocket backend all implement it
+//! and share the command dispatch and topic matching helpers below.
+
+use std::error::Error;
+use std::sync::{Arc, Mutex, mpsc::{self, Receiver, RecvTimeoutError, Sender}};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+use super::{
+    BrowserCommand, BrowserEvent, CommandError, CommandOutcome, CommandPolicy, CommandRejection,
+    CommandRequest, EventEnvelope,
+    EventSequencer, IncomingCommand, InstanceStatus, PendingCommand, Presence,
+    DEFAULT_COMMAND_TIMEOUT_MS, TOPIC_ROOT,
+};
+
+/// Result type used by transport operations
+pub type TransportResult<T> = Result<T, Box<dyn Error>>;
+
+/// How the browser engine holds its transport
+pub type SharedTransport = Arc<Mutex<dyn EventTransport>>;
+
+/// A message seen on a transport, as delivered to subscribers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransportMessage {
+    pub topic: String,
+    pub payload: String,
+}
+
+/// Publish/subscribe and command delivery, independent of the wire protocol
+pub trait EventTransport: Send {
+    /// Where events go, for logs, e.g. `mqtt://localhost:1883`
+    fn describe(&self) -> String;
+
+    /// Start delivering events and accepting commands
+    fn connect(&mut self) -> TransportResult<()>;
+
+    /// Publish an already stamped event
+    fn publish_envelope(&mut self, envelope: &EventEnvelope) -> TransportResult<()>;
+
+    /// Stamp and publish an event
+    fn publish(&mut self, event: BrowserEvent) -> TransportResult<()> {
+        let envelope = self.sequencer().stamp(event);
+        self.publish_envelope(&envelope)
+    }
+
+    /// Receive every message whose topic matches `filter` (MQTT wildcards `+` and `#`)
+    fn subscribe(&mut self, filter: &str) -> TransportResult<Receiver<TransportMessage>>;
+
+    /// Where incoming commands are queued for the engine
+    fn set_command_sender(&mut self, sender: Sender<PendingCommand>);
+
+    /// Which incoming commands are accepted, and from whom
+    fn set_command_policy(&mut self, policy: CommandPolicy);
+
+    fn set_headless(&mut self, headless: bool);
+
+    /// Resolve a base topic such as `browser/command` for this instance
+    fn topic(&self, base: &str) -> String;
+
+    fn sequencer(&self) -> Arc<EventSequencer>;
+
+    /// Continue an existing sequence, e.g. one already used by the browser engine
+    fn set_sequencer(&mut self, sequencer: Arc<EventSequencer>);
+
+    /// Status record for this instance with the given engine state
+    fn status(&self, presence: Presence, tab_count: usize, active_url: Option<String>) -> InstanceStatus;
+
+    fn publish_status(&mut self, status: &InstanceStatus) -> TransportResult<()>;
+
+    /// Publish a payload that is not an event, such as a live recording line,
+    /// to a topic already resolved with `topic`
+    fn publish_message(&mut self, topic: &str, payload: &str) -> TransportResult<()>;
+
+    /// Announce that we are going away and stop delivering events
+    fn disconnect(&mut self, tab_count: usize, active_url: Option<String>) -> TransportResult<()>;
+}
+
+/// Prefix `base` with `tinker/<instance>/` when running with an instance id
+pub fn namespaced_topic(instance_id: Option<&str>, base: &str) -> String {
+    match instance_id {
+        Some(instance_id) => format!("{}/{}/{}", TOPIC_ROOT, instance_id, base),
+        None => base.to_string(),
+    }
+}
+
+/// Replace characters that are not valid in a single MQTT topic level with `-`
+pub fn sanitize_instance_id(id: &str) -> String {
+    let sanitized: String = id.chars()
+        .map(|c| if matches!(c, '/' | '+' | '#') || c.is_whitespace() { '-' } else { c })
+        .collect();
+    if sanitized != id {
+        info!("Sanitized instance id {:?} to {:?}", id, sanitized);
+    }
+    sanitized
+}
+
+/// MQTT topic filter matching, supporting `+` (one level) and trailing `#`
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// A topic filter and where matching messages go
+type Subscription = (String, Sender<TransportMessage>);
+
+/// Fan-out of published messages to local subscribers
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    subscribers: Arc<Mutex<Vec<Subscription>>>,
+}
+
+impl Subscribers {
+    pub fn add(&self, filter: &str) -> Receiver<TransportMessage> {
+        let (tx, rx) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push((filter.to_string(), tx));
+        }
+        rx
+    }
+
+    /// Deliver to every matching subscriber, forgetting the ones that hung up
+    pub fn deliver(&self, topic: &str, payload: &str) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|(filter, tx)| {
+                !topic_matches(filter, topic) || tx.send(TransportMessage {
+                    topic: topic.to_string(),
+                    payload: payload.to_string(),
+                }).is_ok()
+            });
+        }
+    }
+}
+
+/// Parse a command payload, with or without correlation data
+pub fn parse_command(payload: &str) -> Result<(Option<CommandRequest>, BrowserCommand), CommandError> {
+    match serde_json::from_str::<IncomingCommand>(payload) {
+        Ok(IncomingCommand::Request(request)) => {
+            let command = request.command.clone();
+            Ok((Some(request), command))
+        }
+        Ok(IncomingCommand::Bare(command)) => Ok((None, command)),
+        Err(_) => Err(CommandError::new("invalid_command", format!("Invalid command format: {}", payload))),
+    }
+}
+
+/// How long to wait for the engine before reporting a timeout
+pub fn command_timeout(request: Option<&CommandRequest>) -> Duration {
+    Duration::from_millis(
+        request
+            .and_then(|request| request.timeout_ms)
+            .unwrap_or(DEFAULT_COMMAND_TIMEOUT_MS)
+    )
+}
+
+/// Events published once a command has finished, successfully or not
+pub fn command_finished_events(command: &str, outcome: &CommandOutcome) -> Vec<BrowserEvent> {
+    let mut events = Vec::new();
+    if let Err(error) = outcome {
+        events.push(BrowserEvent::Error { message: error.message.clone() });
+    }
+    events.push(BrowserEvent::CommandExecuted {
+        command: command.to_string(),
+        success: outcome.is_ok(),
+    });
+    events
+}
+
+/// Hands commands to the browser engine and waits for their outcome.
+///
+/// Clones share the engine's command sender, so a transport can pass one to
+/// its connection threads.
+#[derive(Clone, Default)]
+pub struct CommandDispatcher {
+    sender: Arc<Mutex<Option<Sender<PendingCommand>>>>,
+    policy: Arc<Mutex<CommandPolicy>>,
+}
+
+impl CommandDispatcher {
+    pub fn set_sender(&self, sender: Sender<PendingCommand>) {
+        if let Ok(mut current) = self.sender.lock() {
+            *current = Some(sender);
+        }
+    }
+
+    pub fn sender(&self) -> Option<Sender<PendingCommand>> {
+        self.sender.lock().ok().and_then(|sender| sender.clone())
+    }
+
+    pub fn set_policy(&self, policy: CommandPolicy) {
+        if let Ok(mut current) = self.policy.lock() {
+            *current = policy;
+        }
+    }
+
+    /// Whether incoming commands should be ignored altogether
+    pub fn is_observer(&self) -> bool {
+        self.policy.lock().map(|policy| policy.observer).unwrap_or(false)
+    }
+
+    /// Check a command against the policy before dispatching it
+    pub fn authorize(&self, request: Option<&CommandRequest>, command: &BrowserCommand) -> Result<(), CommandRejection> {
+        match self.policy.lock() {
+            Ok(policy) => policy.authorize(request, command),
+            // A poisoned policy must not turn into an open door
+            Err(_) => Err(CommandRejection::CommandNotAllowed { command: command.name().to_string() }),
+        }
+    }
+
+    /// Queue a command and call `finish` with its outcome once it has really run.
+    ///
+    /// Waiting happens on a separate thread so a slow command does not stall
+    /// the caller's connection.
+    pub fn dispatch<F>(&self, command: BrowserCommand, timeout: Duration, finish: F)
+    where
+        F: FnOnce(CommandOutcome, Duration) + Send + 'static,
+    {
+        let started = Instant::now();
+        let Some(sender) = self.sender() else {
+            let error = CommandError::new("unavailable", "No browser engine is accepting commands");
+            finish(Err(error), started.elapsed());
+            return;
+        };
+
+        debug!("Dispatching command: {:?}", command);
+        let (pending, outcome_rx) = PendingCommand::with_responder(command);
+        if let Err(e) = sender.send(pending) {
+            let error = CommandError::new("unavailable", format!("Failed to send command: {}", e));
+            finish(Err(error), started.elapsed());
+            return;
+        }
+
+        std::thread::spawn(move || {
+            let outcome = match outcome_rx.recv_timeout(timeout) {
+                Ok(outcome) => outcome,
+                Err(RecvTimeoutError::Timeout) => Err(CommandError::new(
+                    "timeout",
+                    format!("Command did not complete within {} ms", timeout.as_millis()),
+                )),
+                Err(RecvTimeoutError::Disconnected) => Err(CommandError::new(
+                    "dropped",
+                    "Browser engine dropped the command without a result",
+                )),
+            };
+            finish(outcome, started.elapsed());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("browser/#", "browser/tabs/created"));
+        assert!(topic_matches("tinker/+/browser/navigation", "tinker/ci/browser/navigation"));
+        assert!(topic_matches("browser/error", "browser/error"));
+        assert!(!topic_matches("browser/+", "browser/tabs/created"));
+        assert!(!topic_matches("browser/error", "browser/navigation"));
+        assert!(!topic_matches("browser/tabs", "browser"));
+    }
+
+    #[test]
+    fn test_subscribers_drop_closed_receivers() {
+        let subscribers = Subscribers::default();
+        let tabs = subscribers.add("browser/tabs/#");
+        drop(subscribers.add("browser/#"));
+
+        subscribers.deliver("browser/tabs/closed", "{}");
+        subscribers.deliver("browser/error", "{}");
+
+        assert_eq!(tabs.try_iter().map(|m| m.topic).collect::<Vec<_>>(), vec!["browser/tabs/closed"]);
+        assert_eq!(subscribers.subscribers.lock().unwrap().len(), 1);
+    }
+}
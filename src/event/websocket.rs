@@ -0,0 +1,211 @@
+//! WebSocket event transport served by the axum API.
+//!
+//! Every published message is sent to connected sockets as
+//! `{"topic": ..., "payload": ...}`. Text frames from a socket are handled like
+//! payloads on the MQTT command topic; correlated replies go back to that
+//! socket only. Connect with `?topics=browser/tabs/#,browser/error` to filter.
+
+use std::sync::{Arc, mpsc::{Receiver, Sender}};
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Query, State},
+    response::Response,
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc as async_mpsc};
+use tracing::{debug, error, info, warn};
+use super::in_process::InProcessTransport;
+use super::transport::{self, TransportMessage, TransportResult};
+use super::{CommandPolicy, EventEnvelope, EventSequencer, EventTransport, InstanceStatus, PendingCommand, Presence};
+
+/// Port the WebSocket transport is served on unless configured otherwise
+pub const DEFAULT_WS_PORT: u16 = 3004;
+
+/// Path the transport is mounted at
+pub const WS_PATH: &str = "/events/ws";
+
+/// Messages buffered per socket before a slow client starts missing them
+const FEED_CAPACITY: usize = 1024;
+
+/// Event transport whose subscribers are WebSocket clients
+#[derive(Clone)]
+pub struct WebSocketTransport {
+    local: InProcessTransport,
+    feed: broadcast::Sender<TransportMessage>,
+}
+
+impl Default for WebSocketTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WebSocketTransport {
+    pub fn new() -> Self {
+        let (feed, _) = broadcast::channel(FEED_CAPACITY);
+        Self {
+            local: InProcessTransport::new(),
+            feed,
+        }
+    }
+
+    /// Namespace every topic under `tinker/<instance>/`
+    pub fn with_instance_id(mut self, instance_id: Option<&str>) -> Self {
+        self.local = self.local.with_instance_id(instance_id);
+        self
+    }
+
+    /// Routes serving the transport at `/events/ws`, to merge into the API router
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route(WS_PATH, get(ws_handler))
+            .with_state(self.clone())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FeedParams {
+    /// Comma-separated topic filters; everything when absent
+    topics: Option<String>,
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Query(params): Query<FeedParams>,
+    State(transport): State<WebSocketTransport>,
+) -> Response {
+    let filters: Vec<String> = params.topics
+        .map(|topics| topics.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+        .unwrap_or_default();
+    // Subscribe before upgrading so nothing published after the handshake is missed
+    let feed = transport.feed.subscribe();
+    ws.on_upgrade(move |socket| handle_socket(socket, transport, feed, filters))
+}
+
+/// Wire form of a message; JSON payloads are embedded rather than quoted
+fn frame(message: &TransportMessage) -> String {
+    let payload = serde_json::from_str::<Value>(&message.payload)
+        .unwrap_or_else(|_| Value::String(message.payload.clone()));
+    json!({ "topic": message.topic, "payload": payload }).to_string()
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    transport: WebSocketTransport,
+    mut feed: broadcast::Receiver<TransportMessage>,
+    filters: Vec<String>,
+) {
+    debug!("WebSocket client connected (filters: {:?})", filters);
+    let (reply_tx, mut reply_rx) = async_mpsc::unbounded_channel::<TransportMessage>();
+    let wanted = |topic: &str| filters.is_empty() || filters.iter().any(|f| transport::topic_matches(f, topic));
+
+    loop {
+        let outgoing = tokio::select! {
+            message = feed.recv() => match message {
+                Ok(message) if wanted(&message.topic) => message,
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("WebSocket client fell behind and missed {} messages", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            Some(reply) = reply_rx.recv() => reply,
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(payload))) => {
+                    let reply_tx = reply_tx.clone();
+                    transport.local.execute(&payload, move |topic, response| {
+                        match serde_json::to_string(&response) {
+                            Ok(payload) => {
+                                let _ = reply_tx.send(TransportMessage { topic, payload });
+                            }
+                            Err(e) => error!("Failed to serialize command response: {}", e),
+                        }
+                    });
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    debug!("WebSocket error: {}", e);
+                    break;
+                }
+            },
+        };
+
+        if socket.send(Message::Text(frame(&outgoing))).await.is_err() {
+            break;
+        }
+    }
+    debug!("WebSocket client disconnected");
+}
+
+impl EventTransport for WebSocketTransport {
+    fn describe(&self) -> String {
+        format!("websocket ({})", WS_PATH)
+    }
+
+    /// Forward everything published locally to the sockets
+    fn connect(&mut self) -> TransportResult<()> {
+        let messages = self.local.subscribe("#")?;
+        let feed = self.feed.clone();
+        std::thread::spawn(move || {
+            for message in messages {
+                // No connected sockets is not an error
+                let _ = feed.send(message);
+            }
+        });
+        info!("WebSocket event transport ready at {}", WS_PATH);
+        self.local.connect()
+    }
+
+    fn publish_envelope(&mut self, envelope: &EventEnvelope) -> TransportResult<()> {
+        self.local.publish_envelope(envelope)
+    }
+
+    fn subscribe(&mut self, filter: &str) -> TransportResult<Receiver<TransportMessage>> {
+        self.local.subscribe(filter)
+    }
+
+    fn set_command_sender(&mut self, sender: Sender<PendingCommand>) {
+        self.local.set_command_sender(sender);
+    }
+
+    fn set_command_policy(&mut self, policy: CommandPolicy) {
+        self.local.set_command_policy(policy);
+    }
+
+    fn set_headless(&mut self, headless: bool) {
+        self.local.set_headless(headless);
+    }
+
+    fn topic(&self, base: &str) -> String {
+        self.local.topic(base)
+    }
+
+    fn sequencer(&self) -> Arc<EventSequencer> {
+        self.local.sequencer()
+    }
+
+    fn set_sequencer(&mut self, sequencer: Arc<EventSequencer>) {
+        self.local.set_sequencer(sequencer);
+    }
+
+    fn status(&self, presence: Presence, tab_count: usize, active_url: Option<String>) -> InstanceStatus {
+        self.local.status(presence, tab_count, active_url)
+    }
+
+    fn publish_status(&mut self, status: &InstanceStatus) -> TransportResult<()> {
+        self.local.publish_status(status)
+    }
+
+    fn publish_message(&mut self, topic: &str, payload: &str) -> TransportResult<()> {
+        self.local.publish_message(topic, payload)
+    }
+
+    fn disconnect(&mut self, tab_count: usize, active_url: Option<String>) -> TransportResult<()> {
+        self.local.disconnect(tab_count, active_url)
+    }
+}
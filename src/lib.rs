@@ -1,6 +1,7 @@
 pub mod api;
 pub mod browser;
 pub mod event;
+pub mod network;
 pub mod platform;
 pub mod templates;
 
@@ -0,0 +1,207 @@
+//! The certificate authority the proxy uses to read HTTPS traffic.
+//!
+//! The CA is created on first use and kept in a per-user directory, its key
+//! readable by the owner only, so it only has to be trusted once a month: it
+//! is valid for `CA_VALIDITY_DAYS` and replaced when less than a day is left.
+//! It can only sign site certificates, not other CAs. Site certificates are
+//! issued on demand and cached per host.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, IsCa, KeyPair, KeyUsagePurpose};
+use tokio_rustls::rustls::{self, ServerConfig};
+use super::NetworkError;
+
+pub const CA_CERT_FILE: &str = "tinker-ca.pem";
+const CA_KEY_FILE: &str = "tinker-ca-key.pem";
+const CA_NAME: &str = "Tinker Network Recorder CA";
+
+/// How long a new CA is valid for
+pub const CA_VALIDITY_DAYS: i64 = 30;
+
+pub struct CertificateAuthority {
+    ca: Certificate,
+    cert_path: PathBuf,
+    expires: DateTime<Utc>,
+    sites: Mutex<HashMap<String, Arc<ServerConfig>>>,
+}
+
+/// `tinker/ca` in the user's configuration directory, or `tinker-ca` when there is none
+pub fn default_dir() -> PathBuf {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    match config {
+        Some(config) => config.join("tinker").join("ca"),
+        None => PathBuf::from("tinker-ca"),
+    }
+}
+
+impl CertificateAuthority {
+    /// Load the CA kept in `dir`, creating it there if there is none yet or it is about to expire
+    pub fn load_or_create(dir: &Path) -> Result<Self, NetworkError> {
+        let cert_path = dir.join(CA_CERT_FILE);
+        let key_path = dir.join(CA_KEY_FILE);
+        let io_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| NetworkError::Io { path, source }
+        };
+
+        // The certificate is written once, so its age says when it expires
+        let now = Utc::now();
+        let saved = fs::metadata(&cert_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(|modified| DateTime::<Utc>::from(modified) + Duration::days(CA_VALIDITY_DAYS))
+            .filter(|expires| *expires - now > Duration::days(1));
+        let key_pair = match saved {
+            Some(_) if key_path.exists() => {
+                restrict_permissions(&key_path).map_err(io_error(&key_path))?;
+                let pem = fs::read_to_string(&key_path).map_err(io_error(&key_path))?;
+                Some(KeyPair::from_pem(&pem).map_err(|e| NetworkError::Certificate(e.to_string()))?)
+            }
+            _ => None,
+        };
+        let created = key_pair.is_none();
+        let expires = saved.filter(|_| !created).unwrap_or(now + Duration::days(CA_VALIDITY_DAYS));
+
+        // Signing only needs the key and the name, so a reloaded CA issues
+        // certificates that chain to the one saved when it was created
+        let ca = Certificate::from_params(ca_params(key_pair, now - Duration::days(1), expires))
+            .map_err(|e| NetworkError::Certificate(e.to_string()))?;
+        if created {
+            create_private_dir(dir).map_err(io_error(dir))?;
+            let cert = ca.serialize_pem().map_err(|e| NetworkError::Certificate(e.to_string()))?;
+            write_private(&key_path, ca.serialize_private_key_pem().as_bytes()).map_err(io_error(&key_path))?;
+            fs::write(&cert_path, cert).map_err(io_error(&cert_path))?;
+        }
+
+        Ok(Self {
+            ca,
+            cert_path,
+            expires,
+            sites: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The certificate to trust in the browser
+    pub fn cert_path(&self) -> &Path {
+        &self.cert_path
+    }
+
+    /// TLS settings presenting a certificate for `host`, signed by this CA
+    pub fn server_config(&self, host: &str) -> Result<Arc<ServerConfig>, NetworkError> {
+        let mut sites = self.sites.lock().map_err(|_| NetworkError::Proxy("Failed to lock certificate cache".to_string()))?;
+        if let Some(config) = sites.get(host) {
+            return Ok(config.clone());
+        }
+
+        let mut params = CertificateParams::new(vec![host.to_string()]);
+        params.distinguished_name = name(host);
+        // Never valid for longer than the CA itself
+        set_validity(&mut params, Utc::now() - Duration::days(1), self.expires);
+        let site = Certificate::from_params(params).map_err(|e| NetworkError::Certificate(e.to_string()))?;
+        let der = site.serialize_der_with_signer(&self.ca).map_err(|e| NetworkError::Certificate(e.to_string()))?;
+
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![rustls::Certificate(der)], rustls::PrivateKey(site.serialize_private_key_der()))
+            .map_err(|e| NetworkError::Certificate(e.to_string()))?;
+        let config = Arc::new(config);
+        sites.insert(host.to_string(), config.clone());
+        Ok(config)
+    }
+}
+
+fn name(common_name: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, common_name);
+    name
+}
+
+fn set_validity(params: &mut CertificateParams, from: DateTime<Utc>, until: DateTime<Utc>) {
+    params.not_before = rcgen::date_time_ymd(from.year(), from.month() as u8, from.day() as u8);
+    params.not_after = rcgen::date_time_ymd(until.year(), until.month() as u8, until.day() as u8);
+}
+
+fn ca_params(key_pair: Option<KeyPair>, from: DateTime<Utc>, until: DateTime<Utc>) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = name(CA_NAME);
+    // Site certificates only, no intermediate CAs
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    set_validity(&mut params, from, until);
+    params.key_pair = key_pair;
+    params
+}
+
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Write a file only its owner can read, replacing any file already there
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files
+    restrict_permissions(path)?;
+    file.write_all(contents)
+}
+
+fn restrict_permissions(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if fs::metadata(path)?.permissions().mode() & 0o077 != 0 {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ca_is_created_once_and_issues_site_certificates() {
+        let dir = std::env::temp_dir().join(format!("tinker-ca-{}", std::process::id()));
+        let ca = CertificateAuthority::load_or_create(&dir).unwrap();
+        let cert = fs::read_to_string(ca.cert_path()).unwrap();
+        assert!(cert.starts_with("-----BEGIN CERTIFICATE-----"));
+
+        // Reloading keeps the saved certificate, and site certificates are cached
+        let ca = CertificateAuthority::load_or_create(&dir).unwrap();
+        assert_eq!(fs::read_to_string(ca.cert_path()).unwrap(), cert);
+        let first = ca.server_config("example.com").unwrap();
+        assert!(Arc::ptr_eq(&first, &ca.server_config("example.com").unwrap()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ca_key_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("tinker-ca-key-{}", std::process::id()));
+        CertificateAuthority::load_or_create(&dir).unwrap();
+        let mode = fs::metadata(dir.join(CA_KEY_FILE)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,441 @@
+//! HAR 1.2 archives: the recorder that writes them and the index replays
+//! serve responses from.
+//!
+//! Bodies that are not UTF-8 are stored base64-encoded with
+//! `"encoding": "base64"`, as the format allows for response content; request
+//! bodies use the same field, which most HAR tools tolerate.
+//!
+//! The recorder keeps the archive in memory and writes it when recording
+//! stops, so the proxy never waits on the disk. Cookies and credentials are
+//! left out of archives unless asked for, as archives tend to get shared.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{debug, error};
+use crate::event::BrowserEvent;
+use super::NetworkError;
+
+pub const HAR_VERSION: &str = "1.2";
+
+/// Headers left out of archives unless credentials are kept
+pub const CREDENTIAL_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie", "set-cookie"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HarLog {
+    pub version: String,
+    pub creator: Creator,
+    #[serde(default)]
+    pub pages: Vec<Page>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Creator {
+    pub name: String,
+    pub version: String,
+}
+
+/// One browser tab; entries point at it through `pageref`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page {
+    pub started_date_time: String,
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub page_timings: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pageref: Option<String>,
+    pub started_date_time: String,
+    /// Total time in milliseconds
+    pub time: f64,
+    pub request: Request,
+    pub response: Response,
+    #[serde(default)]
+    pub cache: Map<String, Value>,
+    pub timings: Timings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<Value>,
+    pub headers: Vec<Header>,
+    #[serde(default)]
+    pub query_string: Vec<Header>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<PostData>,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostData {
+    pub mime_type: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub status: u16,
+    pub status_text: String,
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<Value>,
+    pub headers: Vec<Header>,
+    pub content: Content,
+    #[serde(rename = "redirectURL", default)]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    pub size: i64,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+/// Phase durations in milliseconds; the proxy only tells waiting apart
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Timings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+/// A body as HAR text and encoding
+pub fn encode_body(body: &[u8]) -> (String, Option<String>) {
+    match std::str::from_utf8(body) {
+        Ok(text) => (text.to_string(), None),
+        Err(_) => (BASE64.encode(body), Some("base64".to_string())),
+    }
+}
+
+/// The bytes of a HAR body
+pub fn decode_body(text: &str, encoding: Option<&str>) -> Result<Vec<u8>, String> {
+    match encoding {
+        Some("base64") => BASE64.decode(text).map_err(|e| format!("Invalid base64 body: {}", e)),
+        Some(other) => Err(format!("Unsupported body encoding '{}'", other)),
+        None => Ok(text.as_bytes().to_vec()),
+    }
+}
+
+impl Har {
+    pub fn new() -> Self {
+        Self {
+            log: HarLog {
+                version: HAR_VERSION.to_string(),
+                creator: Creator {
+                    name: "Tinker".to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                pages: Vec::new(),
+                entries: Vec::new(),
+            },
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, NetworkError> {
+        let file = File::open(path).map_err(|source| NetworkError::Io { path: path.to_path_buf(), source })?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| NetworkError::InvalidArchive {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })
+    }
+
+    /// Write the archive in one go, replacing the file only once it is complete
+    pub fn save(&self, path: &Path) -> Result<(), NetworkError> {
+        let io_error = |source| NetworkError::Io { path: path.to_path_buf(), source };
+        let partial = path.with_extension("har.partial");
+        let file = File::create(&partial).map_err(io_error)?;
+        let mut writer = BufWriter::new(&file);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(|e| io_error(e.into()))?;
+        writer.flush().map_err(io_error)?;
+        drop(writer);
+        file.sync_data().map_err(io_error)?;
+        std::fs::rename(&partial, path).map_err(io_error)
+    }
+}
+
+impl Default for Har {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects entries while recording, attributing each to the active tab
+pub struct NetworkRecorder {
+    har: Har,
+    path: PathBuf,
+    active_tab: Option<usize>,
+    keep_credentials: bool,
+    dirty: bool,
+}
+
+impl NetworkRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            har: Har::new(),
+            path,
+            active_tab: None,
+            keep_credentials: false,
+            dirty: false,
+        }
+    }
+
+    /// Archive cookies and authorization headers too
+    pub fn with_credentials(mut self, keep: bool) -> Self {
+        self.keep_credentials = keep;
+        self
+    }
+
+    /// Follow the browser's tabs so entries land on the page of the tab that was active
+    pub fn observe(&mut self, event: &BrowserEvent) {
+        match event {
+            BrowserEvent::TabCreated { id, url } => {
+                self.add_page(*id, url);
+                self.active_tab.get_or_insert(*id);
+            }
+            BrowserEvent::TabActivated { id } => {
+                self.add_page(*id, "");
+                self.active_tab = Some(*id);
+            }
+            BrowserEvent::TabClosed { id } if self.active_tab == Some(*id) => self.active_tab = None,
+            _ => {}
+        }
+    }
+
+    fn add_page(&mut self, id: usize, url: &str) {
+        let page_id = page_id(id);
+        if self.har.log.pages.iter().all(|page| page.id != page_id) {
+            self.har.log.pages.push(Page {
+                started_date_time: chrono::Utc::now().to_rfc3339(),
+                id: page_id,
+                title: url.to_string(),
+                page_timings: Map::new(),
+            });
+        }
+    }
+
+    pub fn record(&mut self, mut entry: Entry) {
+        entry.pageref = self.active_tab.map(page_id);
+        debug!("Recorded {} {} -> {}", entry.request.method, entry.request.url, entry.response.status);
+        if !self.keep_credentials {
+            redact(&mut entry);
+        }
+        self.har.log.entries.push(entry);
+        self.dirty = true;
+    }
+
+    /// Write the archive if anything was recorded since the last write; the
+    /// browser does so when recording stops, and dropping the recorder does too
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        match self.har.save(&self.path) {
+            Ok(()) => self.dirty = false,
+            Err(e) => error!("Failed to save network archive: {}", e),
+        }
+    }
+}
+
+impl Drop for NetworkRecorder {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Drop the cookies and credentials an exchange carried
+fn redact(entry: &mut Entry) {
+    let credential = |header: &Header| CREDENTIAL_HEADERS.iter().any(|name| header.name.eq_ignore_ascii_case(name));
+    entry.request.headers.retain(|header| !credential(header));
+    entry.response.headers.retain(|header| !credential(header));
+    entry.request.cookies.clear();
+    entry.response.cookies.clear();
+}
+
+fn page_id(tab_id: usize) -> String {
+    format!("tab_{}", tab_id)
+}
+
+/// Responses from an archive, looked up by method and URL. Repeated
+/// requests get the recorded responses in order, the last one once they run out.
+pub struct ArchiveIndex {
+    entries: Vec<Entry>,
+    by_request: HashMap<(String, String), Vec<usize>>,
+    served: Mutex<HashMap<(String, String), usize>>,
+}
+
+impl ArchiveIndex {
+    pub fn new(har: Har) -> Self {
+        let mut by_request: HashMap<(String, String), Vec<usize>> = HashMap::new();
+        for (index, entry) in har.log.entries.iter().enumerate() {
+            by_request.entry(request_key(&entry.request.method, &entry.request.url))
+                .or_default()
+                .push(index);
+        }
+        Self {
+            entries: har.log.entries,
+            by_request,
+            served: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The next recorded response to `method url`
+    pub fn lookup(&self, method: &str, url: &str) -> Option<&Response> {
+        let key = request_key(method, url);
+        let indexes = self.by_request.get(&key)?;
+        let mut served = self.served.lock().ok()?;
+        let count = served.entry(key).or_insert(0);
+        let index = indexes[(*count).min(indexes.len() - 1)];
+        *count += 1;
+        Some(&self.entries[index].response)
+    }
+}
+
+/// URLs compare without their fragment, which never reaches the network
+fn request_key(method: &str, url: &str) -> (String, String) {
+    let url = url.split('#').next().unwrap_or(url);
+    (method.to_ascii_uppercase(), url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(method: &str, url: &str, body: &[u8]) -> Entry {
+        let (text, encoding) = encode_body(body);
+        Entry {
+            pageref: None,
+            started_date_time: "2024-01-01T00:00:00+00:00".to_string(),
+            time: 12.0,
+            request: Request {
+                method: method.to_string(),
+                url: url.to_string(),
+                http_version: "HTTP/1.1".to_string(),
+                cookies: Vec::new(),
+                headers: Vec::new(),
+                query_string: Vec::new(),
+                post_data: None,
+                headers_size: -1,
+                body_size: 0,
+            },
+            response: Response {
+                status: 200,
+                status_text: "OK".to_string(),
+                http_version: "HTTP/1.1".to_string(),
+                cookies: Vec::new(),
+                headers: vec![Header { name: "content-type".to_string(), value: "text/plain".to_string() }],
+                content: Content { size: body.len() as i64, mime_type: "text/plain".to_string(), text: Some(text), encoding },
+                redirect_url: String::new(),
+                headers_size: -1,
+                body_size: body.len() as i64,
+            },
+            cache: Map::new(),
+            timings: Timings { send: 0.0, wait: 12.0, receive: 0.0 },
+        }
+    }
+
+    #[test]
+    fn test_bodies_round_trip() {
+        let (text, encoding) = encode_body(&[0xff, 0x00, 0x10]);
+        assert_eq!(encoding.as_deref(), Some("base64"));
+        assert_eq!(decode_body(&text, encoding.as_deref()).unwrap(), vec![0xff, 0x00, 0x10]);
+        assert_eq!(encode_body(b"hello"), ("hello".to_string(), None));
+    }
+
+    #[test]
+    fn test_repeated_requests_replay_in_order() {
+        let mut har = Har::new();
+        har.log.entries.push(entry("GET", "https://example.com/n", b"1"));
+        har.log.entries.push(entry("GET", "https://example.com/n", b"2"));
+        let index = ArchiveIndex::new(har);
+
+        let body = |response: Option<&Response>| response.and_then(|r| r.content.text.clone());
+        assert_eq!(body(index.lookup("get", "https://example.com/n#top")), Some("1".to_string()));
+        assert_eq!(body(index.lookup("GET", "https://example.com/n")), Some("2".to_string()));
+        assert_eq!(body(index.lookup("GET", "https://example.com/n")), Some("2".to_string()));
+        assert!(index.lookup("POST", "https://example.com/n").is_none());
+    }
+
+    #[test]
+    fn test_recorder_attributes_entries_to_tabs() {
+        let path = std::env::temp_dir().join(format!("tinker-har-{}.har", std::process::id()));
+        let mut recorder = NetworkRecorder::new(path.clone());
+        recorder.observe(&BrowserEvent::TabCreated { id: 0, url: "https://example.com".to_string() });
+        recorder.record(entry("GET", "https://example.com/", b"home"));
+        recorder.observe(&BrowserEvent::TabCreated { id: 1, url: "https://example.org".to_string() });
+        recorder.observe(&BrowserEvent::TabActivated { id: 1 });
+        recorder.record(entry("GET", "https://example.org/", b"other"));
+        recorder.flush();
+
+        let har = Har::load(&path).unwrap();
+        assert_eq!(har.log.version, HAR_VERSION);
+        assert_eq!(har.log.pages.len(), 2);
+        let pages: Vec<_> = har.log.entries.iter().map(|entry| entry.pageref.as_deref()).collect();
+        assert_eq!(pages, vec![Some("tab_0"), Some("tab_1")]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_credentials_are_left_out() {
+        let header = |name: &str| Header { name: name.to_string(), value: "secret".to_string() };
+        let mut login = entry("POST", "https://example.com/login", b"ok");
+        login.request.headers = vec![header("Authorization"), header("Cookie"), header("Accept")];
+        login.response.headers.push(header("set-cookie"));
+
+        let path = std::env::temp_dir().join(format!("tinker-har-credentials-{}.har", std::process::id()));
+        let mut recorder = NetworkRecorder::new(path.clone());
+        recorder.record(login.clone());
+        let recorded = &recorder.har.log.entries[0];
+        let names: Vec<_> = recorded.request.headers.iter().map(|header| header.name.as_str()).collect();
+        assert_eq!(names, vec!["Accept"]);
+        assert_eq!(recorded.response.headers, vec![Header { name: "content-type".to_string(), value: "text/plain".to_string() }]);
+
+        let mut keeping = NetworkRecorder::new(path).with_credentials(true);
+        keeping.record(login);
+        assert_eq!(keeping.har.log.entries[0].request.headers.len(), 3);
+        // Nothing to clean up afterwards
+        recorder.dirty = false;
+        keeping.dirty = false;
+    }
+}
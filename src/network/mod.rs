@@ -0,0 +1,49 @@
+//! Network recording and offline playback.
+//!
+//! With `--network`, the content view talks to the web through a local
+//! proxy. A recording session archives every request and response in a HAR
+//! file next to the event recording; a replay serves the responses from that
+//! archive, so it does not depend on the network or on sites staying the same.
+
+use std::path::{Path, PathBuf};
+
+pub mod ca;
+pub mod har;
+pub mod proxy;
+
+pub use self::ca::{default_dir as default_ca_dir, CertificateAuthority};
+pub use self::har::{ArchiveIndex, Har, NetworkRecorder};
+pub use self::proxy::{serve, ProxyMode};
+
+#[derive(Debug, thiserror::Error)]
+pub enum NetworkError {
+    #[error("Failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid network archive {path}: {message}")]
+    InvalidArchive { path: PathBuf, message: String },
+
+    #[error("Certificate error: {0}")]
+    Certificate(String),
+
+    #[error("Proxy error: {0}")]
+    Proxy(String),
+}
+
+/// Where the network archive of an event recording lives by default
+pub fn archive_path_for(recording: &Path) -> PathBuf {
+    recording.with_extension("har")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_sits_next_to_the_recording() {
+        assert_eq!(archive_path_for(Path::new("runs/session.jsonl")), PathBuf::from("runs/session.har"));
+    }
+}
@@ -0,0 +1,358 @@
+//! Local HTTP proxy the content view is pointed at.
+//!
+//! Plain HTTP arrives as absolute-URI requests. HTTPS arrives as `CONNECT`
+//! tunnels, which the proxy terminates with a certificate from the
+//! `CertificateAuthority` so it can read the requests inside. When recording,
+//! requests go to the network and each exchange becomes a HAR entry; when
+//! playing back, responses come from the archive and nothing leaves the machine.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use hyper::body::Bytes;
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, HOST, LOCATION};
+use hyper::http::request::Parts;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Client, Method, Request, Response, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, warn};
+use super::ca::CertificateAuthority;
+use super::har::{self, ArchiveIndex, Content, Entry, Header, NetworkRecorder, PostData, Timings};
+use super::NetworkError;
+
+/// Headers that describe one connection rather than the message
+const HOP_BY_HOP: &[&str] = &[
+    "connection", "keep-alive", "proxy-authenticate", "proxy-authorization", "proxy-connection",
+    "te", "trailer", "transfer-encoding", "upgrade",
+];
+
+/// Marks responses the proxy made up rather than recorded or fetched
+const PROXY_HEADER: &str = "x-tinker-proxy";
+
+pub enum ProxyMode {
+    /// Fetch from the network and archive every exchange
+    Record(Arc<Mutex<NetworkRecorder>>),
+    /// Answer from an archive only
+    Playback(Arc<ArchiveIndex>),
+}
+
+struct Proxy {
+    mode: ProxyMode,
+    ca: Arc<CertificateAuthority>,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+/// Start the proxy on `addr` (port 0 picks a free one) and return where it listens
+pub async fn serve(mode: ProxyMode, ca: Arc<CertificateAuthority>, addr: SocketAddr) -> Result<SocketAddr, NetworkError> {
+    let listener = TcpListener::bind(addr).await.map_err(|e| NetworkError::Proxy(format!("Failed to bind {}: {}", addr, e)))?;
+    let addr = listener.local_addr().map_err(|e| NetworkError::Proxy(e.to_string()))?;
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let proxy = Arc::new(Proxy { mode, ca, client: Client::builder().build(connector) });
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("Proxy failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let proxy = proxy.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| proxy.clone().handle(request));
+                if let Err(e) = Http::new().http1_only(true).serve_connection(stream, service).with_upgrades().await {
+                    debug!("Proxy connection ended: {}", e);
+                }
+            });
+        }
+    });
+    Ok(addr)
+}
+
+impl Proxy {
+    async fn handle(self: Arc<Self>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+        if request.method() == Method::CONNECT {
+            return Ok(self.tunnel(request));
+        }
+        let url = request.uri().to_string();
+        Ok(self.exchange(request, url).await)
+    }
+
+    /// Accept a CONNECT and serve the HTTPS requests inside it
+    fn tunnel(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        let Some(authority) = request.uri().authority().cloned() else {
+            return proxy_response(StatusCode::BAD_REQUEST, "CONNECT needs a host and port".to_string());
+        };
+        let config = match self.ca.server_config(authority.host()) {
+            Ok(config) => config,
+            Err(e) => return proxy_response(StatusCode::BAD_GATEWAY, e.to_string()),
+        };
+        let origin = match authority.port_u16() {
+            Some(443) | None => format!("https://{}", authority.host()),
+            Some(port) => format!("https://{}:{}", authority.host(), port),
+        };
+
+        tokio::spawn(async move {
+            let upgraded = match hyper::upgrade::on(request).await {
+                Ok(upgraded) => upgraded,
+                Err(e) => return debug!("CONNECT to {} was not upgraded: {}", origin, e),
+            };
+            let stream = match TlsAcceptor::from(config).accept(upgraded).await {
+                Ok(stream) => stream,
+                // Usually the browser refusing the certificate: the CA is not trusted
+                Err(e) => return warn!("TLS with the browser failed for {}: {}", origin, e),
+            };
+            let service = service_fn(move |request: Request<Body>| {
+                let url = format!("{}{}", origin, request.uri().path_and_query().map_or("/", |path| path.as_str()));
+                let proxy = self.clone();
+                async move { Ok::<_, Infallible>(proxy.exchange(request, url).await) }
+            });
+            if let Err(e) = Http::new().http1_only(true).serve_connection(stream, service).await {
+                debug!("Tunnel connection ended: {}", e);
+            }
+        });
+        Response::new(Body::empty())
+    }
+
+    async fn exchange(&self, request: Request<Body>, url: String) -> Response<Body> {
+        match &self.mode {
+            ProxyMode::Playback(archive) => replay(archive, request.method(), &url),
+            ProxyMode::Record(recorder) => match self.forward(recorder, request, &url).await {
+                Ok(response) => response,
+                Err(message) => {
+                    warn!("Proxy failed to fetch {}: {}", url, message);
+                    proxy_response(StatusCode::BAD_GATEWAY, message)
+                }
+            },
+        }
+    }
+
+    /// Fetch from the network and archive the exchange
+    async fn forward(&self, recorder: &Mutex<NetworkRecorder>, request: Request<Body>, url: &str) -> Result<Response<Body>, String> {
+        let started_at = chrono::Utc::now().to_rfc3339();
+        let started = Instant::now();
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await.map_err(|e| format!("Failed to read request body: {}", e))?;
+
+        let uri: Uri = url.parse().map_err(|e| format!("Invalid URL: {}", e))?;
+        let mut upstream = Request::builder().method(parts.method.clone()).uri(uri);
+        for (name, value) in end_to_end(&parts.headers) {
+            if name != HOST {
+                upstream = upstream.header(name, value);
+            }
+        }
+        // Archive bodies as the page sees them, without content encodings to undo on playback
+        upstream = upstream.header("accept-encoding", "identity");
+        let upstream = upstream.body(Body::from(body.clone())).map_err(|e| e.to_string())?;
+
+        let response = self.client.request(upstream).await.map_err(|e| e.to_string())?;
+        let (response_parts, response_body) = response.into_parts();
+        let response_body = hyper::body::to_bytes(response_body).await
+            .map_err(|e| format!("Failed to read response body: {}", e))?;
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        let entry = har_entry(&parts, &body, url, &response_parts, &response_body, started_at, elapsed_ms);
+        if let Ok(mut recorder) = recorder.lock() {
+            recorder.record(entry);
+        }
+
+        let mut response = Response::builder().status(response_parts.status);
+        for (name, value) in end_to_end(&response_parts.headers) {
+            response = response.header(name, value);
+        }
+        response.body(Body::from(response_body)).map_err(|e| e.to_string())
+    }
+}
+
+/// Answer from the archive, never from the network
+fn replay(archive: &ArchiveIndex, method: &Method, url: &str) -> Response<Body> {
+    let Some(recorded) = archive.lookup(method.as_str(), url) else {
+        warn!("Not in the network archive: {} {}", method, url);
+        return proxy_response(StatusCode::NOT_FOUND, format!("{} {} is not in the network archive", method, url));
+    };
+    let body = match recorded.content.text.as_deref() {
+        Some(text) => match har::decode_body(text, recorded.content.encoding.as_deref()) {
+            Ok(body) => body,
+            Err(e) => return proxy_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+        },
+        None => Vec::new(),
+    };
+
+    let mut response = Response::builder().status(recorded.status);
+    for header in &recorded.headers {
+        let name = header.name.to_ascii_lowercase();
+        // Archived bodies are stored decoded, and the length follows from the body
+        if HOP_BY_HOP.contains(&name.as_str()) || name == "content-encoding" || name == "content-length" {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&header.value)) {
+            response = response.header(name, value);
+        }
+    }
+    response.body(Body::from(body))
+        .unwrap_or_else(|e| proxy_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+fn end_to_end(headers: &HeaderMap) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+    headers.iter().filter(|(name, _)| !HOP_BY_HOP.contains(&name.as_str()))
+}
+
+fn proxy_response(status: StatusCode, message: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(message));
+    *response.status_mut() = status;
+    response.headers_mut().insert(PROXY_HEADER, HeaderValue::from_static("1"));
+    response
+}
+
+fn har_headers(headers: &HeaderMap) -> Vec<Header> {
+    headers.iter()
+        .map(|(name, value)| Header {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        })
+        .collect()
+}
+
+fn header_text(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+}
+
+fn har_entry(
+    request: &Parts,
+    request_body: &Bytes,
+    url: &str,
+    response: &hyper::http::response::Parts,
+    response_body: &Bytes,
+    started_at: String,
+    elapsed_ms: f64,
+) -> Entry {
+    let query_string = url::Url::parse(url)
+        .map(|url| url.query_pairs().map(|(name, value)| Header { name: name.into_owned(), value: value.into_owned() }).collect())
+        .unwrap_or_default();
+    let post_data = (!request_body.is_empty()).then(|| {
+        let (text, encoding) = har::encode_body(request_body);
+        PostData {
+            mime_type: header_text(&request.headers, CONTENT_TYPE).unwrap_or_default(),
+            text,
+            encoding,
+        }
+    });
+    let (text, encoding) = har::encode_body(response_body);
+
+    Entry {
+        pageref: None,
+        started_date_time: started_at,
+        time: elapsed_ms,
+        request: har::Request {
+            method: request.method.to_string(),
+            url: url.to_string(),
+            http_version: format!("{:?}", request.version),
+            cookies: Vec::new(),
+            headers: har_headers(&request.headers),
+            query_string,
+            post_data,
+            headers_size: -1,
+            body_size: request_body.len() as i64,
+        },
+        response: har::Response {
+            status: response.status.as_u16(),
+            status_text: response.status.canonical_reason().unwrap_or_default().to_string(),
+            http_version: format!("{:?}", response.version),
+            cookies: Vec::new(),
+            headers: har_headers(&response.headers),
+            content: Content {
+                size: response_body.len() as i64,
+                mime_type: header_text(&response.headers, CONTENT_TYPE).unwrap_or_default(),
+                text: Some(text),
+                encoding,
+            },
+            redirect_url: header_text(&response.headers, LOCATION).unwrap_or_default(),
+            headers_size: -1,
+            body_size: response_body.len() as i64,
+        },
+        cache: Default::default(),
+        timings: Timings { send: 0.0, wait: elapsed_ms, receive: 0.0 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use super::super::har::Har;
+
+    /// A site that answers every request with a counter, so repeats are told apart
+    async fn site() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(Mutex::new(0));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let hits = hits.clone();
+                tokio::spawn(Http::new().serve_connection(stream, service_fn(move |request: Request<Body>| {
+                    let hits = hits.clone();
+                    async move {
+                        let hit = { let mut hits = hits.lock().unwrap(); *hits += 1; *hits };
+                        Ok::<_, Infallible>(Response::builder()
+                            .header(CONTENT_TYPE, "text/plain")
+                            .body(Body::from(format!("{} #{}", request.uri().path(), hit)))
+                            .unwrap())
+                    }
+                })));
+            }
+        });
+        addr
+    }
+
+    /// Send a plain HTTP request through the proxy and return the raw response
+    async fn get(proxy: SocketAddr, url: &str) -> String {
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n", url);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_record_then_play_back_offline() {
+        let dir = std::env::temp_dir().join(format!("tinker-proxy-{}", std::process::id()));
+        let ca = Arc::new(CertificateAuthority::load_or_create(&dir).unwrap());
+        let archive = dir.join("session.har");
+        let site = site().await;
+        let url = format!("http://{}/page?q=1", site);
+
+        let recorder = Arc::new(Mutex::new(NetworkRecorder::new(archive.clone())));
+        let proxy = serve(ProxyMode::Record(recorder.clone()), ca.clone(), "127.0.0.1:0".parse().unwrap()).await.unwrap();
+        assert!(get(proxy, &url).await.ends_with("/page #1"));
+        assert!(get(proxy, &url).await.ends_with("/page #2"));
+        recorder.lock().unwrap().flush();
+
+        let har = Har::load(&archive).unwrap();
+        assert_eq!(har.log.entries.len(), 2);
+        assert_eq!(har.log.entries[0].request.query_string[0].value, "1");
+        assert_eq!(har.log.entries[0].response.content.mime_type, "text/plain");
+
+        // Served in recorded order from the archive alone
+        let index = Arc::new(ArchiveIndex::new(har));
+        let proxy = serve(ProxyMode::Playback(index), ca, "127.0.0.1:0".parse().unwrap()).await.unwrap();
+        assert!(get(proxy, &url).await.ends_with("/page #1"));
+        assert!(get(proxy, &url).await.ends_with("/page #2"));
+        let missing = get(proxy, &format!("http://{}/elsewhere", site)).await;
+        assert!(missing.starts_with("HTTP/1.1 404"));
+        assert!(missing.contains(PROXY_HEADER));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+// Deterministic page: Date, performance.now and Math.random follow a seed
+// and a virtual clock instead of the real ones, and timers and animation
+// frames fire on that clock. The engine fills in STATE and EPOCH_MS, then
+// hands over the recording's timeline with window.__tinkerClock.set(ms, rate);
+// the clock runs on from there at rate times real time.
+(function ()
+{
+    if (window.__tinkerClock)
+    {
+        return;
+    }
+
+    const STATE = __TINKER_RANDOM_STATE__;
+    const EPOCH_MS = __TINKER_EPOCH_MS__;
+    const FRAME_MS = 1000 / 60;
+    const RealDate = Date;
+    const realNow = performance.now.bind(performance);
+    const realSetTimeout = window.setTimeout.bind(window);
+    const realClearTimeout = window.clearTimeout.bind(window);
+
+    // Where the clock was at realAnchor, and how fast it has run since
+    let base = 0;
+    let rate = 1;
+    let realAnchor = realNow();
+
+    // Time never runs backwards, even when the engine's reading lags the page's
+    let last = 0;
+    const clock = () =>
+    {
+        last = Math.max(last, base + (realNow() - realAnchor) * rate);
+        return last;
+    };
+    const now = () => EPOCH_MS + Math.floor(clock());
+
+    // Date() without arguments reads the virtual clock; explicit dates are untouched
+    function VirtualDate(...args)
+    {
+        if (!new.target)
+        {
+            return new RealDate(now()).toString();
+        }
+        return args.length ? new RealDate(...args) : new RealDate(now());
+    }
+    VirtualDate.prototype = RealDate.prototype;
+    VirtualDate.now = now;
+    VirtualDate.parse = RealDate.parse;
+    VirtualDate.UTC = RealDate.UTC;
+    window.Date = VirtualDate;
+
+    Object.defineProperty(performance, 'now', { value: clock, configurable: true });
+
+    // xoshiro128**, seeded by the engine so every page load draws the same sequence
+    const s = Uint32Array.from(STATE);
+    Math.random = function ()
+    {
+        const result = Math.imul(((Math.imul(s[1], 5) << 7) | (Math.imul(s[1], 5) >>> 25)), 9) >>> 0;
+        const t = s[1] << 9;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = (s[3] << 11) | (s[3] >>> 21);
+        return result / 4294967296;
+    };
+
+    // Timers wait for their virtual due time and fire in due order, ties by creation
+    const timers = new Map();
+    let nextId = 1;
+    let wakeUp = null;
+
+    function schedule()
+    {
+        if (wakeUp !== null)
+        {
+            realClearTimeout(wakeUp);
+            wakeUp = null;
+        }
+        if (!timers.size || rate <= 0)
+        {
+            return;
+        }
+        let due = Infinity;
+        timers.forEach((timer) => { due = Math.min(due, timer.due); });
+        wakeUp = realSetTimeout(fireDue, Math.max(0, (due - clock()) / rate));
+    }
+
+    function fireDue()
+    {
+        wakeUp = null;
+        const at = clock();
+        const ready = Array.from(timers.entries())
+            .filter(([, timer]) => timer.due <= at)
+            .sort(([a, x], [b, y]) => x.due - y.due || a - b);
+        ready.forEach(([id, timer]) =>
+        {
+            // An earlier callback may have cleared this one
+            if (timers.get(id) !== timer)
+            {
+                return;
+            }
+            if (timer.interval === null)
+            {
+                timers.delete(id);
+            } else
+            {
+                timer.due += timer.interval;
+            }
+            try
+            {
+                timer.callback();
+            } catch (e)
+            {
+                realSetTimeout(() => { throw e; });
+            }
+        });
+        schedule();
+    }
+
+    function addTimer(callback, delay, interval)
+    {
+        const id = nextId++;
+        const wait = Math.max(0, Number(delay) || 0);
+        timers.set(id, { due: clock() + wait, interval: interval ? Math.max(1, wait) : null, callback });
+        schedule();
+        return id;
+    }
+
+    function clearTimer(id)
+    {
+        if (timers.delete(id))
+        {
+            schedule();
+        }
+    }
+
+    function callbackOf(handler, args)
+    {
+        return typeof handler === 'function' ? () => handler(...args) : () => (0, eval)(String(handler));
+    }
+
+    window.setTimeout = (handler, delay, ...args) => addTimer(callbackOf(handler, args), delay, false);
+    window.setInterval = (handler, delay, ...args) => addTimer(callbackOf(handler, args), delay, true);
+    window.clearTimeout = clearTimer;
+    window.clearInterval = clearTimer;
+    window.requestAnimationFrame = (callback) => addTimer(() => callback(clock()), FRAME_MS, false);
+    window.cancelAnimationFrame = clearTimer;
+
+    Object.defineProperty(window, '__tinkerClock', {
+        value: Object.freeze({
+            set: (ms, speed) =>
+            {
+                base = ms;
+                rate = speed === undefined ? 1 : speed;
+                realAnchor = realNow();
+                schedule();
+            },
+            now: now,
+        }),
+    });
+})();
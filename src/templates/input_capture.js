@@ -0,0 +1,206 @@
+// Input capture: reports clicks, typing, form submissions, scrolling and
+// special keys to the browser engine so they end up in recordings. Nothing is
+// reported until the engine turns capture on for a recording.
+(function ()
+{
+    if (window.__tinkerInputCapture || !window.ipc || !window.ipc.postMessage)
+    {
+        return;
+    }
+    window.__tinkerInputCapture = true;
+
+    // Keep the native channel; the window chrome replaces window.ipc later on
+    const post = window.ipc.postMessage.bind(window.ipc);
+    const send = (msg) => post(JSON.stringify(msg));
+
+    let enabled = false;
+
+    // Only real pages are recorded, not the browser's own chrome
+    const captured = () => enabled && /^(https?|file):$/.test(location.protocol);
+
+    const INPUT_DELAY_MS = 400;
+    const SCROLL_DELAY_MS = 200;
+    const MODIFIER_KEYS = ['Shift', 'Control', 'Alt', 'Meta', 'CapsLock'];
+    const STABLE_ATTRIBUTES = ['data-testid', 'data-test', 'name', 'aria-label'];
+
+    function escape(value)
+    {
+        return window.CSS && CSS.escape ? CSS.escape(value) : value.replace(/[^\w-]/g, '\\$&');
+    }
+
+    function unique(selector)
+    {
+        try
+        {
+            return document.querySelectorAll(selector).length === 1;
+        } catch (e)
+        {
+            return false;
+        }
+    }
+
+    // Prefer ids and test attributes, which survive layout changes, over positions
+    function selectorFor(element)
+    {
+        const parts = [];
+        let current = element;
+        while (current && current.nodeType === Node.ELEMENT_NODE && current !== document.documentElement)
+        {
+            const tag = current.tagName.toLowerCase();
+            if (current.id && unique('#' + escape(current.id)))
+            {
+                parts.unshift('#' + escape(current.id));
+                return parts.join(' > ');
+            }
+            const attribute = STABLE_ATTRIBUTES.find((name) =>
+            {
+                const value = current.getAttribute(name);
+                return value && unique(`${tag}[${name}="${escape(value)}"]`);
+            });
+            if (attribute)
+            {
+                parts.unshift(`${tag}[${attribute}="${escape(current.getAttribute(attribute))}"]`);
+                return parts.join(' > ');
+            }
+
+            const parent = current.parentElement;
+            const siblings = parent ? Array.from(parent.children).filter((child) => child.tagName === current.tagName) : [];
+            parts.unshift(siblings.length > 1 ? `${tag}:nth-of-type(${siblings.indexOf(current) + 1})` : tag);
+            current = parent;
+        }
+        return parts.length ? parts.join(' > ') : 'html';
+    }
+
+    function elementOf(target)
+    {
+        return target instanceof Element ? target : (target && target.parentElement) || null;
+    }
+
+    // Typing is reported once the field goes quiet, not per keystroke
+    const pendingInput = new Map();
+
+    function flushInput(element)
+    {
+        const timer = pendingInput.get(element);
+        if (timer === undefined)
+        {
+            return;
+        }
+        clearTimeout(timer);
+        pendingInput.delete(element);
+
+        const redacted = element.type === 'password';
+        send({
+            type: 'textInput',
+            selector: selectorFor(element),
+            value: redacted ? '' : (element.isContentEditable ? element.textContent : element.value),
+            redacted: redacted,
+        });
+    }
+
+    function flushAllInput()
+    {
+        Array.from(pendingInput.keys()).forEach(flushInput);
+    }
+
+    document.addEventListener('input', (e) =>
+    {
+        const element = elementOf(e.target);
+        if (!e.isTrusted || !captured() || !element)
+        {
+            return;
+        }
+        clearTimeout(pendingInput.get(element));
+        pendingInput.set(element, setTimeout(() => flushInput(element), INPUT_DELAY_MS));
+    }, true);
+
+    document.addEventListener('change', (e) =>
+    {
+        const element = elementOf(e.target);
+        if (e.isTrusted && element)
+        {
+            flushInput(element);
+        }
+    }, true);
+
+    document.addEventListener('click', (e) =>
+    {
+        const element = elementOf(e.target);
+        if (!e.isTrusted || !captured() || !element)
+        {
+            return;
+        }
+        flushAllInput();
+        send({ type: 'click', selector: selectorFor(element), x: e.clientX, y: e.clientY, button: e.button });
+    }, true);
+
+    document.addEventListener('submit', (e) =>
+    {
+        if (!e.isTrusted || !captured())
+        {
+            return;
+        }
+        flushAllInput();
+        send({ type: 'formSubmitted', selector: selectorFor(e.target) });
+    }, true);
+
+    document.addEventListener('keydown', (e) =>
+    {
+        if (!e.isTrusted || !captured() || MODIFIER_KEYS.includes(e.key))
+        {
+            return;
+        }
+        // Printable characters without a shortcut modifier arrive as text input
+        const shortcut = e.ctrlKey || e.metaKey || e.altKey;
+        if (e.key.length === 1 && !shortcut)
+        {
+            return;
+        }
+        flushAllInput();
+        const modifiers = [['Control', e.ctrlKey], ['Alt', e.altKey], ['Shift', e.shiftKey], ['Meta', e.metaKey]]
+            .filter(([, held]) => held)
+            .map(([name]) => name);
+        const element = elementOf(e.target);
+        send({
+            type: 'keyPress',
+            selector: element && element !== document.body ? selectorFor(element) : null,
+            key: modifiers.concat([e.key]).join('+'),
+        });
+    }, true);
+
+    const pendingScroll = new Map();
+
+    // Called by the engine as recording starts and stops; input still waiting is dropped
+    window.__tinkerCaptureInput = (on) =>
+    {
+        enabled = !!on;
+        if (!enabled)
+        {
+            pendingInput.forEach((timer) => clearTimeout(timer));
+            pendingInput.clear();
+            pendingScroll.forEach((timer) => clearTimeout(timer));
+            pendingScroll.clear();
+        }
+    };
+
+    document.addEventListener('scroll', (e) =>
+    {
+        if (!e.isTrusted || !captured())
+        {
+            return;
+        }
+        const page = e.target === document || e.target === document.documentElement;
+        const target = page ? document : e.target;
+        clearTimeout(pendingScroll.get(target));
+        pendingScroll.set(target, setTimeout(() =>
+        {
+            pendingScroll.delete(target);
+            send({
+                type: 'scroll',
+                selector: page ? null : selectorFor(target),
+                x: page ? window.scrollX : target.scrollLeft,
+                y: page ? window.scrollY : target.scrollTop,
+            });
+        }, SCROLL_DELAY_MS));
+    }, true);
+})();
@@ -0,0 +1,112 @@
+// Replays one captured input event; the engine appends the call with the
+// event as `tinkerReplayInput({...})`. Returns the selector that was used.
+function tinkerReplayInput(input)
+{
+    function find(selector)
+    {
+        const element = selector ? document.querySelector(selector) : null;
+        if (selector && !element)
+        {
+            throw new Error('No element matches ' + selector);
+        }
+        return element;
+    }
+
+    function setValue(element, value)
+    {
+        if (element.isContentEditable)
+        {
+            element.textContent = value;
+        } else
+        {
+            // Use the prototype's setter so frameworks tracking the value notice the change
+            const descriptor = Object.getOwnPropertyDescriptor(Object.getPrototypeOf(element), 'value');
+            if (descriptor && descriptor.set)
+            {
+                descriptor.set.call(element, value);
+            } else
+            {
+                element.value = value;
+            }
+        }
+        element.dispatchEvent(new Event('input', { bubbles: true }));
+        element.dispatchEvent(new Event('change', { bubbles: true }));
+    }
+
+    switch (input.type)
+    {
+        case 'click':
+        {
+            const element = find(input.selector);
+            const init = { bubbles: true, cancelable: true, clientX: input.x, clientY: input.y, button: input.button };
+            element.dispatchEvent(new MouseEvent('mousedown', init));
+            if (typeof element.focus === 'function')
+            {
+                element.focus();
+            }
+            element.dispatchEvent(new MouseEvent('mouseup', init));
+            if (input.button === 0 && typeof element.click === 'function')
+            {
+                element.click();
+            } else
+            {
+                element.dispatchEvent(new MouseEvent(input.button === 2 ? 'contextmenu' : 'auxclick', init));
+            }
+            break;
+        }
+        case 'textInput':
+        {
+            const element = find(input.selector);
+            element.focus();
+            setValue(element, input.value);
+            break;
+        }
+        case 'formSubmitted':
+        {
+            const form = find(input.selector);
+            if (typeof form.requestSubmit === 'function')
+            {
+                form.requestSubmit();
+            } else
+            {
+                form.submit();
+            }
+            break;
+        }
+        case 'scroll':
+        {
+            if (input.selector)
+            {
+                const element = find(input.selector);
+                element.scrollLeft = input.x;
+                element.scrollTop = input.y;
+            } else
+            {
+                window.scrollTo(input.x, input.y);
+            }
+            break;
+        }
+        case 'keyPress':
+        {
+            const element = find(input.selector) || document.activeElement || document.body;
+            const parts = input.key.split('+');
+            // A literal plus sign is the last part left empty by the split
+            const key = parts.pop() || '+';
+            const init = {
+                key: key,
+                bubbles: true,
+                cancelable: true,
+                ctrlKey: parts.includes('Control'),
+                altKey: parts.includes('Alt'),
+                shiftKey: parts.includes('Shift'),
+                metaKey: parts.includes('Meta'),
+            };
+            element.dispatchEvent(new KeyboardEvent('keydown', init));
+            element.dispatchEvent(new KeyboardEvent('keyup', init));
+            break;
+        }
+        default:
+            throw new Error('Unknown input type ' + input.type);
+    }
+    return input.selector || null;
+}
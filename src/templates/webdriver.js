@@ -0,0 +1,426 @@
+// WebDriver support: element lookup, input, script execution and screenshots
+// for the WebDriver and DevTools endpoints. Sent ahead of every call and installed once per page.
+(function ()
+{
+    if (window.__tinkerWebDriver)
+    {
+        return;
+    }
+
+    const ELEMENT_KEY = 'element-6066-11e4-a52e-4f735466cecf';
+    // References from an earlier page must not resolve to elements of this one
+    const PAGE = Math.random().toString(36).slice(2);
+    const elements = new Map();
+    // Results that are not ready when the call returns, collected with `result`
+    const results = new Map();
+    let nextElement = 0;
+    let nextResult = 0;
+
+    // Keys from the WebDriver key table; everything else is typed as it is
+    const KEYS = {
+        '\uE003': 'Backspace',
+        '\uE004': 'Tab',
+        '\uE006': 'Enter',
+        '\uE007': 'Enter',
+        '\uE00C': 'Escape',
+        '\uE00D': ' ',
+        '\uE012': 'ArrowLeft',
+        '\uE013': 'ArrowUp',
+        '\uE014': 'ArrowRight',
+        '\uE015': 'ArrowDown',
+        '\uE017': 'Delete',
+    };
+
+    class WebDriverError extends Error
+    {
+        constructor(error, message)
+        {
+            super(message);
+            this.error = error;
+        }
+    }
+
+    function reference(element)
+    {
+        for (const [id, known] of elements)
+        {
+            if (known === element)
+            {
+                return { [ELEMENT_KEY]: id };
+            }
+        }
+        const id = PAGE + '-' + (++nextElement);
+        elements.set(id, element);
+        return { [ELEMENT_KEY]: id };
+    }
+
+    function element(id)
+    {
+        const found = elements.get(id);
+        if (!found)
+        {
+            throw new WebDriverError('no such element', 'Unknown element ' + id);
+        }
+        if (!found.isConnected)
+        {
+            throw new WebDriverError('stale element reference', 'Element ' + id + ' is no longer in the page');
+        }
+        return found;
+    }
+
+    function find(using, value, root, multiple)
+    {
+        const scope = root ? element(root) : document;
+        let found;
+        switch (using)
+        {
+            case 'css selector':
+                try
+                {
+                    found = Array.from(scope.querySelectorAll(value));
+                } catch (e)
+                {
+                    throw new WebDriverError('invalid selector', e.message);
+                }
+                break;
+            case 'tag name':
+                found = Array.from(scope.getElementsByTagName(value));
+                break;
+            case 'link text':
+            case 'partial link text':
+                found = Array.from(scope.querySelectorAll('a')).filter((link) =>
+                {
+                    const text = link.innerText.trim();
+                    return using === 'link text' ? text === value : text.includes(value);
+                });
+                break;
+            case 'xpath':
+                found = xpath(value, scope);
+                break;
+            default:
+                throw new WebDriverError('invalid argument', 'Unknown locator strategy ' + using);
+        }
+
+        if (multiple)
+        {
+            return found.map(reference);
+        }
+        if (!found.length)
+        {
+            throw new WebDriverError('no such element', 'No element matches ' + using + ' ' + JSON.stringify(value));
+        }
+        return reference(found[0]);
+    }
+
+    function xpath(expression, scope)
+    {
+        let snapshot;
+        try
+        {
+            snapshot = document.evaluate(expression, scope, null, XPathResult.ORDERED_NODE_SNAPSHOT_TYPE, null);
+        } catch (e)
+        {
+            throw new WebDriverError('invalid selector', e.message);
+        }
+        const found = [];
+        for (let i = 0; i < snapshot.snapshotLength; i++)
+        {
+            const node = snapshot.snapshotItem(i);
+            if (node.nodeType !== Node.ELEMENT_NODE)
+            {
+                throw new WebDriverError('invalid selector', expression + ' selects something other than elements');
+            }
+            found.push(node);
+        }
+        return found;
+    }
+
+    function click(id)
+    {
+        const target = element(id);
+        target.scrollIntoView({ block: 'center', inline: 'center' });
+        const rect = target.getBoundingClientRect();
+        if (!rect.width && !rect.height)
+        {
+            throw new WebDriverError('element not interactable', 'Element ' + id + ' has no size');
+        }
+
+        const x = rect.left + rect.width / 2;
+        const y = rect.top + rect.height / 2;
+        const hit = document.elementFromPoint(x, y);
+        if (hit && hit !== target && !target.contains(hit))
+        {
+            throw new WebDriverError('element click intercepted', 'Another element would receive the click');
+        }
+
+        const init = { bubbles: true, cancelable: true, view: window, clientX: x, clientY: y, button: 0 };
+        target.dispatchEvent(new MouseEvent('mousedown', init));
+        if (target.focus)
+        {
+            target.focus();
+        }
+        target.dispatchEvent(new MouseEvent('mouseup', init));
+        target.click();
+        return null;
+    }
+
+    function edit(target, change)
+    {
+        if ('value' in target)
+        {
+            target.value = change(target.value);
+        } else if (target.isContentEditable)
+        {
+            target.textContent = change(target.textContent);
+        } else
+        {
+            return;
+        }
+        target.dispatchEvent(new Event('input', { bubbles: true }));
+    }
+
+    function sendKeys(id, text)
+    {
+        const target = element(id);
+        target.focus();
+        for (const character of text)
+        {
+            const key = KEYS[character] || character;
+            const init = { key, bubbles: true, cancelable: true };
+            if (target.dispatchEvent(new KeyboardEvent('keydown', init)))
+            {
+                if (key === 'Enter' && target.tagName === 'TEXTAREA')
+                {
+                    edit(target, (value) => value + '\n');
+                } else if (key === 'Enter' && target.form)
+                {
+                    target.form.requestSubmit ? target.form.requestSubmit() : target.form.submit();
+                } else if (key === 'Backspace')
+                {
+                    edit(target, (value) => value.slice(0, -1));
+                } else if (key.length === 1)
+                {
+                    edit(target, (value) => value + key);
+                }
+            }
+            target.dispatchEvent(new KeyboardEvent('keyup', init));
+        }
+        target.dispatchEvent(new Event('change', { bubbles: true }));
+        return null;
+    }
+
+    function text(id)
+    {
+        return element(id).innerText;
+    }
+
+    // Arguments and results cross as JSON, with element references standing in for elements
+    function revive(value)
+    {
+        if (Array.isArray(value))
+        {
+            return value.map(revive);
+        }
+        if (value && typeof value === 'object')
+        {
+            if (typeof value[ELEMENT_KEY] === 'string')
+            {
+                return element(value[ELEMENT_KEY]);
+            }
+            return Object.fromEntries(Object.entries(value).map(([key, item]) => [key, revive(item)]));
+        }
+        return value;
+    }
+
+    function serialize(value, seen)
+    {
+        if (value === undefined || value === null || typeof value === 'function')
+        {
+            return null;
+        }
+        if (value instanceof Element)
+        {
+            return reference(value);
+        }
+        if (typeof value !== 'object')
+        {
+            return value;
+        }
+        if (seen.has(value))
+        {
+            throw new WebDriverError('javascript error', 'The script returned a cyclic value');
+        }
+        seen.add(value);
+        const result = Array.isArray(value) || value instanceof NodeList || value instanceof HTMLCollection
+            ? Array.from(value, (item) => serialize(item, seen))
+            : Object.fromEntries(Object.entries(value).map(([key, item]) => [key, serialize(item, seen)]));
+        seen.delete(value);
+        return result;
+    }
+
+    function execute(script, args)
+    {
+        return serialize(new Function(script).apply(window, revive(args)), new Set());
+    }
+
+    function later(promise)
+    {
+        const token = ++nextResult;
+        results.set(token, null);
+        promise.then(
+            (ok) => results.set(token, { ok }),
+            (e) => results.set(token, { error: 'javascript error', message: String(e && e.message || e) }));
+        return token;
+    }
+
+    // A DevTools remote object, always by value
+    function remoteObject(value)
+    {
+        const object = { type: typeof value };
+        if (value === null)
+        {
+            object.subtype = 'null';
+        } else if (Array.isArray(value))
+        {
+            object.subtype = 'array';
+        } else if (value instanceof Node)
+        {
+            object.subtype = 'node';
+        }
+        if (value !== undefined)
+        {
+            object.value = serialize(value, new Set());
+        }
+        return object;
+    }
+
+    // Evaluates in the global scope; awaited promises come back as `{pending: token}`
+    function evaluate(expression, awaitPromise)
+    {
+        const value = (0, eval)(expression);
+        if (awaitPromise && value && typeof value.then === 'function')
+        {
+            return { pending: later(Promise.resolve(value).then(remoteObject)) };
+        }
+        return remoteObject(value);
+    }
+
+    // Draws the DOM through an SVG image, so only same-origin styles make it
+    // into the picture; the base64 PNG is collected with `result`
+    function screenshot()
+    {
+        const token = ++nextResult;
+        const width = document.documentElement.clientWidth;
+        const height = window.innerHeight;
+
+        let css = '';
+        for (const sheet of document.styleSheets)
+        {
+            try
+            {
+                for (const rule of sheet.cssRules)
+                {
+                    css += rule.cssText + '\n';
+                }
+            } catch (e)
+            {
+                // Cross-origin stylesheets cannot be read
+            }
+        }
+        const page = document.documentElement.cloneNode(true);
+        page.querySelectorAll('script, style, link[rel="stylesheet"]').forEach((node) => node.remove());
+        const style = document.createElement('style');
+        style.textContent = css;
+        page.insertBefore(style, page.firstChild);
+
+        const markup = new XMLSerializer().serializeToString(page);
+        const svg = '<svg xmlns="http://www.w3.org/2000/svg" width="' + width + '" height="' + height + '">'
+            + '<foreignObject x="' + -window.scrollX + '" y="' + -window.scrollY + '" width="'
+            + document.documentElement.scrollWidth + '" height="' + document.documentElement.scrollHeight + '">'
+            + markup + '</foreignObject></svg>';
+
+        results.set(token, null);
+        const image = new Image();
+        image.onload = () =>
+        {
+            try
+            {
+                const canvas = document.createElement('canvas');
+                canvas.width = width;
+                canvas.height = height;
+                const context = canvas.getContext('2d');
+                context.fillStyle = '#fff';
+                context.fillRect(0, 0, width, height);
+                context.drawImage(image, 0, 0);
+                results.set(token, { ok: canvas.toDataURL('image/png').split(',')[1] });
+            } catch (e)
+            {
+                results.set(token, { error: 'unable to capture screen', message: e.message });
+            }
+        };
+        image.onerror = () =>
+        {
+            results.set(token, { error: 'unable to capture screen', message: 'The page could not be rendered' });
+        };
+        image.src = 'data:image/svg+xml;charset=utf-8,' + encodeURIComponent(svg);
+        return token;
+    }
+
+    // A later result once it is ready, null until then
+    function result(token)
+    {
+        const settled = results.get(token);
+        if (settled === undefined)
+        {
+            throw new WebDriverError('unknown error', 'The page was left before the result was ready');
+        }
+        if (settled === null)
+        {
+            return null;
+        }
+        results.delete(token);
+        if (settled.error)
+        {
+            throw new WebDriverError(settled.error, settled.message);
+        }
+        return settled.ok;
+    }
+
+    // Set before a navigation: a new document comes without the mark, and a
+    // navigation within the document changes the URL but keeps it
+    function markNavigating()
+    {
+        window.__tinkerNavigatingFrom = location.href;
+        return null;
+    }
+
+    function ready()
+    {
+        const from = window.__tinkerNavigatingFrom;
+        if (from !== undefined && from === location.href)
+        {
+            return false;
+        }
+        delete window.__tinkerNavigatingFrom;
+        return document.readyState === 'complete';
+    }
+
+    const commands = { find, click, sendKeys, text, execute, evaluate, screenshot, result, markNavigating, ready };
+
+    window.__tinkerWebDriver = {
+        call(name, args)
+        {
+            try
+            {
+                return { ok: commands[name].apply(null, args) };
+            } catch (e)
+            {
+                if (e instanceof WebDriverError)
+                {
+                    return { error: e.error, message: e.message };
+                }
+                return { error: 'javascript error', message: String(e && e.message || e) };
+            }
+        },
+    };
+})();
@@ -0,0 +1,373 @@
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+use axum::Router;
+use futures_util::{SinkExt, StreamExt};
+use hyper::{Body, Client, HeaderMap, Method, Request, StatusCode};
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+use tinker::{api, browser::BrowserEngine, event::CommandPolicy};
+
+/// Serve `app` on a free port and run `client` against it on its own runtime.
+///
+/// Commands from the API run on this thread, as they would on the event loop,
+/// until the client is done; one more pass publishes what its last requests left.
+fn with_api_server<F, Fut, T>(browser: &mut BrowserEngine, app: Router, client: F) -> T
+where
+    F: FnOnce(SocketAddr) -> Fut + Send + 'static,
+    Fut: Future<Output = T>,
+    T: Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+            client(addr).await
+        })
+    });
+
+    while !client.is_finished() {
+        browser.process_pending_commands();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    browser.process_pending_commands();
+    client.join().unwrap()
+}
+
+/// Send a request and return the status and JSON body
+async fn call(addr: SocketAddr, method: Method, path: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let (status, _, body) = call_with(addr, method, path, &[], body).await;
+    (status, body)
+}
+
+/// Like `call`, with extra request headers, also returning the response headers
+async fn call_with(addr: SocketAddr, method: Method, path: &str, headers: &[(&str, &str)], body: Option<Value>) -> (StatusCode, HeaderMap, Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .header("content-type", "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = request
+        .body(body.map(|body| Body::from(body.to_string())).unwrap_or_else(Body::empty))
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    let (parts, body) = response.into_parts();
+    // Event streams never end, so only their head is read
+    if parts.headers.get("content-type").is_some_and(|kind| kind == "text/event-stream") {
+        return (parts.status, parts.headers, Value::Null);
+    }
+    let bytes = hyper::body::to_bytes(body).await.unwrap();
+    (parts.status, parts.headers, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[test]
+fn test_control_endpoints() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.create_tab("https://example.com").unwrap();
+
+    let app = api::app(browser.handle());
+    let seen = with_api_server(&mut browser, app, |addr| async move {
+        let mut seen = Vec::new();
+
+        let (status, tabs) = call(addr, Method::GET, "/tabs", None).await;
+        seen.push((status, tabs[0]["url"].clone()));
+        let (status, created) = call(addr, Method::POST, "/tabs", Some(json!({"url": "https://example.org"}))).await;
+        seen.push((status, created["tab_created"]["id"].clone()));
+        let (status, page) = call(addr, Method::GET, "/page", None).await;
+        seen.push((status, page["url"].clone()));
+        let (status, navigated) = call(addr, Method::POST, "/navigate", Some(json!({"url": "https://example.org/next"}))).await;
+        seen.push((status, navigated["navigated"]["url"].clone()));
+        let (status, switched) = call(addr, Method::POST, "/tabs/0/activate", None).await;
+        seen.push((status, switched["tab_switched"]["id"].clone()));
+        let (status, error) = call(addr, Method::DELETE, "/tabs/42", None).await;
+        seen.push((status, error["kind"].clone()));
+        let (status, events) = call(addr, Method::GET, "/events?limit=2", None).await;
+        seen.push((status, json!(events.as_array().map(Vec::len))));
+        seen.push((status, events[1]["type"].clone()));
+        seen
+    });
+
+    assert_eq!(seen, vec![
+        (StatusCode::OK, json!("https://example.com")),
+        (StatusCode::OK, json!(1)),
+        (StatusCode::OK, json!("https://example.org")),
+        (StatusCode::OK, json!("https://example.org/next")),
+        (StatusCode::OK, json!(0)),
+        (StatusCode::NOT_FOUND, json!("tab_not_found")),
+        (StatusCode::OK, json!(2)),
+        (StatusCode::OK, json!("tab_activated")),
+    ]);
+}
+
+#[test]
+fn test_command_policy_applies_to_the_api() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.create_tab("https://example.com").unwrap();
+    browser.set_command_policy(CommandPolicy::observer());
+
+    let app = api::app(browser.handle());
+    let ((status, error), (replay_status, _), read_status) = with_api_server(&mut browser, app, |addr| async move {
+        let navigated = call(addr, Method::POST, "/navigate", Some(json!({"url": "https://example.org"}))).await;
+        let replay = call(addr, Method::POST, "/replay/start", None).await;
+        let (status, _) = call(addr, Method::GET, "/tabs", None).await;
+        (navigated, replay, status)
+    });
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error["kind"], "forbidden");
+    assert_eq!(replay_status, StatusCode::FORBIDDEN);
+    // Reads are not commands, so observers still answer them
+    assert_eq!(read_status, StatusCode::OK);
+    assert_eq!(browser.handle().active_tab().unwrap().url, "https://example.com");
+    assert!(browser.handle().recent_events(10).iter().any(|envelope| envelope.event_type == "command_rejected"));
+}
+
+#[test]
+fn test_webdriver_session() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.create_tab("https://example.com").unwrap();
+
+    let app = api::app(browser.handle());
+    let seen = with_api_server(&mut browser, app, |addr| async move {
+        let mut seen = Vec::new();
+
+        let capabilities = json!({"capabilities": {"alwaysMatch": {"timeouts": {"implicit": 100}}}});
+        let (status, created) = call(addr, Method::POST, "/session", Some(capabilities.clone())).await;
+        seen.push((status, created["value"]["capabilities"]["timeouts"]["implicit"].clone()));
+        let session = format!("/session/{}", created["value"]["sessionId"].as_str().unwrap());
+        let (status, refused) = call(addr, Method::POST, "/session", Some(capabilities)).await;
+        seen.push((status, refused["value"]["error"].clone()));
+
+        let (status, opened) = call(addr, Method::POST, &format!("{}/window/new", session), Some(json!({}))).await;
+        seen.push((status, opened["value"]["handle"].clone()));
+        let (status, handles) = call(addr, Method::GET, &format!("{}/window/handles", session), None).await;
+        seen.push((status, handles["value"].clone()));
+        let (status, current) = call(addr, Method::GET, &format!("{}/window", session), None).await;
+        seen.push((status, current["value"].clone()));
+        let (status, _) = call(addr, Method::POST, &format!("{}/window", session), Some(json!({"handle": "1"}))).await;
+        seen.push((status, Value::Null));
+        let (status, missing) = call(addr, Method::POST, &format!("{}/window", session), Some(json!({"handle": "7"}))).await;
+        seen.push((status, missing["value"]["error"].clone()));
+
+        let (status, _) = call(addr, Method::POST, &format!("{}/url", session), Some(json!({"url": "https://example.org/"}))).await;
+        seen.push((status, Value::Null));
+        let (status, url) = call(addr, Method::GET, &format!("{}/url", session), None).await;
+        seen.push((status, url["value"].clone()));
+
+        let (status, _) = call(addr, Method::DELETE, &session, None).await;
+        seen.push((status, Value::Null));
+        let (status, ended) = call(addr, Method::GET, &format!("{}/url", session), None).await;
+        seen.push((status, ended["value"]["error"].clone()));
+        seen
+    });
+
+    assert_eq!(seen, vec![
+        (StatusCode::OK, json!(100)),
+        (StatusCode::INTERNAL_SERVER_ERROR, json!("session not created")),
+        (StatusCode::OK, json!("1")),
+        (StatusCode::OK, json!(["0", "1"])),
+        (StatusCode::OK, json!("0")),
+        (StatusCode::OK, Value::Null),
+        (StatusCode::NOT_FOUND, json!("no such window")),
+        (StatusCode::OK, Value::Null),
+        (StatusCode::OK, json!("https://example.org/")),
+        (StatusCode::OK, Value::Null),
+        (StatusCode::NOT_FOUND, json!("invalid session id")),
+    ]);
+}
+
+#[test]
+fn test_devtools_protocol() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.create_tab("https://example.com").unwrap();
+
+    let app = api::app(browser.handle());
+    let messages = with_api_server(&mut browser, app, |addr| async move {
+        let (_, version) = call(addr, Method::GET, "/json/version", None).await;
+        let (_, targets) = call(addr, Method::GET, "/json/list", None).await;
+        assert_eq!(targets[0]["webSocketDebuggerUrl"], format!("ws://{}/devtools/page/0", addr));
+
+        let url = version["webSocketDebuggerUrl"].as_str().unwrap().to_string();
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let requests = [
+            json!({"id": 1, "method": "Target.setDiscoverTargets", "params": {"discover": true}}),
+            json!({"id": 2, "method": "Target.attachToTarget", "params": {"targetId": "0", "flatten": true}}),
+            json!({"id": 3, "method": "Network.enable", "sessionId": "session-1"}),
+            json!({"id": 4, "method": "Page.navigate", "sessionId": "session-1", "params": {"url": "https://example.org/"}}),
+            json!({"id": 5, "method": "Page.navigate", "params": {"url": "https://example.org/"}}),
+            json!({"id": 6, "method": "Target.createTarget", "params": {"url": "https://example.net/"}}),
+        ];
+        for request in requests {
+            socket.send(Message::Text(request.to_string())).await.unwrap();
+        }
+
+        // Until the last reply and the event for the tab it created
+        let mut messages: Vec<Value> = Vec::new();
+        let done = |messages: &[Value]| {
+            messages.iter().any(|m| m["id"] == 6)
+                && messages.iter().any(|m| m["params"]["targetInfo"]["targetId"] == "1")
+        };
+        while !done(&messages) {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+            messages.push(serde_json::from_str(message.to_text().unwrap()).unwrap());
+        }
+        messages
+    });
+
+    let find = |predicate: &dyn Fn(&Value) -> bool| messages.iter().find(|message| predicate(message)).cloned().unwrap_or_default();
+
+    assert_eq!(find(&|m| m["method"] == "Target.targetCreated")["params"]["targetInfo"]["url"], "https://example.com");
+    assert_eq!(find(&|m| m["method"] == "Target.attachedToTarget")["params"]["sessionId"], "session-1");
+    assert_eq!(find(&|m| m["id"] == 2)["result"]["sessionId"], "session-1");
+    assert_eq!(find(&|m| m["id"] == 3)["sessionId"], "session-1");
+    let navigated = find(&|m| m["id"] == 4);
+    assert_eq!(navigated["result"]["frameId"], "0");
+
+    // Network events for the attached session follow Tinker's navigation
+    let request = find(&|m| m["method"] == "Network.requestWillBeSent");
+    assert_eq!(request["sessionId"], "session-1");
+    assert_eq!(request["params"]["documentURL"], "https://example.org/");
+    assert_eq!(request["params"]["loaderId"], navigated["result"]["loaderId"]);
+
+    // Page methods need a target
+    assert_eq!(find(&|m| m["id"] == 5)["error"]["code"], -32601);
+    assert_eq!(find(&|m| m["id"] == 6)["result"]["targetId"], "1");
+    assert_eq!(find(&|m| m["method"] == "Target.targetCreated" && m["params"]["targetInfo"]["targetId"] == "1")["params"]["targetInfo"]["url"], "https://example.net/");
+}
+
+#[test]
+fn test_event_stream() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.create_tab("https://example.com").unwrap();
+
+    let app = api::app(browser.handle());
+    let (content_type, text, frames) = with_api_server(&mut browser, app, |addr| async move {
+        use hyper::body::HttpBody;
+
+        let uri = format!("http://{}/events/stream?types=tab_created&backlog=5", addr);
+        let mut sse = Client::new().get(uri.parse().unwrap()).await.unwrap();
+        let content_type = sse.headers()["content-type"].to_str().unwrap().to_string();
+        let url = format!("ws://{}/events/stream?tab=1", addr);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        call(addr, Method::POST, "/tabs", Some(json!({"url": "https://example.org"}))).await;
+        call(addr, Method::POST, "/tabs/0/activate", None).await;
+        call(addr, Method::POST, "/tabs/1/activate", None).await;
+
+        // The backlog's tab first, then the new one
+        let mut text = String::new();
+        while text.matches("event:tab_created").count() < 2 {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), sse.body_mut().data()).await.unwrap().unwrap().unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+
+        // Only tab 1's events reach the socket
+        let mut frames = Vec::new();
+        for _ in 0..2 {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+            let envelope: Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            frames.push((envelope["type"].clone(), envelope["tab_id"].clone()));
+        }
+        (content_type, text, frames)
+    });
+
+    assert_eq!(content_type, "text/event-stream");
+    let urls: Vec<&str> = text.lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| if data.contains("example.com") { "https://example.com" } else { "https://example.org" })
+        .collect();
+    assert_eq!(urls, vec!["https://example.com", "https://example.org"]);
+    assert!(!text.contains("tab_activated"));
+    assert_eq!(frames, vec![
+        (json!("tab_created"), json!(1)),
+        (json!("tab_activated"), json!(1)),
+    ]);
+}
+
+#[test]
+fn test_api_tokens_and_origins() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.create_tab("https://example.com").unwrap();
+
+    let access: api::auth::ApiAccess = serde_json::from_value(json!({
+        "tokens": [{ "name": "ci", "token": "s3cret", "scopes": ["events", "navigate"] }],
+        "cors_origins": ["http://localhost:8080"]
+    })).unwrap();
+    let app = api::auth::protect(api::app(browser.handle()), access, browser.handle());
+    let (seen, allowed, reply) = with_api_server(&mut browser, app, |addr| async move {
+        let status = |(status, _, _): (StatusCode, HeaderMap, Value)| status;
+        let bearer = [("authorization", "Bearer s3cret")];
+        let mut seen = Vec::new();
+
+        seen.push(call(addr, Method::GET, "/tabs", None).await.0);
+        seen.push(status(call_with(addr, Method::GET, "/tabs", &[("authorization", "Bearer wrong")], None).await));
+        // Only WebSocket and EventSource clients may put the token in the URL
+        seen.push(call(addr, Method::GET, "/tabs?access_token=s3cret", None).await.0);
+        let accept = [("accept", "text/event-stream")];
+        seen.push(status(call_with(addr, Method::GET, "/events/stream?access_token=s3cret", &accept, None).await));
+        seen.push(call(addr, Method::GET, "/health", None).await.0);
+        seen.push(status(call_with(addr, Method::GET, "/tabs", &bearer, None).await));
+        let navigate = Some(json!({"url": "https://example.org"}));
+        seen.push(status(call_with(addr, Method::POST, "/navigate", &bearer, navigate).await));
+        let execute = Some(json!({"script": "return 1", "args": []}));
+        seen.push(status(call_with(addr, Method::POST, "/session/abc/execute/sync", &bearer, execute).await));
+
+        let evil = [("origin", "https://evil.example"), ("authorization", "Bearer s3cret")];
+        seen.push(status(call_with(addr, Method::GET, "/tabs", &evil, None).await));
+        let (preflight, headers, _) = call_with(addr, Method::OPTIONS, "/navigate", &[("origin", "http://localhost:8080")], None).await;
+        seen.push(preflight);
+        let allowed = headers["access-control-allow-origin"].to_str().unwrap().to_string();
+
+        let url = format!("ws://{}/devtools/browser?access_token=s3cret", addr);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let evaluate = json!({"id": 1, "method": "Runtime.evaluate", "params": {"expression": "1"}});
+        socket.send(Message::Text(evaluate.to_string())).await.unwrap();
+        let reply = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+        let reply: Value = serde_json::from_str(reply.to_text().unwrap()).unwrap();
+        (seen, allowed, reply)
+    });
+
+    assert_eq!(seen, vec![
+        StatusCode::UNAUTHORIZED,
+        StatusCode::UNAUTHORIZED,
+        StatusCode::UNAUTHORIZED,
+        StatusCode::OK,
+        StatusCode::OK,
+        StatusCode::OK,
+        StatusCode::OK,
+        StatusCode::FORBIDDEN,
+        StatusCode::FORBIDDEN,
+        StatusCode::NO_CONTENT,
+    ]);
+    assert_eq!(allowed, "http://localhost:8080");
+    assert!(reply["error"]["message"].as_str().unwrap().contains("Script"));
+
+    let audits: Vec<Value> = browser.handle().recent_events(usize::MAX).into_iter()
+        .filter(|envelope| envelope.event_type == "api_audit")
+        .map(|envelope| serde_json::to_value(&envelope.event).unwrap())
+        .collect();
+    assert_eq!(audits, vec![
+        json!({"ApiAudit": {"token": "ci", "action": "POST /navigate", "success": true}}),
+        json!({"ApiAudit": {"token": "ci", "action": "POST /session/abc/execute/sync", "success": false}}),
+        json!({"ApiAudit": {"token": "ci", "action": "Runtime.evaluate", "success": false}}),
+    ]);
+}
+
+#[test]
+fn test_open_api_checks_host() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    let app = api::auth::protect(api::app(browser.handle()), api::auth::ApiAccess::default(), browser.handle());
+    let statuses = with_api_server(&mut browser, app, |addr| async move {
+        let mut statuses = Vec::new();
+        for host in [addr.to_string(), format!("localhost:{}", addr.port()), "rebound.example".to_string()] {
+            let (status, _, _) = call_with(addr, Method::GET, "/tabs", &[("host", &host)], None).await;
+            statuses.push(status);
+        }
+        statuses
+    });
+    assert_eq!(statuses, vec![StatusCode::OK, StatusCode::OK, StatusCode::FORBIDDEN]);
+}
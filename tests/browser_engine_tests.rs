@@ -1,7 +1,12 @@
+use std::fs;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tinker::{
-    browser::BrowserEngine,
-    event::{EventSystem, BrowserEvent},
+    browser::{BrowserEngine, Determinism, Recording, VerifyOptions, VerifyReport},
+    event::{
+        EventSystem, EventTransport, BrowserEvent, BrowserCommand, CommandResult, InProcessTransport, PendingCommand,
+        RecordingControl, ReplayControl, ReplayState, SeekTarget,
+    },
 };
 
 #[test]
@@ -81,7 +86,7 @@ fn test_tab_error_handling() {
 #[test]
 fn test_event_system() {
     // Create event system
-    let mut events = EventSystem::new("localhost", "test-browser");
+    let mut events = EventSystem::new("localhost", "test-browser").unwrap();
     events.connect().unwrap();
     let events = Arc::new(Mutex::new(events));
 
@@ -124,4 +129,330 @@ fn test_event_replay() {
         tabs.get_active_tab().map(|tab| tab.url.clone()).unwrap()
     };
     assert_eq!(final_url, "https://example.com/page2");
-} 
+}
+
+#[test]
+fn test_pending_command_replies() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    let sender = browser.command_sender();
+
+    // Queue a command with a responder, as the MQTT RPC layer does
+    let (pending, outcome) = PendingCommand::with_responder(BrowserCommand::CreateTab {
+        url: "https://example.com".to_string(),
+    });
+    sender.send(pending).unwrap();
+    assert_eq!(browser.process_pending_commands(), 1);
+
+    let tab_id = match outcome.recv().unwrap() {
+        Ok(CommandResult::TabCreated { id, url }) => {
+            assert_eq!(url, "https://example.com");
+            id
+        }
+        other => panic!("Unexpected outcome: {:?}", other),
+    };
+    assert_eq!(browser.tabs.lock().unwrap().get_active_tab().unwrap().id, tab_id);
+
+    // Errors carry the typed error kind
+    let (pending, outcome) = PendingCommand::with_responder(BrowserCommand::SwitchTab { id: 999 });
+    sender.send(pending).unwrap();
+    browser.process_pending_commands();
+    let error = outcome.recv().unwrap().unwrap_err();
+    assert_eq!(error.kind, "tab_not_found");
+}
+
+#[test]
+fn test_history_commands() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    let id = browser.create_tab("https://example.com/1").unwrap();
+    browser.navigate("https://example.com/2").unwrap();
+
+    let result = browser.handle_command(BrowserCommand::Back).unwrap();
+    assert_eq!(result, CommandResult::Navigated { id, url: "https://example.com/1".to_string() });
+    assert!(browser.handle_command(BrowserCommand::Back).is_err());
+
+    let result = browser.handle_command(BrowserCommand::Forward).unwrap();
+    assert_eq!(result, CommandResult::Navigated { id, url: "https://example.com/2".to_string() });
+
+    let result = browser.handle_command(BrowserCommand::Reload).unwrap();
+    assert_eq!(result, CommandResult::Navigated { id, url: "https://example.com/2".to_string() });
+}
+
+#[test]
+fn test_get_tabs_and_shutdown() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    let first = browser.create_tab("https://example.com/1").unwrap();
+    let second = browser.create_tab("https://example.com/2").unwrap();
+    browser.switch_to_tab(second).unwrap();
+
+    let Ok(CommandResult::Tabs { tabs }) = browser.handle_command(BrowserCommand::GetTabs) else {
+        panic!("Expected a tab list");
+    };
+    assert_eq!(tabs.iter().map(|tab| tab.id).collect::<Vec<_>>(), vec![first, second]);
+    assert!(!tabs[0].active);
+    assert!(tabs[1].active);
+
+    assert_eq!(browser.handle_command(BrowserCommand::Shutdown).unwrap(), CommandResult::Completed);
+    assert!(!browser.running);
+}
+
+#[test]
+fn test_commands_needing_a_window() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.create_tab("https://example.com").unwrap();
+
+    // Without a running event loop there is no window or web view to act on
+    let (pending, outcome) = PendingCommand::with_responder(BrowserCommand::EvaluateScript {
+        tab_id: None,
+        script: "document.title".to_string(),
+    });
+    browser.command_sender().send(pending).unwrap();
+    browser.process_pending_commands();
+    assert_eq!(outcome.recv().unwrap().unwrap_err().kind, "javascript");
+    assert_eq!(browser.handle_command(BrowserCommand::Stop).unwrap_err().kind(), "javascript");
+    let (pending, outcome) = PendingCommand::with_responder(BrowserCommand::Screenshot { tab_id: None });
+    browser.command_sender().send(pending).unwrap();
+    browser.process_pending_commands();
+    assert_eq!(outcome.recv().unwrap().unwrap_err().kind, "javascript");
+    let error = browser.handle_command(BrowserCommand::Screenshot { tab_id: Some(42) }).unwrap_err();
+    assert_eq!(error.kind(), "tab_not_found");
+
+    let error = browser.handle_command(BrowserCommand::SetViewport { width: 1024, height: 768 }).unwrap_err();
+    assert_eq!(error.kind(), "window");
+}
+
+#[test]
+fn test_replay_drives_browser() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    // A tab that already exists shifts live ids away from the recorded ones
+    browser.create_tab("about:blank").unwrap();
+
+    let recorded = vec![
+        BrowserEvent::TabCreated { id: 0, url: "https://example.com/a".to_string() },
+        BrowserEvent::TabCreated { id: 1, url: "https://example.com/b".to_string() },
+        BrowserEvent::TabActivated { id: 1 },
+        BrowserEvent::TabUrlChanged { id: 1, url: "https://example.com/c".to_string() },
+        BrowserEvent::Navigation { url: "https://example.com/c".to_string() },
+        BrowserEvent::PageLoaded { url: "https://example.com/c".to_string() },
+        BrowserEvent::TabClosed { id: 0 },
+        BrowserEvent::TabActivated { id: 7 },
+        BrowserEvent::Error { message: "recorded failure".to_string() },
+    ];
+    for event in recorded {
+        let _ = browser.handle_command(BrowserCommand::PlayEvent { event });
+    }
+
+    let tabs = browser.tabs.lock().unwrap();
+    let urls: Vec<_> = tabs.get_all_tabs().iter().map(|tab| tab.url.clone()).collect();
+    assert_eq!(urls.len(), 2);
+    assert!(urls.contains(&"about:blank".to_string()));
+    assert_eq!(tabs.get_active_tab().unwrap().url, "https://example.com/c");
+    drop(tabs);
+
+    let report = browser.replay_report();
+    assert_eq!(report.applied, 5);
+    assert_eq!(report.derived, 2);
+    let skipped: Vec<_> = report.skipped.iter().map(|s| (s.index, s.event_type.as_str())).collect();
+    assert_eq!(skipped, vec![(7, "tab_activated"), (8, "error")]);
+}
+
+#[test]
+fn test_replay_controls() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    let mut replay = |control| match browser.handle_command(BrowserCommand::Replay(control)) {
+        Ok(CommandResult::ReplayStatus(status)) => status,
+        other => panic!("unexpected replay result: {:?}", other),
+    };
+
+    let status = replay(ReplayControl::Status);
+    assert_eq!(status.state, ReplayState::Stopped);
+    assert_eq!(status.total, 0);
+
+    let status = replay(ReplayControl::AddBreakpoint { breakpoint: "error".parse().unwrap() });
+    assert_eq!(status.breakpoints.len(), 1);
+    let status = replay(ReplayControl::SetSpeed { speed: 50.0 });
+    assert_eq!(status.speed, 10.0);
+
+    // Pausing or seeking a stopped player leaves it stopped
+    let status = replay(ReplayControl::Pause);
+    assert_eq!(status.state, ReplayState::Stopped);
+    let status = replay(ReplayControl::Seek { to: SeekTarget::Index(3) });
+    assert_eq!(status.index, 0);
+
+    let status = replay(ReplayControl::ClearBreakpoints);
+    assert!(status.breakpoints.is_empty());
+}
+
+#[test]
+fn test_replay_can_start_paused() {
+    let path = std::env::temp_dir().join(format!("tinker-paused-{}.jsonl", std::process::id()));
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.start_recording(path.to_str().unwrap()).unwrap();
+    for index in 0..3 {
+        browser.create_tab(&format!("https://example.com/{}", index)).unwrap();
+    }
+    browser.stop_recording().unwrap();
+    browser.load_recording(path.to_str().unwrap()).unwrap();
+
+    // Nothing goes out before the seek and the pause take effect
+    browser.start_replay_from(Some(SeekTarget::Index(1)), true).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(browser.process_pending_commands(), 0);
+    let status = browser.control_replay(ReplayControl::Status).unwrap();
+    assert_eq!((status.state, status.index), (ReplayState::Paused, 1));
+
+    // Starting over hands the timeline to one new feeder
+    browser.start_replay_from(None, false).unwrap();
+    let started = Instant::now();
+    while browser.control_replay(ReplayControl::Status).unwrap().state != ReplayState::Stopped {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(browser.process_pending_commands(), status.total);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_page_input_is_recorded() {
+    let dir = std::env::temp_dir().join(format!("tinker-input-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("input.jsonl");
+
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.create_tab("https://example.com/login").unwrap();
+    browser.start_recording(path.to_str().unwrap()).unwrap();
+
+    // Messages as the capture script posts them from the page
+    let page = browser.ipc_sender();
+    for message in [
+        r##"{"type":"click","selector":"#login","x":40,"y":12.5,"button":0}"##,
+        r##"{"type":"textInput","selector":"input[name=\"user\"]","value":"alice"}"##,
+        r##"{"type":"textInput","selector":"#password","value":"","redacted":true}"##,
+        r##"{"type":"keyPress","selector":null,"key":"Control+Enter"}"##,
+        r##"{"type":"scroll","selector":null,"x":0,"y":900}"##,
+        r##"{"type":"formSubmitted","selector":"#login-form"}"##,
+        r##"{"type":"click","x":1,"y":2}"##,
+    ] {
+        page.send(message.to_string()).unwrap();
+    }
+    assert_eq!(browser.process_pending_commands(), 0);
+    browser.stop_recording().unwrap();
+
+    let recording = Recording::load(&path).unwrap();
+    let types: Vec<_> = recording.records.iter().map(|r| r.envelope.event_type.as_str()).collect();
+    assert_eq!(types, vec!["click", "text_input", "text_input", "key_pressed", "scrolled", "form_submitted"]);
+
+    // Without a page to dispatch into, input is skipped, and passwords never replay
+    for record in &recording.records[..3] {
+        let _ = browser.handle_command(BrowserCommand::PlayEvent { event: record.envelope.event.clone() });
+    }
+    let report = browser.replay_report();
+    assert_eq!(report.applied, 0);
+    assert_eq!(report.skipped.len(), 3);
+    assert!(report.skipped[0].reason.contains("No web view"));
+    assert_eq!(report.skipped[2].reason, "password values are not recorded");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_page_input_is_ignored_unless_recording() {
+    let transport = InProcessTransport::new();
+    let mut browser = BrowserEngine::new(true, Some(Arc::new(Mutex::new(transport.clone()))), None);
+    let input = transport.clone().subscribe("browser/input/#").unwrap();
+    let page = browser.ipc_sender();
+    let typed = r##"{"type":"textInput","selector":"#q","value":"secret plans"}"##;
+
+    page.send(typed.to_string()).unwrap();
+    browser.process_pending_commands();
+    assert!(input.recv_timeout(Duration::from_millis(200)).is_err());
+
+    browser.control_recording(RecordingControl::Start { path: None, live: false }).unwrap();
+    page.send(typed.to_string()).unwrap();
+    browser.process_pending_commands();
+    assert!(input.recv_timeout(Duration::from_secs(1)).unwrap().payload.contains("secret plans"));
+
+    browser.stop_recording().unwrap();
+    page.send(typed.to_string()).unwrap();
+    browser.process_pending_commands();
+    assert!(input.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn test_deterministic_recording_keeps_its_seed() {
+    let path = std::env::temp_dir().join(format!("tinker-deterministic-{}.jsonl", std::process::id()));
+    let determinism = Determinism { seed: 1234, epoch_ms: 1_700_000_000_000 };
+
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.determinism = Some(determinism);
+    browser.start_recording(path.to_str().unwrap()).unwrap();
+    browser.create_tab("https://example.com").unwrap();
+    browser.stop_recording().unwrap();
+
+    let header = Recording::load(&path).unwrap().header.unwrap();
+    assert_eq!(header.determinism, Some(determinism));
+    // A replay keeps the recorded seed and clock unless told otherwise
+    assert_eq!(Determinism::resolve(header.determinism, None), determinism);
+    fs::remove_file(&path).unwrap();
+}
+
+/// Replay `path` with verification and drive the engine until the report is in
+fn verify_recording(path: &str) -> (BrowserEngine, VerifyReport) {
+    let mut browser = BrowserEngine::new(true, None, None);
+    browser.load_recording(path).unwrap();
+    browser.verify_replay(VerifyOptions { settle: Duration::ZERO, ..VerifyOptions::default() });
+    browser.start_replay().unwrap();
+
+    let started = Instant::now();
+    loop {
+        browser.process_pending_commands();
+        if let Some(report) = browser.check_verification() {
+            return (browser, report);
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "replay did not finish");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn test_verified_replay() {
+    let dir = std::env::temp_dir().join(format!("tinker-verify-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let recording = dir.join("session.jsonl");
+    let recording = recording.to_str().unwrap();
+
+    let mut recorded = BrowserEngine::new(true, None, None);
+    recorded.start_recording(recording).unwrap();
+    recorded.handle_command(BrowserCommand::CreateTab { url: "https://example.com/a".to_string() }).unwrap();
+    recorded.handle_command(BrowserCommand::Navigate { url: "https://example.com/b".to_string() }).unwrap();
+    recorded.stop_recording().unwrap();
+
+    let (browser, report) = verify_recording(recording);
+    assert!(report.passed, "{}", report);
+    assert_eq!(report.matched, 1);
+    assert!(!browser.running);
+    assert_eq!(browser.exit_code, 0);
+
+    // A recording that expects a different page fails, with both sides in the diff
+    let edited: String = fs::read_to_string(recording).unwrap()
+        .lines()
+        .map(|line| {
+            let line = if line.contains("TabUrlChanged") {
+                line.replace("example.com/b", "example.com/z")
+            } else {
+                line.to_string()
+            };
+            line + "\n"
+        })
+        .collect();
+    fs::write(recording, edited).unwrap();
+
+    let (browser, report) = verify_recording(recording);
+    assert!(!report.passed);
+    assert_eq!(browser.exit_code, 1);
+    let diff = report.to_string();
+    assert!(diff.contains("- missing    tab_url_changed"), "{}", diff);
+    assert!(diff.contains("+ unexpected tab_url_changed"), "{}", diff);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
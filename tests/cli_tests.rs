@@ -1,4 +1,7 @@
+use std::fs;
+use std::path::PathBuf;
 use assert_cmd::Command;
+use predicates::prelude::PredicateBooleanExt;
 
 #[test]
 fn test_help_command() {
@@ -19,3 +22,120 @@ fn test_version_command() {
 }
 
 // TODO: Add tests for the --headless, --url, --tabs, --record, --record-path, --replay, and --replay-speed options
+
+/// A small streamed recording in a fresh directory
+fn recording_fixture(name: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("tinker-cli-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let record = |sequence: u64, offset: u64, event_type: &str, event: &str| format!(
+        r#"{{"timestamp_ms":{offset},"schema_version":1,"sequence":{sequence},"timestamp":"2024-01-01T00:00:00+00:00","monotonic_ms":{offset},"session_id":"s","type":"{event_type}","event":{event}}}"#
+    );
+    let lines = [
+        r#"{"format":"tinker-recording","format_version":2,"tinker_version":"0.1.0","started_at":"2024-01-01T00:00:00+00:00","session_id":"s","initial_url":"https://example.com/?token=abc","window_size":{"width":800,"height":600}}"#.to_string(),
+        record(1, 1000, "navigation", r#"{"Navigation":{"url":"https://example.com/?token=abc"}}"#),
+        record(2, 1500, "title_changed", r#"{"TitleChanged":{"title":"Example"}}"#),
+        record(3, 2000, "page_loaded", r#"{"PageLoaded":{"url":"https://example.com/?token=abc"}}"#),
+    ];
+    let path = dir.join("session.jsonl");
+    fs::write(&path, lines.join("\n") + "\n").unwrap();
+    (dir, path)
+}
+
+#[test]
+fn test_recording_list_and_validate() {
+    let (dir, path) = recording_fixture("list");
+
+    Command::cargo_bin("tinker").unwrap()
+        .args(["recording", "list"]).arg(&path)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("title_changed"))
+        .stdout(predicates::str::contains("2000 ms"));
+
+    Command::cargo_bin("tinker").unwrap()
+        .args(["recording", "validate"]).arg(&path)
+        .assert()
+        .success();
+
+    // Out-of-order sequences are reported with their line
+    let broken = fs::read_to_string(&path).unwrap().replace(r#""sequence":3"#, r#""sequence":1"#);
+    fs::write(&path, broken).unwrap();
+    Command::cargo_bin("tinker").unwrap()
+        .args(["recording", "validate"]).arg(&path)
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("line 4: sequence 1 does not follow 2"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_recording_edits() {
+    let (dir, path) = recording_fixture("edit");
+    let edited = dir.join("edited.jsonl");
+
+    Command::cargo_bin("tinker").unwrap()
+        .args(["recording", "drop", "--type", "title_changed", "-o"]).arg(&edited).arg(&path)
+        .assert()
+        .success();
+    Command::cargo_bin("tinker").unwrap()
+        .args(["recording", "retime", "--zero", "--shift-ms", "-500", "-o"]).arg(&edited).arg(&edited)
+        .assert()
+        .success();
+    Command::cargo_bin("tinker").unwrap()
+        .args(["recording", "scrub", "--replace", "token=[^&]+", "token=REDACTED", "-o"]).arg(&edited).arg(&edited)
+        .assert()
+        .success();
+
+    let text = fs::read_to_string(&edited).unwrap();
+    assert!(!text.contains("title_changed"));
+    assert!(!text.contains("token=abc"));
+    assert!(text.contains(r#""timestamp_ms":0,"#));
+    assert!(text.contains(r#""timestamp_ms":500,"#));
+
+    Command::cargo_bin("tinker").unwrap()
+        .args(["recording", "trim", "--from", "time:1500"]).arg(&path)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("title_changed"))
+        .stdout(predicates::str::contains("Navigation").not());
+
+    Command::cargo_bin("tinker").unwrap()
+        .args(["recording", "drop", "--type", "no_such_event"]).arg(&path)
+        .assert()
+        .failure();
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_network_replay_needs_its_archive() {
+    let (dir, path) = recording_fixture("network");
+
+    // Offline playback refuses to start without the archive next to the recording
+    Command::cargo_bin("tinker").unwrap()
+        .args(["--headless", "--network", "--network-ca"]).arg(dir.join("ca"))
+        .arg("--replay").arg(&path)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("session.har"));
+
+    Command::cargo_bin("tinker").unwrap()
+        .args(["--headless", "--network", "--network-ca"]).arg(dir.join("ca"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--network needs --record"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_api_needs_a_real_token_off_loopback() {
+    // An empty token is no token, so the API stays on loopback
+    Command::cargo_bin("tinker").unwrap()
+        .env("TINKER_API_TOKEN", "")
+        .args(["--headless", "--api-port", "0", "--api-bind", "0.0.0.0"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Refusing to serve the API on 0.0.0.0 without tokens"));
+}
@@ -0,0 +1,83 @@
+//! The broker side of MQTT, just enough for fake brokers in tests to accept a
+//! client and see what it sends.
+
+// Each test binary uses only some of these
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+
+/// Username and password as sent in the CONNECT packet
+pub type Credentials = (Option<String>, Option<String>);
+
+const CONNECT: u8 = 1;
+const PUBLISH: u8 = 3;
+
+/// CONNACK: connection accepted
+const CONNACK: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+
+/// Read one MQTT packet, returning its fixed header byte and body
+pub fn read_packet(stream: &mut impl Read) -> Option<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte).ok()?;
+    let header = byte[0];
+
+    // Variable-length "remaining length" header
+    let mut len = 0usize;
+    let mut multiplier = 1usize;
+    loop {
+        stream.read_exact(&mut byte).ok()?;
+        len += (byte[0] & 0x7f) as usize * multiplier;
+        multiplier *= 128;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).ok()?;
+    Some((header, body))
+}
+
+/// Read the client's CONNECT packet, accept it, and return its username and password
+pub fn accept_connect(stream: &mut (impl Read + Write)) -> Option<Credentials> {
+    let (header, packet) = read_packet(stream)?;
+    if header >> 4 != CONNECT {
+        return None;
+    }
+
+    let field = |cursor: &mut usize| {
+        let n = u16::from_be_bytes([packet[*cursor], packet[*cursor + 1]]) as usize;
+        let value = String::from_utf8_lossy(&packet[*cursor + 2..*cursor + 2 + n]).into_owned();
+        *cursor += 2 + n;
+        value
+    };
+
+    let mut cursor = 0;
+    field(&mut cursor); // protocol name
+    cursor += 1; // protocol level
+    let flags = packet[cursor];
+    cursor += 3; // flags and keep alive
+    field(&mut cursor); // client id
+    if flags & 0x04 != 0 {
+        // last will topic and message
+        field(&mut cursor);
+        field(&mut cursor);
+    }
+    let username = (flags & 0x80 != 0).then(|| field(&mut cursor));
+    let password = (flags & 0x40 != 0).then(|| field(&mut cursor));
+
+    stream.write_all(&CONNACK).ok()?;
+    stream.flush().ok()?;
+    Some((username, password))
+}
+
+/// Topic and payload of a PUBLISH packet; `None` for any other packet
+pub fn publish(header: u8, body: &[u8]) -> Option<(String, String)> {
+    if header >> 4 != PUBLISH {
+        return None;
+    }
+    let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    let topic = String::from_utf8_lossy(&body[2..2 + topic_len]).into_owned();
+    // QoS 1 and 2 carry a packet id after the topic
+    let start = 2 + topic_len + if header & 0x06 != 0 { 2 } else { 0 };
+    Some((topic, String::from_utf8_lossy(&body[start..]).into_owned()))
+}
@@ -1,4 +1,4 @@
-use tinker::event::{BrowserEvent, EventSystem};
+use tinker::event::{BrowserCommand, BrowserEvent, CommandError, CommandRequest, CommandResponse, CommandResult, EventSystem, client_id_for, BROADCAST_COMMAND_TOPIC, Presence};
 
 #[test]
 fn test_event_serialization() {
@@ -40,7 +40,7 @@ fn test_event_serialization() {
 
 #[test]
 fn test_event_system_creation() {
-    let system = EventSystem::new("localhost", "test-client");
+    let system = EventSystem::new("localhost", "test-client").unwrap();
     assert!(system.client.is_none());
     assert_eq!(system.options.client_id(), "test-client");
 }
@@ -97,4 +97,90 @@ fn test_event_cloning() {
         }
         _ => panic!("Event cloning failed"),
     }
-} 
+}
+
+#[test]
+fn test_command_request_parsing() {
+    let json = r#"{"request_id":"abc-1","reply_to":"orchestrator/replies","command":{"create_tab":{"url":"https://example.com"}}}"#;
+    let request: CommandRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.request_id, "abc-1");
+    assert_eq!(request.reply_to.as_deref(), Some("orchestrator/replies"));
+    assert!(request.timeout_ms.is_none());
+    assert!(matches!(request.command, BrowserCommand::CreateTab { ref url } if url == "https://example.com"));
+}
+
+#[test]
+fn test_command_response_serialization() {
+    let response = CommandResponse::new(
+        "abc-1".to_string(),
+        Ok(CommandResult::TabCreated { id: 3, url: "https://example.com".to_string() }),
+        std::time::Duration::from_millis(12),
+    );
+    let json = serde_json::to_value(&response).unwrap();
+    assert_eq!(json["request_id"], "abc-1");
+    assert_eq!(json["success"], true);
+    assert_eq!(json["result"]["tab_created"]["id"], 3);
+    assert!(json.get("error").is_none());
+
+    let response = CommandResponse::new(
+        "abc-2".to_string(),
+        Err(CommandError::new("timeout", "Command did not complete within 10 ms")),
+        std::time::Duration::from_millis(10),
+    );
+    let json = serde_json::to_value(&response).unwrap();
+    assert_eq!(json["success"], false);
+    assert_eq!(json["error"]["kind"], "timeout");
+    assert!(json.get("result").is_none());
+}
+
+#[test]
+fn test_instance_topic_namespacing() {
+    let event = BrowserEvent::TabCreated {
+        id: 1,
+        url: "https://example.com".to_string(),
+    };
+
+    // Without an instance id the legacy topics are kept
+    let system = EventSystem::new("localhost", "test-client").unwrap();
+    assert_eq!(system.get_topic(&event), "browser/tabs/created");
+    assert_eq!(system.command_topic(), "browser/command");
+    assert_eq!(system.announce_topic(), "tinker/instances/test-client");
+
+    let system = EventSystem::new("localhost", "test-client").unwrap().with_instance_id(Some("ci-7"));
+    assert_eq!(system.instance_id(), Some("ci-7"));
+    assert_eq!(system.get_topic(&event), "tinker/ci-7/browser/tabs/created");
+    assert_eq!(system.command_topic(), "tinker/ci-7/browser/command");
+    assert_eq!(system.announce_topic(), "tinker/instances/ci-7");
+    assert_ne!(system.command_topic(), BROADCAST_COMMAND_TOPIC);
+
+    // Wildcards and separators cannot leak into the topic tree
+    let system = EventSystem::new("localhost", "test-client").unwrap().with_instance_id(Some("a/b#"));
+    assert_eq!(system.instance_id(), Some("a-b-"));
+}
+
+#[test]
+fn test_client_ids_are_unique_per_instance() {
+    assert_eq!(client_id_for(Some("ci-7")), "tinker-browser-ci-7");
+    assert_ne!(client_id_for(Some("ci-7")), client_id_for(Some("ci-8")));
+    assert!(client_id_for(None).starts_with("tinker-browser-"));
+}
+
+#[test]
+fn test_instance_status() {
+    let mut system = EventSystem::new("localhost", "test-client").unwrap().with_instance_id(Some("ci-7"));
+    system.set_headless(true);
+    assert_eq!(system.status_topic(), "tinker/ci-7/browser/status");
+
+    let status = system.status(Presence::Online, 2, Some("https://example.com".to_string()));
+    let json = serde_json::to_value(&status).unwrap();
+    assert_eq!(json["presence"], "online");
+    assert_eq!(json["instance_id"], "ci-7");
+    assert_eq!(json["client_id"], "test-client");
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["headless"], true);
+    assert_eq!(json["tab_count"], 2);
+    assert_eq!(json["active_url"], "https://example.com");
+
+    // Disconnecting without a broker connection is a no-op
+    assert!(system.disconnect(0, None).is_ok());
+}
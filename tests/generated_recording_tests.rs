@@ -0,0 +1,74 @@
+//! Generated by `tinker recording generate` from session.jsonl
+//! Left out 1 recorded events that cannot be replayed
+
+// Not every recording needs every helper
+#![allow(dead_code, unused_mut)]
+
+use std::collections::HashMap;
+use tinker::browser::BrowserEngine;
+use tinker::event::{BrowserCommand, CommandResult, TabInfo};
+
+fn run(browser: &mut BrowserEngine, command: BrowserCommand) -> CommandResult {
+    let description = format!("{:?}", command);
+    browser.handle_command(command).unwrap_or_else(|e| panic!("{} failed: {}", description, e))
+}
+
+fn tab(browser: &BrowserEngine, id: usize) -> Option<TabInfo> {
+    browser.tab_list().into_iter().find(|tab| tab.id == id)
+}
+
+fn active(browser: &BrowserEngine) -> TabInfo {
+    browser.tab_list().into_iter().find(|tab| tab.active).expect("a tab is active")
+}
+
+// Titles come from pages, which a headless engine does not run, so a tab
+// without one has nothing to compare yet
+fn expect_title(browser: &BrowserEngine, title: &str) {
+    let tab = active(browser);
+    if !tab.title.is_empty() {
+        assert_eq!(tab.title, title);
+    }
+}
+
+#[test]
+fn recording_2024_session() {
+    let mut browser = BrowserEngine::new(true, None, None);
+    // Recorded tab id -> tab id in this run
+    let mut tabs: HashMap<usize, usize> = HashMap::new();
+
+    // 0 ms
+    let id = match run(&mut browser, BrowserCommand::CreateTab { url: "about:blank".to_string() }) {
+        CommandResult::TabCreated { id, .. } => id,
+        other => panic!("unexpected result: {:?}", other),
+    };
+    tabs.insert(0, id);
+    assert_eq!(tab(&browser, id).unwrap().url, "about:blank");
+
+    // 100 ms
+    run(&mut browser, BrowserCommand::SwitchTab { id: tabs[&0] });
+    assert_eq!(active(&browser).id, tabs[&0]);
+
+    // 200 ms
+    run(&mut browser, BrowserCommand::Navigate { url: "https://example.com".to_string() });
+    assert_eq!(active(&browser).url, "https://example.com");
+    // The page finished loading at "https://example.com/"
+    expect_title(&browser, "Example \"Domain\"");
+
+    // 600 ms
+    // Page input: click on #more
+
+    // 700 ms
+    let id = match run(&mut browser, BrowserCommand::CreateTab { url: "https://example.org".to_string() }) {
+        CommandResult::TabCreated { id, .. } => id,
+        other => panic!("unexpected result: {:?}", other),
+    };
+    tabs.insert(1, id);
+    assert_eq!(tab(&browser, id).unwrap().url, "https://example.org");
+
+    // 800 ms
+    let id = tabs.remove(&1).expect("tab was created");
+    run(&mut browser, BrowserCommand::CloseTab { id });
+    assert!(tab(&browser, id).is_none());
+
+    assert_eq!(browser.tab_list().len(), 1);
+}
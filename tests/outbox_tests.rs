@@ -0,0 +1,73 @@
+mod common;
+
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tinker::event::{BrowserEvent, EventSystem};
+
+/// A broker that accepts one connection and reports every PUBLISH as (topic, payload)
+fn spawn_broker(listener: TcpListener) -> mpsc::Receiver<(String, String)> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let Ok((mut stream, _)) = listener.accept() else { return };
+        if common::accept_connect(&mut stream).is_none() {
+            return;
+        }
+
+        while let Some((header, body)) = common::read_packet(&mut stream) {
+            let Some(message) = common::publish(header, &body) else { continue };
+            if tx.send(message).is_err() {
+                return;
+            }
+        }
+    });
+
+    rx
+}
+
+#[test]
+fn test_outbox_flushes_in_order_after_reconnect() {
+    // Reserve a port, then leave it closed so the broker is "down"
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+    let mut events = EventSystem::new(&format!("mqtt://127.0.0.1:{}", port), "outbox-test").unwrap();
+    events.connect().unwrap();
+    assert!(!events.is_online());
+
+    for url in ["https://one.example", "https://two.example", "https://three.example"] {
+        events.publish(BrowserEvent::Navigation { url: url.to_string() }).unwrap();
+    }
+    let stats = events.outbox_stats();
+    assert_eq!(stats.pending, 3);
+    assert_eq!(stats.queued, 3);
+
+    // Bring the broker up; the client reconnects on its own and flushes the outbox
+    let published = spawn_broker(TcpListener::bind(("127.0.0.1", port)).unwrap());
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut navigations = Vec::new();
+    while navigations.len() < 3 {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let (topic, payload) = published.recv_timeout(remaining).expect("queued events were never flushed");
+        if topic == "browser/navigation" {
+            let event: serde_json::Value = serde_json::from_str(&payload).unwrap();
+            navigations.push(event["event"]["Navigation"]["url"].as_str().unwrap().to_string());
+        }
+    }
+
+    assert_eq!(navigations, vec!["https://one.example", "https://two.example", "https://three.example"]);
+
+    // Stats are published once the flush completes
+    let payload = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let (topic, payload) = published.recv_timeout(remaining).expect("outbox stats were never published");
+        if topic == "browser/outbox" {
+            break payload;
+        }
+    };
+    let stats: serde_json::Value = serde_json::from_str(&payload).unwrap();
+    assert_eq!(stats["flushed"], 3);
+    assert_eq!(stats["pending"], 0);
+    assert!(events.is_online());
+}
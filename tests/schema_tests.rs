@@ -0,0 +1,40 @@
+use std::fs;
+use std::path::PathBuf;
+use tinker::event::schema;
+
+/// The schemas shipped in `schemas/` must match what the Rust types generate
+#[test]
+fn test_shipped_schemas_match_types() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("schemas");
+
+    for (name, document) in schema::documents() {
+        let shipped = fs::read_to_string(dir.join(name))
+            .unwrap_or_else(|e| panic!("schemas/{} is missing ({}); run `tinker schema --out-dir schemas`", name, e));
+        assert!(
+            shipped == schema::to_pretty(&document),
+            "schemas/{} is out of date; run `tinker schema --out-dir schemas`",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_schemas_describe_wire_format() {
+    let command = schema::schema_for::<tinker::event::BrowserCommand>();
+    // Variants with fields are objects keyed by name; unit variants are plain strings
+    let variants: Vec<String> = command["oneOf"].as_array().unwrap().iter()
+        .flat_map(|variant| {
+            let names = variant["required"].as_array().or(variant["enum"].as_array());
+            names.unwrap().clone()
+        })
+        .map(|name| name.as_str().unwrap().to_string())
+        .collect();
+    assert!(variants.contains(&"create_tab".to_string()));
+    assert!(variants.contains(&"back".to_string()));
+
+    let envelope = schema::schema_for::<tinker::event::EventEnvelope>();
+    let required = envelope["required"].as_array().unwrap();
+    for field in ["schema_version", "sequence", "timestamp", "type", "event"] {
+        assert!(required.iter().any(|r| r == field), "{} should be required", field);
+    }
+}
@@ -0,0 +1,285 @@
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use tinker::{
+    api,
+    browser::BrowserEngine,
+    event::{
+        acl, BrowserCommand, BrowserEvent, CommandPolicy, CommandRequest, CommandResult,
+        EventTransport, InProcessTransport, PendingCommand, RecordingControl, WebSocketTransport, FETCH_CHUNK_BYTES,
+    },
+};
+
+#[test]
+fn test_in_process_events_and_commands() {
+    let transport = InProcessTransport::new().with_instance_id(Some("embedded"));
+    let mut browser = BrowserEngine::new(true, Some(Arc::new(Mutex::new(transport.clone()))), None);
+    let tab_events = transport.clone().subscribe("tinker/embedded/browser/tabs/#").unwrap();
+
+    // Commands go through the same request/response path as MQTT
+    let responses = transport.request(CommandRequest {
+        request_id: "req-1".to_string(),
+        reply_to: None,
+        timeout_ms: Some(5_000),
+        source: None,
+        token: None,
+        command: BrowserCommand::CreateTab { url: "https://example.com".to_string() },
+    });
+    assert_eq!(browser.process_pending_commands(), 1);
+
+    let response = responses.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(response.success);
+    assert_eq!(response.instance_id.as_deref(), Some("embedded"));
+    let Some(CommandResult::TabCreated { id, .. }) = response.result else {
+        panic!("Unexpected response: {:?}", response);
+    };
+
+    let created = tab_events.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(created.topic, "tinker/embedded/browser/tabs/created");
+    let envelope: serde_json::Value = serde_json::from_str(&created.payload).unwrap();
+    assert_eq!(envelope["type"], "tab_created");
+    assert_eq!(envelope["tab_id"], id);
+}
+
+#[test]
+fn test_remote_live_recording() {
+    let transport = InProcessTransport::new();
+    let mut browser = BrowserEngine::new(true, Some(Arc::new(Mutex::new(transport.clone()))), None);
+    let lines = transport.clone().subscribe("browser/recording/#").unwrap();
+    let run = |browser: &mut BrowserEngine, control: RecordingControl| {
+        let responses = transport.request(CommandRequest {
+            request_id: "req-1".to_string(),
+            reply_to: None,
+            timeout_ms: Some(5_000),
+            source: None,
+            token: None,
+            command: BrowserCommand::Recording(control),
+        });
+        assert_eq!(browser.process_pending_commands(), 1);
+        responses.recv_timeout(Duration::from_secs(5)).unwrap().result.unwrap()
+    };
+
+    let CommandResult::RecordingStatus(status) = run(&mut browser, RecordingControl::Start { path: None, live: true }) else {
+        panic!("Expected the recorder's status");
+    };
+    assert!(status.recording && status.live);
+    browser.create_tab("https://example.com").unwrap();
+    run(&mut browser, RecordingControl::Stop);
+
+    // A follower sees the header, every record and the status changes
+    let mut followed = String::new();
+    let mut topics = Vec::new();
+    while let Ok(message) = lines.recv_timeout(Duration::from_millis(200)) {
+        if message.topic != "browser/recording/status" {
+            followed.push_str(&message.payload);
+            followed.push('\n');
+        }
+        topics.push(message.topic);
+    }
+    assert_eq!(topics, vec![
+        "browser/recording/header",
+        "browser/recording/status",
+        "browser/recording/record",
+        "browser/recording/record",
+        "browser/recording/status",
+    ]);
+
+    // ...and can rebuild exactly what a fetch returns
+    let CommandResult::Recording { content, next } = run(&mut browser, RecordingControl::Fetch { offset: 0 }) else {
+        panic!("Expected the recording");
+    };
+    assert_eq!(next, None);
+    assert_eq!(content, followed);
+    assert!(content.contains("\"type\":\"tab_created\""));
+}
+
+#[test]
+fn test_remote_recording_files() {
+    let transport = InProcessTransport::new();
+    let mut browser = BrowserEngine::new(true, Some(Arc::new(Mutex::new(transport.clone()))), None);
+    let dir = std::env::temp_dir().join(format!("tinker-recordings-{}", std::process::id()));
+    browser.recordings_dir = dir.clone();
+    // Clones share the policy, so it can change while `run` holds the transport
+    let mut policy = transport.clone();
+    let run = |browser: &mut BrowserEngine, control: RecordingControl| {
+        let responses = transport.request(CommandRequest {
+            request_id: "req-1".to_string(),
+            reply_to: None,
+            timeout_ms: Some(5_000),
+            source: None,
+            token: None,
+            command: BrowserCommand::Recording(control),
+        });
+        browser.process_pending_commands();
+        responses.recv_timeout(Duration::from_secs(5)).unwrap()
+    };
+
+    // The default policy keeps remote senders from writing files
+    let save = RecordingControl::Save { path: "run.jsonl".to_string() };
+    assert_eq!(run(&mut browser, save.clone()).error.unwrap().kind, "forbidden");
+
+    policy.set_command_policy(serde_json::from_str(r#"{ "default": { "commands": ["*"], "write_files": true } }"#).unwrap());
+    run(&mut browser, RecordingControl::Start { path: None, live: false });
+    for index in 0..2_000 {
+        browser.create_tab(&format!("https://example.com/{}", index)).unwrap();
+    }
+    run(&mut browser, RecordingControl::Stop);
+    assert!(run(&mut browser, save).success);
+    assert!(dir.join("run.jsonl").exists());
+    let outside = run(&mut browser, RecordingControl::Save { path: "../escaped.jsonl".to_string() });
+    assert_eq!(outside.error.unwrap().kind, "recording");
+
+    // Large recordings are fetched a chunk at a time
+    let mut fetched = String::new();
+    let mut offset = Some(0);
+    let mut chunks = 0;
+    while let Some(from) = offset {
+        let Some(CommandResult::Recording { content, next }) = run(&mut browser, RecordingControl::Fetch { offset: from }).result else {
+            panic!("Expected the recording");
+        };
+        assert!(content.len() <= FETCH_CHUNK_BYTES && content.ends_with('\n'));
+        fetched.push_str(&content);
+        offset = next;
+        chunks += 1;
+    }
+    assert!(chunks > 1);
+    assert_eq!(fetched, std::fs::read_to_string(dir.join("run.jsonl")).unwrap());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_command_policy() {
+    let mut transport = InProcessTransport::new();
+    transport.set_command_sender(spawn_engine_stub());
+    transport.set_command_policy(serde_json::from_str(r#"{
+        "default": { "commands": ["get_tabs"] },
+        "sources": { "ci": { "secret": "s3cret", "commands": ["navigate"], "urls": ["https://example.com/*"] } }
+    }"#).unwrap());
+    let rejections = transport.clone().subscribe("browser/command/rejected").unwrap();
+
+    let expires = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() + 60;
+    let request = |id: &str, source: Option<&str>, url: &str| {
+        let command = BrowserCommand::Navigate { url: url.to_string() };
+        CommandRequest {
+            request_id: id.to_string(),
+            reply_to: None,
+            timeout_ms: Some(5_000),
+            source: source.map(str::to_string),
+            token: source.map(|source| acl::sign_request("s3cret", source, id, &command, expires)),
+            command,
+        }
+    };
+
+    // A signed request for an allowed URL goes through
+    let response = transport.request(request("req-1", Some("ci"), "https://example.com/a"))
+        .recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(response.success);
+
+    // Everything else is refused with a typed reason
+    let response = transport.request(request("req-2", Some("ci"), "https://elsewhere.test/"))
+        .recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.error.unwrap().kind, "forbidden");
+    let rejected: serde_json::Value = serde_json::from_str(&rejections.recv_timeout(Duration::from_secs(1)).unwrap().payload).unwrap();
+    assert_eq!(rejected["event"]["CommandRejected"]["source"], "ci");
+    assert_eq!(rejected["event"]["CommandRejected"]["reason"]["kind"], "url_not_allowed");
+
+    let response = transport.request(request("req-3", None, "https://example.com/a"))
+        .recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(!response.success);
+    let rejected: serde_json::Value = serde_json::from_str(&rejections.recv_timeout(Duration::from_secs(1)).unwrap().payload).unwrap();
+    assert_eq!(rejected["event"]["CommandRejected"]["reason"]["kind"], "command_not_allowed");
+}
+
+#[test]
+fn test_observer_mode_ignores_commands() {
+    let mut transport = InProcessTransport::new();
+    transport.set_command_sender(spawn_engine_stub());
+    transport.set_command_policy(CommandPolicy::observer());
+    let events = transport.clone().subscribe("browser/#").unwrap();
+
+    let responses = transport.request(CommandRequest {
+        request_id: "req-1".to_string(),
+        reply_to: None,
+        timeout_ms: Some(1_000),
+        source: None,
+        token: None,
+        command: BrowserCommand::GetTabs,
+    });
+    assert!(responses.recv_timeout(Duration::from_millis(200)).is_err());
+    // Payloads that are not commands are ignored as well, not reported
+    transport.execute("not a command", |_, _| panic!("observers do not reply"));
+    transport.execute(r#"{"request_id": "req-2", "command": "get_tabs"}"#, |_, _| panic!("observers do not reply"));
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(events.try_recv().is_err());
+
+    // Events still flow out
+    transport.publish(BrowserEvent::Navigation { url: "https://example.com".to_string() }).unwrap();
+    assert_eq!(events.recv_timeout(Duration::from_secs(1)).unwrap().topic, "browser/navigation");
+}
+
+/// Answer every command with `Completed`, standing in for the browser engine
+fn spawn_engine_stub() -> mpsc::Sender<PendingCommand> {
+    let (tx, rx) = mpsc::channel::<PendingCommand>();
+    std::thread::spawn(move || {
+        for pending in rx {
+            if let Some(responder) = pending.responder {
+                let _ = responder.send(Ok(CommandResult::Completed));
+            }
+        }
+    });
+    tx
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_websocket_transport() {
+    let mut transport = WebSocketTransport::new();
+    transport.set_command_sender(spawn_engine_stub());
+    transport.connect().unwrap();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let app = api::router().merge(transport.router());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+    let url = format!("ws://{}/events/ws?topics=browser/navigation,browser/command/reply", addr);
+    let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    let (mut bystander, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+    // Published events reach the socket, filtered by topic
+    transport.publish(BrowserEvent::Error { message: "filtered out".to_string() }).unwrap();
+    transport.publish(BrowserEvent::Navigation { url: "https://example.com".to_string() }).unwrap();
+    let frame = next_frame(&mut socket).await;
+    assert_eq!(frame["topic"], "browser/navigation");
+    assert_eq!(frame["payload"]["event"]["Navigation"]["url"], "https://example.com");
+    assert_eq!(next_frame(&mut bystander).await["topic"], "browser/navigation");
+
+    // Commands sent on the socket get their reply on the same socket
+    let request = serde_json::json!({
+        "request_id": "ws-1",
+        "command": { "navigate": { "url": "https://example.com/next" } }
+    });
+    socket.send(Message::Text(request.to_string())).await.unwrap();
+    let frame = next_frame(&mut socket).await;
+    assert_eq!(frame["topic"], "browser/command/reply");
+    assert_eq!(frame["payload"]["request_id"], "ws-1");
+    assert_eq!(frame["payload"]["success"], true);
+
+    // ...exactly once, and no other socket sees it
+    let quiet = Duration::from_millis(300);
+    assert!(tokio::time::timeout(quiet, socket.next()).await.is_err());
+    assert!(tokio::time::timeout(quiet, bystander.next()).await.is_err());
+}
+
+async fn next_frame<S>(socket: &mut S) -> serde_json::Value
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+        .await
+        .expect("no frame received")
+        .unwrap()
+        .unwrap();
+    serde_json::from_str(message.to_text().unwrap()).unwrap()
+}